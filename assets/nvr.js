@@ -0,0 +1,685 @@
+// Update date and time
+function updateDateTime() {
+    const now = new Date();
+    const dateString = now.toLocaleDateString();
+    const timeString = now.toLocaleTimeString();
+    document.getElementById('datetime').textContent = `${dateString} ${timeString}`;
+}
+
+setInterval(updateDateTime, 1000);
+updateDateTime();
+
+// Per-stream client state that survives reconnects
+const streamState = {};
+
+// WebSocket attempts that never open before falling back to Server-Sent Events,
+// for proxies that block WebSockets
+const WS_FAILURES_BEFORE_SSE = 2;
+
+// How long the dimmed last frame stays up after a disconnect before the canvas is cleared
+const RECONNECT_GRACE_MS = window.NVR_RECONNECT_GRACE_MS ?? 10000;
+
+// Longest crossfade between frames with interpolation on, slower cameras just fade faster
+const MAX_FADE_MS = 1000;
+
+// streamName is the internal name used for IDs and the WebSocket path,
+// displayName is only used for text shown to the viewer. Cameras with a
+// substream start on it, the tile's quality button switches to main and back.
+// With interpolate, each frame is crossfaded into instead of drawn at once.
+function setupStream(streamName, displayName = streamName, hasSubstream = false, interpolate = false) {
+    const canvas = document.getElementById('canvas-' + streamName.toLowerCase());
+    const ctx = canvas.getContext('2d');
+    const stats = document.getElementById('stats-' + streamName.toLowerCase());
+    const fpsElement = document.getElementById('fps-' + streamName.toLowerCase());
+    const statusDot = canvas.parentElement.querySelector('.status-dot');
+    const state = streamState[streamName] || (streamState[streamName] = {
+        ws: null,
+        overlay: false,
+        paused: false,
+        motionBox: null,
+        activeEvents: {},
+        wsFailures: 0,
+        useSse: false,
+        hasFrame: false,
+        graceTimer: null,
+        quality: hasSubstream ? 'sub' : 'main',
+        switching: false,
+        stopped: false,
+        source: null,
+        reconnectTimer: null,
+        initialized: false,
+        lastFrameAt: 0,
+        frameInterval: 0,
+        fade: null,
+        clientId: null,
+        expectedFps: null,
+    });
+    state.tile = canvas.parentElement;
+    state.stopped = false;
+
+    // Only bind DOM handlers once, setupStream runs again on every reconnect
+    if (!state.initialized) {
+        state.initialized = true;
+        bindControls(streamName, canvas, state);
+    }
+
+    // On reconnects the dimmed last frame stays up instead
+    if (!state.hasFrame) {
+        drawMessage(ctx, 'white', 'Connecting to ' + displayName + '...');
+    }
+
+    // H.264 where the browser can play it, decided in the page (see create_html_file)
+    if (window.NVR_MSE && !state.mseFailed) {
+        setupMse(streamName, displayName, hasSubstream, interpolate, state);
+        return;
+    }
+
+    let frameCount = 0;
+    let lastTime = Date.now();
+    let fps = 0;
+
+    // Draw one JPEG frame (ArrayBuffer or Uint8Array)
+    function showFrame(data) {
+        // Calculate FPS
+        frameCount++;
+        const now = Date.now();
+        if (now - lastTime >= 1000) {
+            fps = frameCount;
+            frameCount = 0;
+            lastTime = now;
+            fpsElement.textContent = fps + ' FPS';
+        }
+
+        // Smoothed time between frames, how long a crossfade takes
+        const arrived = performance.now();
+        if (state.lastFrameAt) {
+            const interval = arrived - state.lastFrameAt;
+            state.frameInterval = state.frameInterval ? state.frameInterval * 0.8 + interval * 0.2 : interval;
+        }
+        state.lastFrameAt = arrived;
+
+        // Update stats
+        const quality = hasSubstream ? ` · ${state.quality.toUpperCase()}` : '';
+        stats.textContent = `${(data.byteLength / 1024).toFixed(1)} KB${quality}`;
+
+        const blob = new Blob([data], {type: 'image/jpeg'});
+        const url = URL.createObjectURL(blob);
+        const img = new Image();
+
+        img.onload = function() {
+            endReconnect(state);
+            const drawOverlay = function() {
+                if (state.overlay && state.motionBox) {
+                    drawMotionBox(ctx, state.motionBox, img);
+                }
+            };
+            if (interpolate && state.hasFrame && state.frameInterval) {
+                crossfade(ctx, state, img, Math.min(state.frameInterval, MAX_FADE_MS), drawOverlay);
+            } else {
+                ctx.drawImage(img, 0, 0, canvas.width, canvas.height);
+                drawOverlay();
+            }
+            state.hasFrame = true;
+            URL.revokeObjectURL(url);
+        };
+
+        img.onerror = function(err) {
+            console.error(`Error loading image for ${streamName}:`, err);
+            statusDot.style.backgroundColor = 'red';
+        };
+
+        img.src = url;
+    }
+
+    const basePath = window.NVR_BASE_PATH || '';
+    // Streams may be served on a port of their own, on the same host as the page
+    const streamHost = window.NVR_STREAM_PORT ? window.location.hostname + ':' + window.NVR_STREAM_PORT : window.location.host;
+
+    // Server-Sent Events fallback: base64 frames over plain HTTP. EventSource
+    // reconnects by itself, so this is only set up once.
+    if (state.useSse) {
+        const source = new EventSource(window.location.protocol + '//' + streamHost + basePath + '/sse/' + streamName.toLowerCase());
+        state.source = source;
+        source.onopen = function() {
+            console.log('Connected to ' + streamName + ' over SSE');
+            stats.textContent = 'Connected (SSE)';
+            statusDot.style.backgroundColor = 'var(--ok)';
+        };
+        source.addEventListener('frame', function(event) {
+            showFrame(Uint8Array.from(atob(event.data), c => c.charCodeAt(0)));
+        });
+        source.onerror = function() {
+            statusDot.style.backgroundColor = 'var(--warn)';
+        };
+        return;
+    }
+
+    // Connect to WebSocket, under the base path the page is served from
+    const scheme = window.location.protocol === 'https:' ? 'wss://' : 'ws://';
+    const ws = new WebSocket(scheme + streamHost + basePath + '/ws/' + streamName.toLowerCase() + '?quality=' + state.quality);
+    let opened = false;
+
+    ws.binaryType = 'arraybuffer';
+    state.ws = ws;
+
+    ws.onopen = function() {
+        opened = true;
+        state.wsFailures = 0;
+        console.log('Connected to ' + streamName);
+        stats.textContent = 'Connected';
+        statusDot.style.backgroundColor = 'var(--ok)';
+
+        // Restore per-client options after a reconnect
+        if (state.overlay) {
+            sendControl(streamName, { cmd: 'overlay', enabled: true });
+        }
+        if (state.paused) {
+            sendControl(streamName, { cmd: 'pause', enabled: true });
+            stats.textContent = 'Paused';
+        }
+    };
+
+    ws.onmessage = function(event) {
+        // Text messages carry JSON metadata, binary ones are JPEG frames
+        if (typeof event.data === 'string') {
+            handleServerMessage(state, event.data);
+            return;
+        }
+        showFrame(event.data);
+    };
+
+    ws.onclose = function() {
+        console.log('Disconnected from ' + streamName);
+        state.ws = null;
+
+        // Closed because its page was left, stay disconnected
+        if (state.stopped) {
+            return;
+        }
+
+        // Closed on purpose to switch quality, the current frame stays up until the new one
+        if (state.switching) {
+            state.switching = false;
+            setupStream(streamName, displayName, hasSubstream, interpolate);
+            return;
+        }
+
+        // A socket that never opens is likely blocked on the way, try SSE instead
+        if (!opened && ++state.wsFailures >= WS_FAILURES_BEFORE_SSE) {
+            console.log('WebSocket unavailable for ' + streamName + ', falling back to SSE');
+            state.useSse = true;
+        }
+        statusDot.style.backgroundColor = 'var(--warn)';
+
+        // Keep the last frame up, dimmed, for brief hiccups. Only clear it once the
+        // grace period runs out without a new frame.
+        if (state.hasFrame) {
+            state.tile.classList.add('reconnecting');
+            if (!state.graceTimer) {
+                state.graceTimer = setTimeout(function() {
+                    endReconnect(state);
+                    state.hasFrame = false;
+                    drawMessage(ctx, 'red', 'Connection lost. Reconnecting...');
+                }, RECONNECT_GRACE_MS);
+            }
+        } else {
+            drawMessage(ctx, 'red', 'Connection lost. Reconnecting...');
+        }
+
+        // Try to reconnect after a delay
+        state.reconnectTimer = setTimeout(() => setupStream(streamName, displayName, hasSubstream, interpolate), 5000);
+    };
+
+    ws.onerror = function(err) {
+        console.error('WebSocket Error for ' + streamName + ':', err);
+        statusDot.style.backgroundColor = 'red';
+    };
+}
+
+// Media Source Extensions player: fragmented MP4 from /ws/mse played in a <video> that
+// takes the canvas's place. If the browser can't play this camera's H.264 after all (its
+// profile or level, or a decode error), the camera drops back to the MJPEG canvas for good.
+function setupMse(streamName, displayName, hasSubstream, interpolate, state) {
+    const canvas = document.getElementById('canvas-' + streamName.toLowerCase());
+    const stats = document.getElementById('stats-' + streamName.toLowerCase());
+    const fpsElement = document.getElementById('fps-' + streamName.toLowerCase());
+    const statusDot = canvas.parentElement.querySelector('.status-dot');
+
+    let video = state.video;
+    if (!video) {
+        video = document.createElement('video');
+        video.muted = true;
+        video.autoplay = true;
+        video.playsInline = true;
+        video.className = canvas.className;
+        video.hidden = true;
+        video.addEventListener('click', () => toggleSpotlight(state.tile));
+        canvas.after(video);
+        state.video = video;
+    }
+
+    const basePath = window.NVR_BASE_PATH || '';
+    const streamHost = window.NVR_STREAM_PORT ? window.location.hostname + ':' + window.NVR_STREAM_PORT : window.location.host;
+    const scheme = window.location.protocol === 'https:' ? 'wss://' : 'ws://';
+    const ws = new WebSocket(scheme + streamHost + basePath + '/ws/mse/' + streamName.toLowerCase());
+    ws.binaryType = 'arraybuffer';
+    state.ws = ws;
+
+    let buffer = null;
+    const queue = [];
+
+    function fallBack(reason) {
+        console.log(`${streamName}: ${reason}, falling back to MJPEG`);
+        state.mseFailed = true;
+        showMseNotice();
+        ws.close();
+    }
+
+    // Fragments are appended one at a time, the SourceBuffer takes no more while updating
+    function pump() {
+        if (!buffer || buffer.updating || queue.length === 0) {
+            return;
+        }
+        try {
+            buffer.appendBuffer(queue.shift());
+        } catch (err) {
+            fallBack('appending failed (' + err.name + ')');
+        }
+    }
+
+    function onUpdateEnd() {
+        const ranges = video.buffered;
+        if (ranges.length > 0) {
+            const start = ranges.start(0);
+            const end = ranges.end(ranges.length - 1);
+            // Stay near live, a tab in the background falls behind
+            if (end - video.currentTime > 3) {
+                video.currentTime = end - 0.5;
+            }
+            // Drop what was played long ago so the buffer doesn't fill up
+            if (video.currentTime - start > 30) {
+                buffer.remove(start, video.currentTime - 10);
+                return;
+            }
+        }
+        pump();
+    }
+
+    // A fresh MediaSource for each init segment, a new one means the encoder restarted
+    function startSource(mime) {
+        if (video.src) {
+            URL.revokeObjectURL(video.src);
+        }
+        buffer = null;
+        queue.length = 0;
+        const mediaSource = new MediaSource();
+        video.src = URL.createObjectURL(mediaSource);
+        mediaSource.addEventListener('sourceopen', function() {
+            try {
+                buffer = mediaSource.addSourceBuffer(mime);
+            } catch (err) {
+                fallBack(mime + ' rejected (' + err.name + ')');
+                return;
+            }
+            buffer.mode = 'sequence';
+            buffer.addEventListener('updateend', onUpdateEnd);
+            pump();
+        }, { once: true });
+    }
+
+    ws.onopen = function() {
+        state.wsFailures = 0;
+        console.log('Connected to ' + streamName + ' (H.264)');
+        stats.textContent = 'Connected (H.264)';
+        statusDot.style.backgroundColor = 'var(--ok)';
+    };
+
+    ws.onmessage = function(event) {
+        // Text messages are JSON, the only one here announces the init segment's codec
+        if (typeof event.data === 'string') {
+            const message = JSON.parse(event.data);
+            if (message.type !== 'init') {
+                return;
+            }
+            // The page only checked a common profile, this is the camera's actual one
+            if (!MediaSource.isTypeSupported(message.mime)) {
+                fallBack(message.mime + ' not supported');
+                return;
+            }
+            startSource(message.mime);
+            return;
+        }
+        stats.textContent = `${(event.data.byteLength / 1024).toFixed(1)} KB · H.264`;
+        queue.push(event.data);
+        pump();
+    };
+
+    video.onplaying = function() {
+        endReconnect(state);
+        state.hasFrame = true;
+        video.hidden = false;
+        canvas.hidden = true;
+        fpsElement.textContent = 'H.264';
+    };
+
+    video.onerror = function() {
+        fallBack('playback error' + (video.error ? ' (' + video.error.message + ')' : ''));
+    };
+
+    ws.onclose = function() {
+        console.log('Disconnected from ' + streamName);
+        state.ws = null;
+
+        if (state.stopped) {
+            return;
+        }
+
+        // Back to the canvas, which takes over right away
+        if (state.mseFailed) {
+            video.onerror = null;
+            video.removeAttribute('src');
+            video.load();
+            video.hidden = true;
+            canvas.hidden = false;
+            state.hasFrame = false;
+            setupStream(streamName, displayName, hasSubstream, interpolate);
+            return;
+        }
+
+        if (state.switching) {
+            state.switching = false;
+            setupStream(streamName, displayName, hasSubstream, interpolate);
+            return;
+        }
+
+        statusDot.style.backgroundColor = 'var(--warn)';
+        if (state.hasFrame) {
+            state.tile.classList.add('reconnecting');
+        }
+        state.reconnectTimer = setTimeout(() => setupStream(streamName, displayName, hasSubstream, interpolate), 5000);
+    };
+
+    ws.onerror = function(err) {
+        console.error('WebSocket Error for ' + streamName + ':', err);
+        statusDot.style.backgroundColor = 'red';
+    };
+}
+
+// Degraded mode: MSE was asked for, but this browser (or one of the cameras) gets MJPEG
+function showMseNotice() {
+    const notice = document.getElementById('mse-notice');
+    notice.textContent = 'H.264 not supported, showing MJPEG';
+    notice.title = 'This browser can\'t play the H.264 stream, cameras fall back to MJPEG (more bandwidth)';
+    notice.hidden = false;
+}
+
+if (window.NVR_MSE_WANTED && !window.NVR_MSE) {
+    showMseNotice();
+}
+
+// Disconnect a stream whose tile is no longer visible. setupStream brings it back.
+function stopStream(streamName) {
+    const state = streamState[streamName];
+    if (!state) {
+        return;
+    }
+    state.stopped = true;
+    clearTimeout(state.reconnectTimer);
+    if (state.ws) {
+        state.ws.close();
+    }
+    if (state.source) {
+        state.source.close();
+        state.source = null;
+    }
+}
+
+// Pagination: streams is a list of [name, displayName, hasSubstream, interpolate]. Only the current
+// page's tiles are shown and connected, leaving a page closes its connections.
+const CAMERAS_PER_PAGE = window.NVR_CAMERAS_PER_PAGE || 0;
+let pages = [];
+let currentPage = 0;
+
+function showPages(streams) {
+    const perPage = CAMERAS_PER_PAGE > 0 ? CAMERAS_PER_PAGE : streams.length;
+    pages = [];
+    for (let i = 0; i < streams.length; i += perPage) {
+        pages.push(streams.slice(i, i + perPage));
+    }
+    document.getElementById('pager').hidden = pages.length <= 1;
+    document.getElementById('prev-page').addEventListener('click', () => showPage(currentPage - 1));
+    document.getElementById('next-page').addEventListener('click', () => showPage(currentPage + 1));
+
+    // Hide everything, showPage reveals the first page
+    streams.forEach(([name]) => streamTile(name).hidden = true);
+    if (pages.length > 0) {
+        currentPage = 0;
+        startPage(pages[0]);
+    }
+}
+
+function showPage(index) {
+    const next = (index + pages.length) % pages.length;
+    if (next === currentPage) {
+        return;
+    }
+    pages[currentPage].forEach(([name]) => {
+        stopStream(name);
+        streamTile(name).hidden = true;
+    });
+    currentPage = next;
+    startPage(pages[currentPage]);
+}
+
+function startPage(page) {
+    page.forEach(([name, displayName, hasSubstream, interpolate]) => {
+        streamTile(name).hidden = false;
+        setupStream(name, displayName, hasSubstream, interpolate);
+    });
+    document.getElementById('page-label').textContent = `Page ${currentPage + 1} / ${pages.length}`;
+}
+
+function streamTile(streamName) {
+    return document.getElementById('canvas-' + streamName.toLowerCase()).parentElement;
+}
+
+// Black canvas with a centered message
+function drawMessage(ctx, color, text) {
+    ctx.fillStyle = 'black';
+    ctx.fillRect(0, 0, ctx.canvas.width, ctx.canvas.height);
+    ctx.fillStyle = color;
+    ctx.font = '16px Arial';
+    ctx.textAlign = 'center';
+    ctx.fillText(text, ctx.canvas.width/2, ctx.canvas.height/2);
+}
+
+function endReconnect(state) {
+    clearTimeout(state.graceTimer);
+    state.graceTimer = null;
+    state.tile.classList.remove('reconnecting');
+}
+
+function bindControls(streamName, canvas, state) {
+    // Click to spotlight this tile, click again (or Escape) for the grid
+    canvas.addEventListener('click', function() {
+        toggleSpotlight(state.tile);
+    });
+
+    // Fullscreen toggle
+    canvas.addEventListener('dblclick', function() {
+        if (!document.fullscreenElement) {
+            canvas.parentElement.requestFullscreen().catch(err => {
+                console.error(`Could not enter fullscreen: ${err.message}`);
+            });
+        } else {
+            document.exitFullscreen();
+        }
+    });
+
+    // Motion overlay toggle, only affects this viewer
+    const overlayBtn = canvas.parentElement.querySelector('.overlay-btn');
+    overlayBtn.addEventListener('click', function() {
+        state.overlay = !state.overlay;
+        state.motionBox = null;
+        overlayBtn.classList.toggle('active', state.overlay);
+        sendControl(streamName, { cmd: 'overlay', enabled: state.overlay });
+    });
+
+    // Pause/resume, the server stops sending this viewer frames but keeps it subscribed
+    const pauseBtn = canvas.parentElement.querySelector('.pause-btn');
+    const stats = canvas.parentElement.querySelector('.stats');
+    pauseBtn.addEventListener('click', function() {
+        state.paused = !state.paused;
+        pauseBtn.classList.toggle('active', state.paused);
+        pauseBtn.title = state.paused ? 'Resume' : 'Pause';
+        state.tile.classList.toggle('paused', state.paused);
+        if (state.paused) {
+            stats.textContent = 'Paused';
+        }
+        sendControl(streamName, { cmd: 'pause', enabled: state.paused });
+    });
+
+    // Main/sub switch, reconnects with the other quality. SSE only carries main.
+    const qualityBtn = canvas.parentElement.querySelector('.quality-btn');
+    if (qualityBtn) {
+        qualityBtn.addEventListener('click', function() {
+            if (!state.ws) {
+                return;
+            }
+            state.quality = state.quality === 'sub' ? 'main' : 'sub';
+            qualityBtn.classList.toggle('active', state.quality === 'main');
+            state.switching = true;
+            state.ws.close();
+        });
+    }
+}
+
+// Tiles are looked up from the DOM each time, so this works for whatever streams the page has
+function toggleSpotlight(tile) {
+    const container = tile.parentElement;
+    const wasSpotlighted = tile.classList.contains('spotlighted');
+    container.querySelectorAll('.stream.spotlighted').forEach(other => other.classList.remove('spotlighted'));
+
+    if (wasSpotlighted) {
+        container.classList.remove('spotlight');
+        return;
+    }
+    const others = container.querySelectorAll('.stream:not([hidden])').length - 1;
+    container.style.setProperty('--filmstrip-count', Math.max(others, 1));
+    container.classList.add('spotlight');
+    tile.classList.add('spotlighted');
+}
+
+document.addEventListener('keydown', function(event) {
+    const spotlighted = document.querySelector('.stream.spotlighted');
+    if (event.key === 'Escape' && spotlighted) {
+        toggleSpotlight(spotlighted);
+    }
+});
+
+function sendControl(streamName, message) {
+    const ws = streamState[streamName].ws;
+    if (ws && ws.readyState === WebSocket.OPEN) {
+        ws.send(JSON.stringify(message));
+    }
+}
+
+function handleServerMessage(state, text) {
+    let message;
+    try {
+        message = JSON.parse(text);
+    } catch (err) {
+        console.error('Bad message from server:', text);
+        return;
+    }
+
+    if (message.type === 'hello') {
+        // Sent once on connect, before any frame: match the canvas to the preview size.
+        // Resizing clears the canvas, so only when it actually differs.
+        state.clientId = message.client_id;
+        state.expectedFps = message.fps;
+        const canvas = state.tile.querySelector('canvas');
+        if (canvas.width !== message.width || canvas.height !== message.height) {
+            canvas.width = message.width;
+            canvas.height = message.height;
+            state.hasFrame = false;
+        }
+        if (message.codec !== 'jpeg') {
+            console.error(`Unsupported codec ${message.codec} for ${message.stream}`);
+        }
+    } else if (message.type === 'motion') {
+        state.motionBox = message.box;
+    } else if (message.type === 'pipeline') {
+        // Only sent with pipeline_stats, which also puts nvrPipelineStats on the page
+        if (window.nvrPipelineStats) {
+            window.nvrPipelineStats(state.tile, message);
+        }
+    } else if (message.type === 'event') {
+        // Events reported by the camera, highlight the tile while any is active
+        if (message.active) {
+            state.activeEvents[message.topic] = true;
+        } else {
+            delete state.activeEvents[message.topic];
+        }
+        const active = Object.keys(state.activeEvents);
+        state.tile.classList.toggle('event-active', active.length > 0);
+        state.tile.title = active.join(', ');
+    }
+}
+
+// Blend from what the canvas shows now into `img` over `duration` ms. Only makes
+// low-fps cameras look smoother, there's no more picture information than before.
+function crossfade(ctx, state, img, duration, drawOverlay) {
+    if (state.fade) {
+        cancelAnimationFrame(state.fade);
+    }
+    const from = document.createElement('canvas');
+    from.width = ctx.canvas.width;
+    from.height = ctx.canvas.height;
+    from.getContext('2d').drawImage(ctx.canvas, 0, 0);
+
+    const start = performance.now();
+    function step(now) {
+        const t = Math.min((now - start) / duration, 1);
+        ctx.globalAlpha = 1;
+        ctx.drawImage(from, 0, 0);
+        ctx.globalAlpha = t;
+        ctx.drawImage(img, 0, 0, ctx.canvas.width, ctx.canvas.height);
+        ctx.globalAlpha = 1;
+        drawOverlay();
+        state.fade = t < 1 ? requestAnimationFrame(step) : null;
+    }
+    state.fade = requestAnimationFrame(step);
+}
+
+// Box coordinates are in preview pixels, scale them to the canvas
+function drawMotionBox(ctx, box, img) {
+    const scaleX = ctx.canvas.width / img.naturalWidth;
+    const scaleY = ctx.canvas.height / img.naturalHeight;
+    ctx.strokeStyle = '#FF5252';
+    ctx.lineWidth = 2;
+    ctx.strokeRect(box.x * scaleX, box.y * scaleY, box.width * scaleX, box.height * scaleY);
+}
+
+// Toolbar buttons
+// Light/dark theme, remembered per browser. The page applies the saved one before
+// the stylesheet loads, this only flips it.
+document.getElementById('theme-btn').addEventListener('click', function() {
+    const theme = document.documentElement.dataset.theme === 'light' ? 'dark' : 'light';
+    document.documentElement.dataset.theme = theme;
+    try {
+        localStorage.setItem('nvr-theme', theme);
+    } catch (err) {
+        // Storage disabled, the choice lasts for this page only
+    }
+});
+
+document.getElementById('fullscreen-btn').addEventListener('click', function() {
+    if (!document.fullscreenElement) {
+        document.documentElement.requestFullscreen().catch(err => {
+            console.error(`Could not enter fullscreen: ${err.message}`);
+        });
+    } else {
+        document.exitFullscreen();
+    }
+});
@@ -0,0 +1,87 @@
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gst::prelude::*;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use crate::error::Result;
+use crate::{restart_pipeline, PipelineResources, PipelineSpec};
+
+// Opus at 48 kHz, what browsers' WebCodecs AudioDecoder takes without resampling
+pub const SAMPLE_RATE: u32 = 48000;
+
+// One Opus packet (20 ms by default) as broadcast to listeners
+pub type AudioPacket = Arc<[u8]>;
+
+// Audio-only pipeline for `mode: audio` cameras: only the camera's audio RTP stream is
+// linked, no video is depayloaded or decoded. Restarts in place like the video pipeline.
+pub fn setup_audio_pipeline(spec: &PipelineSpec, audio_tx: broadcast::Sender<AudioPacket>) -> Result<PipelineResources> {
+    let camera = &spec.camera;
+    let stream_name = camera.name.clone();
+    println!("{}: Setting up audio-only pipeline", stream_name);
+
+    let protocols = match camera.socks_proxy.is_some() || camera.local_address.is_some() {
+        true => " protocols=tcp",
+        false => "",
+    };
+    let multicast_iface = match &camera.multicast_iface {
+        Some(iface) => format!(" multicast-iface={}", iface),
+        None => String::new(),
+    };
+    let pipeline_str = format!(
+        "rtspsrc location={} user-id={} user-pw={}{}{} ! application/x-rtp,media=audio ! decodebin ! audioconvert ! audioresample ! audio/x-raw,rate={} ! opusenc ! appsink name=audiosink emit-signals=true",
+        camera.url, spec.user, spec.pass, protocols, multicast_iface, SAMPLE_RATE
+    );
+    let pipeline = gst::parse::launch(&pipeline_str)?.downcast::<gst::Pipeline>().unwrap();
+
+    let appsink = pipeline
+        .by_name("audiosink")
+        .expect("Couldn't find audio appsink")
+        .downcast::<gst_app::AppSink>()
+        .unwrap();
+    appsink.set_property("sync", camera.sync.unwrap_or(false));
+    let stats = spec.stats.clone();
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |app_sink| {
+                let Ok(sample) = app_sink.pull_sample() else { return Ok(gst::FlowSuccess::Ok) };
+                let Some(map) = sample.buffer().and_then(|buffer| buffer.map_readable().ok()) else {
+                    return Ok(gst::FlowSuccess::Ok);
+                };
+                stats.mark_frame(map.len());
+                // Nobody listening is fine, packets are only sent live
+                let _ = audio_tx.send(Arc::from(map.as_slice()));
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+
+    pipeline.set_state(gst::State::Playing)?;
+    spec.stats.mark_started();
+
+    let bus = pipeline.bus().expect("Pipeline without bus");
+    let pipeline_weak = pipeline.downgrade();
+    let stats = spec.stats.clone();
+    let bus_watch = bus.add_watch(move |_, msg| {
+        use gst::MessageView;
+
+        match msg.view() {
+            MessageView::Error(err) => println!("{}: Error: {} ({:?})", stream_name, err.error(), err.debug()),
+            MessageView::Eos(..) => println!("{}: End of stream", stream_name),
+            _ => return gst::glib::ControlFlow::Continue,
+        }
+
+        if let Some(pipeline) = pipeline_weak.upgrade() {
+            restart_pipeline(pipeline, stream_name.clone(), stats.clone());
+        }
+
+        gst::glib::ControlFlow::Continue
+    })?;
+
+    Ok(PipelineResources {
+        pipeline,
+        queue: None,
+        _bus_watch: bus_watch,
+        restream: None,
+    })
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Durable record of who watched, fetched or exported what, as JSON lines. Kept apart from
+// the console output, which is mostly debugging noise. The file is rotated by size:
+// audit.log -> audit.log.1 -> ... -> audit.log.<keep>, the oldest is deleted.
+pub struct AuditLog {
+    // Clients show up without an IP when log_client_ips is off
+    log_ips: bool,
+    // None when auditing is disabled
+    file: Option<Mutex<AuditFile>>,
+}
+
+struct AuditFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    len: u64,
+}
+
+#[derive(Serialize)]
+struct AuditEvent<'a> {
+    time: String,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    stream: Option<&'a str>,
+    // Always null until viewers authenticate
+    user: Option<&'a str>,
+    client: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+}
+
+impl AuditLog {
+    pub fn open(path: Option<&Path>, max_bytes: u64, keep: usize, log_ips: bool) -> Result<AuditLog> {
+        let file = match path {
+            Some(path) => {
+                let file = File::options().create(true).append(true).open(path)?;
+                let len = file.metadata()?.len();
+                Some(Mutex::new(AuditFile { path: path.to_path_buf(), max_bytes, keep, file, len }))
+            }
+            None => None,
+        };
+        Ok(AuditLog { log_ips, file })
+    }
+
+    pub fn record(&self, kind: &str, stream: Option<&str>, client: Option<IpAddr>, detail: Option<&str>) {
+        let Some(file) = &self.file else { return };
+        let event = AuditEvent {
+            time: Utc::now().to_rfc3339(),
+            kind,
+            stream,
+            user: None,
+            client: client.filter(|_| self.log_ips),
+            detail,
+        };
+        let mut line = match serde_json::to_string(&event) {
+            Ok(line) => line,
+            Err(err) => {
+                println!("Failed to serialize audit event: {:?}", err);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let mut file = file.lock().unwrap();
+        if file.len > 0 && file.len + line.len() as u64 > file.max_bytes {
+            if let Err(err) = file.rotate() {
+                println!("Failed to rotate audit log: {:?}", err);
+            }
+        }
+        match file.file.write_all(line.as_bytes()) {
+            Ok(()) => file.len += line.len() as u64,
+            Err(err) => println!("Failed to write audit log: {:?}", err),
+        }
+    }
+}
+
+impl AuditFile {
+    fn rotate(&mut self) -> Result<()> {
+        let numbered = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(numbered(self.keep));
+            for n in (1..self.keep).rev() {
+                let _ = std::fs::rename(numbered(n), numbered(n + 1));
+            }
+            std::fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = File::options().create(true).append(true).open(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
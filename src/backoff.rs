@@ -0,0 +1,70 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::ReconnectPolicy;
+
+// A stream's reconnect state, for GET /api/streams
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct BackoffState {
+    // Restarts in a row that haven't brought a frame yet
+    pub attempts: u32,
+    // Set while waiting to restart
+    pub next_retry_at: Option<DateTime<Utc>>,
+    pub next_delay_secs: Option<f64>,
+    // max_attempts ran out, the stream stays stopped until restarted through the API
+    pub gave_up: bool,
+}
+
+// Restart delays for one stream, following its camera's reconnect policy
+#[derive(Default)]
+pub struct Backoff {
+    policy: ReconnectPolicy,
+    state: Mutex<BackoffState>,
+}
+
+impl Backoff {
+    pub fn new(policy: ReconnectPolicy) -> Self {
+        Backoff { policy, state: Mutex::new(BackoffState::default()) }
+    }
+
+    // How long to wait before the next restart, None if the policy's attempts are used up
+    pub fn next_delay(&self) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        if self.policy.max_attempts > 0 && state.attempts >= self.policy.max_attempts {
+            state.gave_up = true;
+            state.next_retry_at = None;
+            state.next_delay_secs = None;
+            return None;
+        }
+        let secs = (self.policy.initial_delay_secs * self.policy.multiplier.powi(state.attempts as i32)).min(self.policy.max_delay_secs);
+        state.attempts += 1;
+        state.next_retry_at = Some(Utc::now() + TimeDelta::milliseconds((secs * 1000.0) as i64));
+        state.next_delay_secs = Some(secs);
+        Some(Duration::from_secs_f64(secs))
+    }
+
+    // The wait is over, the restart is happening
+    pub fn retrying(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.next_retry_at = None;
+        state.next_delay_secs = None;
+    }
+
+    // A frame arrived or the pipeline was rebuilt, the next failure starts from the initial delay
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.attempts != 0 || state.gave_up {
+            *state = BackoffState::default();
+        }
+    }
+
+    pub fn gave_up(&self) -> bool {
+        self.state.lock().unwrap().gave_up
+    }
+
+    pub fn state(&self) -> BackoffState {
+        *self.state.lock().unwrap()
+    }
+}
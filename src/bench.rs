@@ -0,0 +1,120 @@
+use anyhow::{bail, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::cpuload::{self, CpuSampler};
+use crate::supervisor::PipelineSupervisor;
+use crate::{Frame, PipelineSpec, StreamStats};
+
+// A step keeps up when viewers get at least this share of the frames the sources make
+const KEEP_UP_RATIO: f64 = 0.95;
+
+// Lets a just started pipeline negotiate and reach its frame rate before it's measured
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+
+// Stand-in for one viewer per stream, so delivery is part of what's measured
+#[derive(Default)]
+struct ViewerCounts {
+    frames: AtomicU64,
+    lagged: AtomicU64,
+}
+
+// --bench: start the synthetic cameras one at a time through the real supervisor and
+// pipeline, measure each step, and stop at the first one where frames are lost. The
+// sources are raw test patterns, so a real camera's H.264 decoding comes on top.
+pub async fn run(specs: Vec<PipelineSpec>, supervisor: Arc<PipelineSupervisor>, fps: u32, step: Duration, label: &str) -> Result<()> {
+    if specs.is_empty() {
+        bail!("--bench needs at least one camera");
+    }
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    println!("Benchmark: up to {} synthetic camera(s) at {}, {:?} per step, {} CPU core(s)", specs.len(), label, step, cores);
+
+    let total = specs.len();
+    let mut streams: Vec<(Arc<StreamStats>, Arc<ViewerCounts>)> = Vec::new();
+    let mut sampler = CpuSampler::default();
+    let mut sustained = None;
+    let mut saturated = false;
+    for spec in specs {
+        let name = spec.camera.name.clone();
+        let viewer = Arc::new(ViewerCounts::default());
+        watch(spec.tx.subscribe(), viewer.clone());
+        streams.push((spec.stats.clone(), viewer));
+
+        let supervisor_start = supervisor.clone();
+        match tokio::task::spawn_blocking(move || supervisor_start.start(spec)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                println!("{}: Failed to start, stopping the benchmark: {}", name, err);
+                break;
+            }
+            Err(err) => {
+                println!("{}: Start task panicked or was cancelled, stopping the benchmark: {}", name, err);
+                break;
+            }
+        }
+        tokio::time::sleep(SETTLE_TIME).await;
+
+        let count = streams.len();
+        let (frames_before, dropped_before) = totals(&streams);
+        let process_before = cpuload::process_cpu_secs()?;
+        sampler.sample()?;
+        let started = Instant::now();
+        tokio::time::sleep(step).await;
+        let elapsed = started.elapsed().as_secs_f64();
+        let machine_cpu = sampler.sample()?.unwrap_or(0.0);
+        let process_cores = (cpuload::process_cpu_secs()? - process_before) / elapsed;
+        let (frames_after, dropped_after) = totals(&streams);
+
+        let target = (count as u32 * fps) as f64;
+        let delivered = (frames_after - frames_before) as f64 / elapsed;
+        let dropped = dropped_after - dropped_before;
+        println!(
+            "{:>3} stream(s): {:>7.1} of {:>5.0} fps delivered ({:>5.1}%), CPU {:>5.1}% of the machine, {:.2} core(s) per stream, {} frame(s) dropped",
+            count, delivered, target, 100.0 * delivered / target, machine_cpu, process_cores / count as f64, dropped
+        );
+        if delivered < target * KEEP_UP_RATIO || dropped > 0 {
+            println!("Frames start dropping at {} stream(s)", count);
+            saturated = true;
+            break;
+        }
+        sustained = Some(count);
+    }
+
+    match sustained {
+        Some(count) => println!("This box handles {} camera(s) at {} (without camera decoding)", count, label),
+        None => println!("This box doesn't keep up with a single camera at {}", label),
+    }
+    if !saturated && sustained == Some(total) {
+        println!("No frames dropped with all {} camera(s), run with a higher --bench to find the limit", total);
+    }
+    supervisor.shutdown();
+    Ok(())
+}
+
+fn watch(mut rx: broadcast::Receiver<Frame>, counts: Arc<ViewerCounts>) {
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(_) => {
+                    counts.frames.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    counts.lagged.fetch_add(missed, Ordering::Relaxed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    });
+}
+
+// Frames viewers got, and frames lost on the way: broadcaster queue overflows plus viewer lag
+fn totals(streams: &[(Arc<StreamStats>, Arc<ViewerCounts>)]) -> (u64, u64) {
+    streams.iter().fold((0, 0), |(frames, dropped), (stats, viewer)| {
+        (
+            frames + viewer.frames.load(Ordering::Relaxed),
+            dropped + stats.queue_dropped.load(Ordering::Relaxed) + viewer.lagged.load(Ordering::Relaxed),
+        )
+    })
+}
@@ -0,0 +1,206 @@
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gst::prelude::*;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::error::{NvrError, Result};
+
+// H.264 bitrate of the on-demand outputs, in kbit/s
+const LIVE_BITRATE_KBPS: u32 = 1500;
+
+// HLS segment length in seconds and how many segments the playlist keeps
+const HLS_TARGET_DURATION: u32 = 2;
+const HLS_PLAYLIST_LENGTH: u32 = 5;
+
+pub const HLS_PLAYLIST: &str = "playlist.m3u8";
+
+// Outputs besides the MJPEG preview, each an encode branch hung off the camera pipeline's
+// tee only while someone uses it. All of them share the pipeline's single decode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BranchKind {
+    // Fragmented MP4 (H.264) for Media Source Extensions players, over /ws/mse/<stream>
+    Mse,
+    // HLS playlist and MPEG-TS segments for mobile players, under /hls/<stream>/
+    Hls,
+}
+
+impl BranchKind {
+    pub const ALL: [BranchKind; 2] = [BranchKind::Mse, BranchKind::Hls];
+}
+
+// A piece of the fragmented MP4 stream. A viewer joining mid-stream has to start at a
+// fragment boundary (a moof box), the only place a SourceBuffer can pick up.
+#[derive(Clone)]
+pub struct Mp4Chunk {
+    pub data: Arc<[u8]>,
+    pub fragment_start: bool,
+}
+
+// Where a stream's on-demand outputs go. Created once per camera, outlives pipeline rebuilds.
+pub struct LiveOutputs {
+    pub mse: broadcast::Sender<Mp4Chunk>,
+    // ftyp and moov, what every MSE viewer needs before the first fragment
+    pub mse_init: Mutex<Option<Arc<[u8]>>>,
+    pub hls_dir: PathBuf,
+}
+
+impl LiveOutputs {
+    pub fn new(hls_dir: PathBuf) -> Self {
+        LiveOutputs {
+            mse: broadcast::channel(100).0,
+            mse_init: Mutex::new(None),
+            hls_dir,
+        }
+    }
+}
+
+// Encode `kind` from the tee of a running pipeline. Returns the branch, for detach.
+pub fn attach(pipeline: &gst::Pipeline, stream_name: &str, kind: BranchKind, outputs: &Arc<LiveOutputs>) -> Result<gst::Bin> {
+    pipeline.by_name("t").ok_or_else(|| NvrError::ElementMissing("tee".to_string()))?;
+    let encode = format!(
+        "queue leaky=downstream max-size-buffers=5 ! videoconvert ! x264enc tune=zerolatency speed-preset=veryfast bitrate={} key-int-max=30 ! video/x-h264,profile=main ! h264parse",
+        LIVE_BITRATE_KBPS
+    );
+    let description = match kind {
+        BranchKind::Mse => {
+            // The new encoder writes a new moov, viewers mustn't get the old one meanwhile
+            *outputs.mse_init.lock().unwrap() = None;
+            format!("{} ! mp4mux fragment-duration=1000 streamable=true ! appsink name=mse-sink sync=false", encode)
+        }
+        BranchKind::Hls => {
+            // Segments left from an earlier run would otherwise never be cleaned up
+            let _ = std::fs::remove_dir_all(&outputs.hls_dir);
+            std::fs::create_dir_all(&outputs.hls_dir)?;
+            format!(
+                "{} ! hlssink2 location={} playlist-location={} target-duration={} playlist-length={} max-files={}",
+                encode,
+                outputs.hls_dir.join("segment%05d.ts").display(),
+                outputs.hls_dir.join(HLS_PLAYLIST).display(),
+                HLS_TARGET_DURATION,
+                HLS_PLAYLIST_LENGTH,
+                HLS_PLAYLIST_LENGTH + 1
+            )
+        }
+    };
+
+    let branch = gst::parse::bin_from_description(&description, true)?;
+    if kind == BranchKind::Mse {
+        let appsink = branch
+            .by_name("mse-sink")
+            .and_then(|sink| sink.downcast::<gst_app::AppSink>().ok())
+            .ok_or_else(|| NvrError::ElementMissing("mse appsink".to_string()))?;
+        forward_mp4(&appsink, outputs.clone());
+    }
+
+    add(pipeline, &branch)?;
+    println!("{}: Started {:?} output", stream_name, kind);
+    Ok(branch)
+}
+
+// Hang a branch off the tee of a running pipeline and start it
+pub fn add(pipeline: &gst::Pipeline, branch: &gst::Bin) -> Result<()> {
+    let tee = pipeline.by_name("t").ok_or_else(|| NvrError::ElementMissing("tee".to_string()))?;
+    pipeline.add(branch)?;
+    let tee_pad = tee
+        .request_pad_simple("src_%u")
+        .ok_or_else(|| NvrError::Gst(gst::glib::bool_error!("tee refused a new src pad")))?;
+    let branch_sink = branch.static_pad("sink").expect("branch without sink pad");
+    tee_pad
+        .link(&branch_sink)
+        .map_err(|err| NvrError::Gst(gst::glib::bool_error!("can't link branch {}: {:?}", branch.name(), err)))?;
+    branch.sync_state_with_parent()?;
+    Ok(())
+}
+
+// Take a branch off the tee once the tee isn't pushing into it, then shut it down
+pub fn detach(pipeline: &gst::Pipeline, stream_name: &str, kind: BranchKind, branch: gst::Bin, outputs: &LiveOutputs) {
+    println!("{}: Stopping {:?} output, no more viewers", stream_name, kind);
+    // Don't let players pick up a playlist that won't be updated anymore
+    if kind == BranchKind::Hls {
+        let _ = std::fs::remove_file(outputs.hls_dir.join(HLS_PLAYLIST));
+    }
+
+    remove(pipeline, branch);
+}
+
+// Unlink a branch from the tee and shut it down, also used for ROI branches (see roi.rs)
+pub fn remove(pipeline: &gst::Pipeline, branch: gst::Bin) {
+    let branch_sink = branch.static_pad("sink").expect("branch without sink pad");
+    let pipeline_weak = pipeline.downgrade();
+    let shut_down = move || {
+        let _ = branch.set_state(gst::State::Null);
+        if let Some(pipeline) = pipeline_weak.upgrade() {
+            let _ = pipeline.remove(&branch);
+        }
+    };
+    let Some(tee_pad) = branch_sink.peer() else {
+        shut_down();
+        return;
+    };
+    tee_pad.add_probe(gst::PadProbeType::IDLE, move |tee_pad, _| {
+        let _ = tee_pad.unlink(&branch_sink);
+        if let Some(tee) = tee_pad.parent_element() {
+            tee.release_request_pad(tee_pad);
+        }
+        // Changing state from inside the probe would deadlock on the streaming thread
+        let shut_down = shut_down.clone();
+        std::thread::spawn(shut_down);
+        gst::PadProbeReturn::Remove
+    });
+}
+
+// Hand mp4mux's output to MSE viewers. Its header (ftyp, moov) comes as the caps'
+// streamheader, and changes when the encoder restarts with new caps.
+fn forward_mp4(appsink: &gst_app::AppSink, outputs: Arc<LiveOutputs>) {
+    let mut header_caps: Option<gst::Caps> = None;
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let Ok(sample) = appsink.pull_sample() else { return Ok(gst::FlowSuccess::Ok) };
+                if let Some(caps) = sample.caps() {
+                    if header_caps.as_deref() != Some(caps) {
+                        *outputs.mse_init.lock().unwrap() = stream_header(caps);
+                        header_caps = Some(caps.to_owned());
+                    }
+                }
+
+                let Some(buffer) = sample.buffer() else { return Ok(gst::FlowSuccess::Ok) };
+                if buffer.flags().contains(gst::BufferFlags::HEADER) {
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+                let Ok(map) = buffer.map_readable() else { return Ok(gst::FlowSuccess::Ok) };
+                let chunk = Mp4Chunk {
+                    fragment_start: map.get(4..8) == Some(b"moof".as_slice()),
+                    data: Arc::from(map.as_slice()),
+                };
+                // Nobody watching right now is fine, the branch goes away shortly
+                let _ = outputs.mse.send(chunk);
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+}
+
+fn stream_header(caps: &gst::CapsRef) -> Option<Arc<[u8]>> {
+    let headers = caps.structure(0)?.get::<gst::Array>("streamheader").ok()?;
+    let mut header = Vec::new();
+    for value in headers.iter() {
+        let buffer = value.get::<gst::Buffer>().ok()?;
+        header.extend_from_slice(buffer.map_readable().ok()?.as_slice());
+    }
+    Some(Arc::from(header))
+}
+
+// What the web page checks with MediaSource.isTypeSupported before choosing MSE over MJPEG.
+// The encoder makes Main profile; a browser that can play Main at level 3.1 plays ours.
+pub const MSE_PROBE_MIME: &str = "video/mp4; codecs=\"avc1.4d401f\"";
+
+// MSE wants the exact codec, e.g. video/mp4; codecs="avc1.4d401f": profile, constraint
+// flags and level straight out of the avcC box in the init segment
+pub fn mse_mime(init: &[u8]) -> Option<String> {
+    let at = init.windows(4).position(|window| window == b"avcC")?;
+    let config = init.get(at + 5..at + 8)?;
+    Some(format!("video/mp4; codecs=\"avc1.{:02x}{:02x}{:02x}\"", config[0], config[1], config[2]))
+}
@@ -0,0 +1,68 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+// A stream's broadcasting work: deliver whatever its queue holds, then return
+pub trait Drain: Send + Sync {
+    fn drain(&self);
+    // Set while the job is waiting in the pool or being drained, so it's queued once
+    fn scheduled(&self) -> &AtomicBool;
+    fn has_work(&self) -> bool;
+}
+
+// A few threads shared by every stream's broadcasting, for many low frame rate cameras
+// where a thread per stream mostly sleeps. A stream is drained by one thread at a time,
+// so its frames still go out in order.
+pub struct BroadcastPool {
+    ready: Mutex<VecDeque<Arc<dyn Drain>>>,
+    available: Condvar,
+}
+
+impl BroadcastPool {
+    pub fn new(threads: usize) -> Arc<Self> {
+        let pool = Arc::new(BroadcastPool {
+            ready: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+        });
+        for index in 0..threads {
+            let worker = pool.clone();
+            std::thread::Builder::new()
+                .name(format!("broadcast-{}", index))
+                .spawn(move || worker.run())
+                .expect("failed to spawn broadcast thread");
+        }
+        pool
+    }
+
+    // Call after queueing a frame. Does nothing if the job is already waiting or running,
+    // the running drain picks the new frame up.
+    pub fn wake(&self, job: Arc<dyn Drain>) {
+        if job.scheduled().swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.ready.lock().unwrap().push_back(job);
+        self.available.notify_one();
+    }
+
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut ready = self.ready.lock().unwrap();
+                loop {
+                    if let Some(job) = ready.pop_front() {
+                        break job;
+                    }
+                    ready = self.available.wait(ready).unwrap();
+                }
+            };
+            job.drain();
+            job.scheduled().store(false, Ordering::SeqCst);
+            // A frame queued between the drain's last look and clearing the flag found it
+            // still set and didn't schedule, so check once more
+            if job.has_work() && !job.scheduled().swap(true, Ordering::SeqCst) {
+                self.ready.lock().unwrap().push_back(job);
+                self.available.notify_one();
+            }
+        }
+    }
+}
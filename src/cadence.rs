@@ -0,0 +1,86 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+
+// How much of each frame's timing error is taken into the phase and the rate. Small, so
+// arrival jitter averages out over many frames while a source's clock drift is followed.
+const PHASE_GAIN: f64 = 0.02;
+const RATE_GAIN: f64 = 0.0005;
+
+// The estimated frame period stays within this fraction of the configured one
+const MAX_RATE_ERROR: f64 = 0.05;
+
+// Further off than this (a reconnect, a stall) and the cadence starts over from the frame
+const RESYNC_SECS: f64 = 2.0;
+
+// How far the source is off its configured frame rate, for /api/metrics
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Drift {
+    // Smoothed difference between when frames arrive and the steady cadence
+    pub offset_ms: f64,
+    // Source clock against the configured fps: positive runs fast, negative slow
+    pub rate_ppm: f64,
+    // Times the cadence was restarted because frames were too far off it
+    pub resyncs: u64,
+}
+
+// Rebases frame timestamps onto a steady cadence around the configured fps. Arrivals only
+// nudge it: the period tracks the source's actual rate (its clock drift) and the phase
+// follows the arrivals' average, but a single late or early frame moves neither much.
+// Frames the source skipped are recognised and leave the cadence in step.
+pub struct CadenceClock {
+    nominal: f64,
+    period: f64,
+    // Timestamp handed to the last frame
+    last: Option<DateTime<Utc>>,
+    drift: Drift,
+}
+
+impl CadenceClock {
+    pub fn new(fps: f64) -> Self {
+        CadenceClock {
+            nominal: 1.0 / fps,
+            period: 1.0 / fps,
+            last: None,
+            drift: Drift::default(),
+        }
+    }
+
+    // Steady timestamp for a frame that arrived (or was captured) at `arrived`
+    pub fn stamp(&mut self, arrived: DateTime<Utc>) -> DateTime<Utc> {
+        let Some(last) = self.last else {
+            self.last = Some(arrived);
+            return arrived;
+        };
+
+        let since_last = seconds(arrived - last);
+        if since_last.abs() > RESYNC_SECS {
+            self.drift.resyncs += 1;
+            self.drift.offset_ms = 0.0;
+            self.last = Some(arrived);
+            return arrived;
+        }
+
+        // Whole frames between the last one and this, more than one when frames were skipped
+        let frames = (since_last / self.period).round().max(1.0);
+        let expected = frames * self.period;
+        let error = since_last - expected;
+
+        self.period = (self.period + RATE_GAIN * error / frames)
+            .clamp(self.nominal * (1.0 - MAX_RATE_ERROR), self.nominal * (1.0 + MAX_RATE_ERROR));
+        let step = expected + PHASE_GAIN * error;
+        let stamped = last + TimeDelta::nanoseconds((step * 1e9) as i64);
+
+        self.drift.offset_ms = seconds(arrived - stamped) * 1000.0;
+        self.drift.rate_ppm = (self.nominal / self.period - 1.0) * 1e6;
+        self.last = Some(stamped);
+        stamped
+    }
+
+    pub fn drift(&self) -> Drift {
+        self.drift
+    }
+}
+
+fn seconds(delta: TimeDelta) -> f64 {
+    delta.num_microseconds().map_or(f64::MAX, |micros| micros as f64 / 1e6)
+}
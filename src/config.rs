@@ -0,0 +1,2026 @@
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fmt::{Debug, Display};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Rough CPU cost in cores of software-decoding one typical 1080p camera stream
+const DECODE_CORES_PER_STREAM: f64 = 0.25;
+// JPEG encoding the preview, per megapixel of output (640x360 is ~0.23 MP)
+const JPEG_CORES_PER_MEGAPIXEL: f64 = 0.2;
+// x264 re-encode for a restream
+const RESTREAM_CORES: f64 = 0.5;
+
+// RTSP sessions a camera is assumed to accept when no entry sets max_connections.
+// Many consumer cameras stop at two or three.
+const DEFAULT_CAMERA_CONNECTIONS: u32 = 2;
+
+// A minute of 1080p preview JPEGs is already ~100 MB per camera
+const MAX_SEEK_BUFFER_SECS: u64 = 60;
+
+// Events are small, this only guards against a typo keeping millions of them
+const MAX_EVENT_HISTORY: usize = 10_000;
+
+// Every setting is resolved in the same order: CLI flag > NVR_* env var > config file > built-in default.
+// Cameras come from the config file, with CCTV_* env vars added on top (or overriding the url of a
+// camera with the same name).
+
+#[derive(Parser)]
+#[command(about = "RTSP to WebSocket NVR")]
+pub struct Cli {
+    /// YAML config file
+    #[arg(long, env = "NVR_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Check the config, print every problem and exit without starting anything
+    #[arg(long)]
+    pub validate_config: bool,
+
+    /// Print a JSON Schema of the config file and exit
+    #[arg(long)]
+    pub print_schema: bool,
+
+    /// Print every effective setting and where it came from (also NVR_DEBUG=1)
+    #[arg(long)]
+    pub debug: bool,
+
+    /// HTTP port [env: NVR_PORT] [default: 3030]
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Address the HTTP port listens on [env: NVR_BIND_ADDRESS] [default: 0.0.0.0]
+    #[arg(long)]
+    pub bind_address: Option<IpAddr>,
+
+    /// Serve the viewer streams (/ws, /sse, /hls) on this address instead, e.g. 0.0.0.0:3031, leaving the UI and API alone on the HTTP port [env: NVR_STREAM_ADDRESS]
+    #[arg(long)]
+    pub stream_address: Option<SocketAddr>,
+
+    /// Where cameras with rtsp_relay are re-served over RTSP, needs the rtsp-relay feature [env: NVR_RTSP_RELAY_ADDRESS] [default: 0.0.0.0:8554]
+    #[arg(long)]
+    pub rtsp_relay_address: Option<SocketAddr>,
+
+    /// Path prefix when served behind a reverse proxy, e.g. /nvr [env: NVR_BASE_PATH]
+    #[arg(long)]
+    pub base_path: Option<String>,
+
+    /// Name of this instance in GET /api/manifest, for dashboards aggregating several NVRs [env: NVR_SERVER_ID] [default: hostname]
+    #[arg(long)]
+    pub server_id: Option<String>,
+
+    /// Refuse to start with more cameras than this [env: NVR_MAX_STREAMS] [default: 64]
+    #[arg(long)]
+    pub max_streams: Option<usize>,
+
+    /// Frames buffered between each pipeline and its broadcaster [env: NVR_FRAME_QUEUE_SIZE] [default: 4]
+    #[arg(long)]
+    pub frame_queue_size: Option<usize>,
+
+    /// Broadcast every stream's frames from a shared pool of this many threads instead of a thread per stream (0 keeps one per stream) [env: NVR_BROADCAST_THREADS] [default: 0]
+    #[arg(long)]
+    pub broadcast_threads: Option<usize>,
+
+    /// Frames kept per stream and dumped to the debug dir on pipeline errors, 0 disables [env: NVR_DEBUG_FRAMES]
+    #[arg(long)]
+    pub debug_frames: Option<usize>,
+
+    /// Where post-mortem frames are written [env: NVR_DEBUG_DIR] [default: debug]
+    #[arg(long)]
+    pub debug_dir: Option<PathBuf>,
+
+    /// Base directory recordings are served from [env: NVR_RECORDINGS_DIR] [default: recordings]
+    #[arg(long)]
+    pub recordings_dir: Option<PathBuf>,
+
+    /// Where HLS playlists and segments are written while someone watches [env: NVR_HLS_DIR] [default: hls]
+    #[arg(long)]
+    pub hls_dir: Option<PathBuf>,
+
+    /// Where the generated viewer page (index.html) is written [env: NVR_PAGE_DIR] [default: page]
+    #[arg(long)]
+    pub page_dir: Option<PathBuf>,
+
+    /// Include client IPs in connection logs [env: NVR_LOG_CLIENT_IPS] [default: true]
+    #[arg(long)]
+    pub log_client_ips: Option<bool>,
+
+    /// Broadcast a "NO SIGNAL" frame once a second while a camera is down [env: NVR_NO_SIGNAL_FRAMES] [default: false]
+    #[arg(long)]
+    pub no_signal_frames: Option<bool>,
+
+    /// Keep at least this many MB free on the recordings disk [env: NVR_MIN_FREE_DISK_MB] [default: 1024]
+    #[arg(long)]
+    pub min_free_disk_mb: Option<u64>,
+
+    /// What to do when the recordings disk runs low: pause or prune [env: NVR_DISK_FULL_ACTION] [default: pause]
+    #[arg(long)]
+    pub disk_full_action: Option<DiskFullAction>,
+
+    /// Lower preview quality while CPU usage stays above this percentage, lowest-priority cameras first (0 disables) [env: NVR_LOAD_SHED_CPU] [default: 90]
+    #[arg(long)]
+    pub load_shed_cpu: Option<u32>,
+
+    /// Preview JPEG quality cap while shedding load [env: NVR_LOAD_SHED_QUALITY] [default: 40]
+    #[arg(long)]
+    pub load_shed_quality: Option<u32>,
+
+    /// Preview frame rate cap while shedding load (0 leaves the frame rate alone) [env: NVR_LOAD_SHED_FPS] [default: 0]
+    #[arg(long)]
+    pub load_shed_fps: Option<u32>,
+
+    /// Ping WebSocket viewers after this many seconds without a frame, so proxies don't close idle connections (0 disables) [env: NVR_WS_HEARTBEAT_SECS] [default: 20]
+    #[arg(long)]
+    pub ws_heartbeat_secs: Option<u64>,
+
+    /// Disconnect a WebSocket viewer once this many KiB sent to it are still unread, e.g. a stalled client [env: NVR_WS_MAX_SEND_BUFFER_KB] [default: 4096]
+    #[arg(long)]
+    pub ws_max_send_buffer_kb: Option<usize>,
+
+    /// Seconds of recent frames kept in memory per camera for viewers seeking back (0 disables) [env: NVR_SEEK_BUFFER_SECS] [default: 10]
+    #[arg(long)]
+    pub seek_buffer_secs: Option<u64>,
+
+    /// Recent events kept in memory per camera for GET /api/events (0 disables) [env: NVR_EVENT_HISTORY] [default: 100]
+    #[arg(long)]
+    pub event_history: Option<usize>,
+
+    /// Restart a camera pipeline that hasn't reached Playing this many seconds after starting (0 disables) [env: NVR_PIPELINE_START_TIMEOUT_SECS] [default: 20]
+    #[arg(long)]
+    pub pipeline_start_timeout_secs: Option<u64>,
+
+    /// Exit with an error unless none, any or all cameras deliver a frame within require_streams_secs of startup [env: NVR_REQUIRE_STREAMS] [default: none]
+    #[arg(long)]
+    pub require_streams: Option<RequireStreams>,
+
+    /// How long startup waits for the cameras require_streams asks for [env: NVR_REQUIRE_STREAMS_SECS] [default: 60]
+    #[arg(long)]
+    pub require_streams_secs: Option<u64>,
+
+    /// Seconds between per-client summaries of frames dropped for slow viewers [env: NVR_DROP_LOG_INTERVAL_SECS] [default: 10]
+    #[arg(long)]
+    pub drop_log_interval_secs: Option<u64>,
+
+    /// Cameras per page of the web UI, only the visible page is streamed (0 puts all on one page) [env: NVR_CAMERAS_PER_PAGE] [default: 0]
+    #[arg(long)]
+    pub cameras_per_page: Option<usize>,
+
+    /// Seconds the web page keeps showing a dimmed last frame after a disconnect [env: NVR_RECONNECT_GRACE_SECS] [default: 10]
+    #[arg(long)]
+    pub reconnect_grace_secs: Option<u64>,
+
+    /// Play cameras in the web page as H.264 over Media Source Extensions where the browser supports it, MJPEG elsewhere [env: NVR_PREFER_MSE] [default: false]
+    #[arg(long)]
+    pub prefer_mse: Option<bool>,
+
+    /// Push each camera's decode fps, source bitrate and resolution to viewers and show them in the tile footer [env: NVR_PIPELINE_STATS] [default: false]
+    #[arg(long)]
+    pub pipeline_stats: Option<bool>,
+
+    /// Collect GStreamer QoS and latency stats for /api/metrics, syncs the preview sink to the clock [env: NVR_QOS_STATS] [default: false]
+    #[arg(long)]
+    pub qos_stats: Option<bool>,
+
+    /// At startup, try each installed hardware decoder (VA-API, NVDEC, V4L2) and leave the ones that fail to initialize out of decoder: hardware [env: NVR_PROBE_HW_DECODERS] [default: true]
+    #[arg(long)]
+    pub probe_hw_decoders: Option<bool>,
+
+    /// Append connect/disconnect events to this file [env: NVR_ACCESS_LOG]
+    #[arg(long)]
+    pub access_log: Option<PathBuf>,
+
+    /// Write an audit trail of connections, snapshots, recordings and clip downloads to this file as JSON lines [env: NVR_AUDIT_LOG]
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Rotate the audit log once it reaches this many MB [env: NVR_AUDIT_LOG_MAX_MB] [default: 10]
+    #[arg(long)]
+    pub audit_log_max_mb: Option<u64>,
+
+    /// Rotated audit logs kept next to the current one [env: NVR_AUDIT_LOG_KEEP] [default: 5]
+    #[arg(long)]
+    pub audit_log_keep: Option<usize>,
+
+    /// Push per-stream metrics as InfluxDB line protocol to this write URL, e.g. http://influx:8086/api/v2/write?org=home&bucket=nvr (token in NVR_INFLUX_TOKEN) [env: NVR_INFLUX_URL]
+    #[arg(long)]
+    pub influx_url: Option<String>,
+
+    /// Seconds between InfluxDB pushes [env: NVR_INFLUX_INTERVAL_SECS] [default: 10]
+    #[arg(long)]
+    pub influx_interval_secs: Option<u64>,
+
+    /// InfluxDB measurement the stream metrics are written to [env: NVR_INFLUX_MEASUREMENT] [default: nvr_stream]
+    #[arg(long)]
+    pub influx_measurement: Option<String>,
+
+    /// Extra tags on every InfluxDB point, as key=value,key=value [env: NVR_INFLUX_TAGS]
+    #[arg(long)]
+    pub influx_tags: Option<String>,
+
+    /// Credential set used by cameras that don't name one [env: NVR_DEFAULT_CREDENTIALS]
+    #[arg(long)]
+    pub default_credentials: Option<String>,
+
+    /// Benchmark instead of serving: add synthetic cameras one at a time, up to N, and report throughput, CPU per stream and where frames start dropping
+    #[arg(long)]
+    pub bench: Option<usize>,
+
+    /// Resolution of the benchmark's synthetic cameras [default: 1280x720]
+    #[arg(long)]
+    pub bench_resolution: Option<String>,
+
+    /// Frame rate of the benchmark's synthetic cameras [default: 15]
+    #[arg(long)]
+    pub bench_fps: Option<u32>,
+
+    /// Seconds each benchmark step is measured for [default: 10]
+    #[arg(long)]
+    pub bench_step_secs: Option<u64>,
+
+    /// Add N test-pattern cameras served by a built-in RTSP server
+    #[cfg(feature = "fake-source")]
+    #[arg(long)]
+    pub fake_cameras: Option<usize>,
+
+    /// WebSocket connections allowed per second per IP, 0 disables [env: NVR_WS_RATE_LIMIT] [default: 5]
+    #[arg(long)]
+    pub ws_rate_limit: Option<f64>,
+
+    /// WebSocket connections an IP may open in a burst [env: NVR_WS_RATE_BURST] [default: 50]
+    #[arg(long)]
+    pub ws_rate_burst: Option<u32>,
+
+    /// Comma-separated IPs exempt from the rate limit [env: NVR_WS_RATE_ALLOW]
+    #[arg(long, value_delimiter = ',')]
+    pub ws_rate_allow: Option<Vec<IpAddr>>,
+}
+
+// On-disk config file
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub server: ServerConfig,
+    // Named user/pass pairs cameras can refer to instead of repeating them
+    #[serde(default)]
+    pub credentials: BTreeMap<String, CredentialSet>,
+    #[serde(default)]
+    pub cameras: Vec<CameraConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CredentialSet {
+    pub user: String,
+    pub pass: String,
+}
+
+// Server-wide settings from the config file; unset fields fall through to the defaults
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: Option<u16>,
+    pub bind_address: Option<IpAddr>,
+    pub stream_address: Option<SocketAddr>,
+    pub rtsp_relay_address: Option<SocketAddr>,
+    pub base_path: Option<String>,
+    pub server_id: Option<String>,
+    pub max_streams: Option<usize>,
+    pub frame_queue_size: Option<usize>,
+    pub broadcast_threads: Option<usize>,
+    pub debug_frames: Option<usize>,
+    pub debug_dir: Option<PathBuf>,
+    pub recordings_dir: Option<PathBuf>,
+    pub hls_dir: Option<PathBuf>,
+    pub page_dir: Option<PathBuf>,
+    pub log_client_ips: Option<bool>,
+    pub no_signal_frames: Option<bool>,
+    pub reconnect_grace_secs: Option<u64>,
+    pub cameras_per_page: Option<usize>,
+    pub prefer_mse: Option<bool>,
+    pub pipeline_stats: Option<bool>,
+    pub drop_log_interval_secs: Option<u64>,
+    pub ws_heartbeat_secs: Option<u64>,
+    pub ws_max_send_buffer_kb: Option<usize>,
+    pub seek_buffer_secs: Option<u64>,
+    pub event_history: Option<usize>,
+    pub pipeline_start_timeout_secs: Option<u64>,
+    pub require_streams: Option<RequireStreams>,
+    pub require_streams_secs: Option<u64>,
+    pub min_free_disk_mb: Option<u64>,
+    pub disk_full_action: Option<DiskFullAction>,
+    pub load_shed_cpu: Option<u32>,
+    pub load_shed_quality: Option<u32>,
+    pub load_shed_fps: Option<u32>,
+    pub qos_stats: Option<bool>,
+    pub probe_hw_decoders: Option<bool>,
+    pub access_log: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_log_max_mb: Option<u64>,
+    pub audit_log_keep: Option<usize>,
+    pub influx_url: Option<String>,
+    pub influx_interval_secs: Option<u64>,
+    pub influx_measurement: Option<String>,
+    pub influx_tags: Option<String>,
+    pub default_credentials: Option<String>,
+    pub ws_rate_limit: Option<f64>,
+    pub ws_rate_burst: Option<u32>,
+    pub ws_rate_allow: Option<Vec<IpAddr>>,
+    // Config file only, header values often contain commas
+    pub response_headers: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CameraConfig {
+    // Used in URLs, element IDs and as the stream key
+    pub name: String,
+    // Shown in the UI instead of `name` when set
+    #[serde(default)]
+    pub display_name: Option<String>,
+    // Free-form labels (e.g. outdoor, building-a) for dashboards reading GET /api/manifest
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Required unless `source` is set
+    #[serde(default)]
+    pub url: String,
+    // Take frames from a local producer instead of `url`: shm:/path reads a GStreamer
+    // shmsink, unix:/path listens on a Unix socket for back-to-back JPEGs, and
+    // test:1280x720@15 is a synthetic test pattern. Decoded frames go through the same
+    // preview, recording and restreaming branches.
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub mode: CameraMode,
+    // See Settings::camera_credentials for how unset user/pass are filled in
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub pass: Option<String>,
+    // Name of an entry in the top-level `credentials` map
+    #[serde(default)]
+    pub credentials: Option<String>,
+    #[serde(default = "default_width")]
+    pub width: u32,
+    #[serde(default = "default_height")]
+    pub height: u32,
+    // Keep only this part of the camera picture, in the main stream's pixels before
+    // rotation. Applies to everything decoded from `url`: preview, recording, restreaming,
+    // thumbnails and motion snapshots. The substream is left uncropped.
+    #[serde(default)]
+    pub crop: Option<CropRect>,
+    // How the picture is fitted to width x height when its aspect ratio differs
+    #[serde(default)]
+    pub scale_mode: ScaleMode,
+    #[serde(default = "default_jpeg_quality")]
+    pub jpeg_quality: u32,
+    // Lower the preview quality as viewers pile up, to bound the stream's total egress.
+    // Each step applies from its number of viewers on; below the first, jpeg_quality.
+    #[serde(default)]
+    pub quality_ladder: Vec<QualityStep>,
+    // Viewers that may zoom in on part of the picture at once (the roi WebSocket command).
+    // Each gets its own crop, scale and JPEG encode, so this is a CPU budget; 0 disables it.
+    #[serde(default)]
+    pub roi_max_clients: u32,
+    // Clockwise rotation for cameras mounted sideways or upside down: 0, 90, 180 or 270
+    #[serde(default)]
+    pub rotation: u32,
+    #[serde(default)]
+    pub rotation_mode: RotationMode,
+    // Cap on preview frames per second. Recording and restreaming still get every frame.
+    #[serde(default)]
+    pub preview_fps: Option<u32>,
+    // Only scale and JPEG-encode the preview while someone watches it. The camera stays
+    // connected and decoded for recording and restreaming either way.
+    #[serde(default)]
+    pub preview_on_demand: bool,
+    // Higher is more important. Under CPU pressure previews are degraded (load_shed_quality,
+    // load_shed_fps) one priority level at a time, lowest first, and restored highest first.
+    #[serde(default)]
+    pub priority: u32,
+    // Have the preview appsink wait for each frame's time on the pipeline clock instead of
+    // passing frames on as soon as they're decoded. Steadier pacing, but adds the rtspsrc
+    // jitterbuffer latency and drops frames that arrive late. Defaults to qos_stats.
+    #[serde(default)]
+    pub sync: Option<bool>,
+    // Crossfade from each frame into the next in the browser, so low-fps cameras look less
+    // choppy. Purely cosmetic: no new picture information, shown up to a frame later.
+    #[serde(default)]
+    pub interpolate_frames: bool,
+    // Lower-quality stream of the same camera (e.g. rtsp://.../stream2). Viewers can switch
+    // between it and the main `url`, which keeps feeding recording and restreaming.
+    #[serde(default)]
+    pub substream_url: Option<String>,
+    // RTMP endpoint to restream this camera to (e.g. YouTube/Twitch ingest)
+    #[serde(default)]
+    pub restream_url: Option<String>,
+    // Re-serve the camera at rtsp://<rtsp_relay_address>/<name>, encoded to H.264 from the
+    // same decode as the preview, so RTSP clients don't take connections from the camera
+    #[serde(default)]
+    pub rtsp_relay: bool,
+    // How the camera's video gets decoded, see DecodeMode
+    #[serde(default)]
+    pub decoder: DecodeMode,
+    // What to do when the camera switches codecs mid-session, see CodecChange
+    #[serde(default)]
+    pub codec_change: CodecChange,
+    // Take each frame's capture time from the camera's RTCP sender reports (NTP wall clock)
+    // so frames of different cameras can be aligned. Needs a camera with working RTCP and
+    // a synced clock, and GStreamer 1.22+. Without sender reports the server's clock is used.
+    #[serde(default)]
+    pub capture_timestamps: bool,
+    // Rebase frame timestamps (captured_at) onto a steady cadence at this frame rate,
+    // following the source's clock drift slowly instead of every frame's arrival jitter.
+    // For keeping several cameras aligned over hours; the drift shows in /api/metrics.
+    #[serde(default)]
+    pub timestamp_fps: Option<f64>,
+    // Compute motion bounding boxes that viewers can overlay on the preview
+    #[serde(default)]
+    pub motion_detection: bool,
+    // Don't send frames that barely differ from the last one sent (static scenes)
+    #[serde(default)]
+    pub skip_static_frames: bool,
+    // Motion score (0.0-1.0) below which a frame counts as static
+    #[serde(default = "default_static_threshold")]
+    pub static_threshold: f64,
+    // Don't send a frame that is byte for byte the last one sent, for cameras that repeat
+    // their picture to keep up the frame rate. Cheaper than skip_static_frames, no decoding.
+    #[serde(default)]
+    pub skip_duplicate_frames: bool,
+    // Drop preview frames that aren't a complete JPEG (truncated or garbled by a glitching
+    // camera) instead of sending them, counted as corrupt_frames in /api/metrics
+    #[serde(default)]
+    pub drop_corrupt_frames: bool,
+    // Save the preview JPEG to recordings_dir/<name>/ when motion shows up, an event
+    // archive for cameras that don't record video
+    #[serde(default)]
+    pub snapshot_on_motion: bool,
+    // Seconds after a snapshot burst starts before motion can start another one
+    #[serde(default = "default_snapshot_cooldown_secs")]
+    pub snapshot_cooldown_secs: u64,
+    // Consecutive frames saved per motion event
+    #[serde(default = "default_snapshot_burst")]
+    pub snapshot_burst: u32,
+    // Send a Detector/Tamper event when the whole scene suddenly changes and stays changed
+    // (lens covered or sprayed, camera knocked askew)
+    #[serde(default)]
+    pub tamper_detection: bool,
+    // Frame-to-frame change score (0.0-1.0) that counts as a sudden jump
+    #[serde(default = "default_tamper_threshold")]
+    pub tamper_threshold: f64,
+    // How long the scene has to stay changed before the event is sent
+    #[serde(default = "default_tamper_secs")]
+    pub tamper_secs: u64,
+    // ONVIF event service (e.g. http://192.168.1.10/onvif/event_service) to pull the
+    // camera's own events from, using the camera's credentials
+    #[serde(default)]
+    pub onvif_url: Option<String>,
+    // HTTP still image (ONVIF GetSnapshotUri or vendor URL), preferred over the decoded
+    // stream for thumbnails. Fetched with the camera's credentials using basic auth.
+    #[serde(default)]
+    pub snapshot_uri: Option<String>,
+    // Extra headers on this camera's /api/thumbnail and /sse responses, e.g.
+    // Cache-Control: max-age=1 for dashboards embedding the snapshot. Over server ones.
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+    // Reach the camera through a SOCKS5 proxy, socks5://[user:pass@]host:port (RTSP over TCP only)
+    #[serde(default)]
+    pub socks_proxy: Option<String>,
+    // Network interface (e.g. eth1) multicast streams are joined on, for hosts on several
+    // networks where the default route doesn't lead to the camera VLAN
+    #[serde(default)]
+    pub multicast_iface: Option<String>,
+    // Local address to connect to the camera from, through the same kind of local tunnel
+    // as socks_proxy but direct (RTSP over TCP only)
+    #[serde(default)]
+    pub local_address: Option<IpAddr>,
+    // RTSP sessions the camera accepts. Several entries may share one camera url (e.g. a
+    // preview and a recording entry), each opens its own session; the lowest value set by
+    // any entry for that host wins. Only used to warn at startup.
+    #[serde(default)]
+    pub max_connections: Option<u32>,
+    // How long to wait before restarting the pipeline after an error, growing with each
+    // restart that doesn't bring a frame. Defaults to a steady 5 s, retrying forever.
+    #[serde(default)]
+    pub reconnect: ReconnectPolicy,
+    // Record to `recordings_dir/<name>/` in segments; the preview stays JPEG either way
+    #[serde(default)]
+    pub record: bool,
+    #[serde(default)]
+    pub recording_codec: RecordingCodec,
+    // File format of the segments. MKV stays playable when a segment is cut short by a
+    // crash or restart; an MP4 segment is only readable once it was finished.
+    #[serde(default)]
+    pub recording_container: RecordingContainer,
+    // Target bitrate in kbit/s. When unset, software encoders use constant quality (CRF).
+    #[serde(default)]
+    pub recording_bitrate: Option<u32>,
+    // Constant quality for x264enc/x265enc, lower is better (default 23 for H.264, 28 for H.265)
+    #[serde(default)]
+    pub recording_crf: Option<u32>,
+    // Longest stretch between keyframes of the recording, as frames (60) or seconds ("2s").
+    // Clips can only start at a keyframe, so shorter intervals cut exported clips closer to
+    // the requested time; longer ones make smaller files. Unset keeps the encoder's default.
+    #[serde(default)]
+    pub keyframe_interval: Option<KeyframeInterval>,
+    // Pace the recording branch's file writes by the pipeline clock. The files' timestamps
+    // come from the buffers either way; syncing can make the leaky queue in front of the
+    // encoder drop frames when writing falls behind.
+    #[serde(default)]
+    pub recording_sync: bool,
+    // Mux a timed-text track into the recording with the camera name, UTC time and motion
+    // and camera events, so they travel with the file without being burnt into the picture
+    #[serde(default)]
+    pub recording_text_track: bool,
+    // Burn a red dot into the preview's corner while recording, so viewers can tell the
+    // camera is being captured. The recording itself stays clean.
+    #[serde(default)]
+    pub recording_indicator: bool,
+    // Only record inside these windows, live preview runs regardless. Unset records always.
+    #[serde(default)]
+    pub recording_schedule: Option<RecordingSchedule>,
+}
+
+// One step of a camera's quality_ladder
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+pub struct QualityStep {
+    // Applies from this many viewers on
+    pub viewers: usize,
+    pub quality: u32,
+}
+
+// Top-left corner and size, in source pixels
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CropRect {
+    #[serde(default)]
+    pub x: u32,
+    #[serde(default)]
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Restart delays: initial_delay_secs after the first failure, multiplied by multiplier
+// after each further one up to max_delay_secs. After max_attempts restarts in a row without
+// a frame the stream is left stopped until restarted through the API (0 never gives up).
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectPolicy {
+    #[serde(default = "default_reconnect_delay_secs")]
+    pub initial_delay_secs: f64,
+    #[serde(default = "default_reconnect_max_delay_secs")]
+    pub max_delay_secs: f64,
+    #[serde(default = "default_reconnect_multiplier")]
+    pub multiplier: f64,
+    #[serde(default)]
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay_secs: default_reconnect_delay_secs(),
+            max_delay_secs: default_reconnect_max_delay_secs(),
+            multiplier: default_reconnect_multiplier(),
+            max_attempts: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum KeyframeInterval {
+    Frames(u32),
+    // "<seconds>s", turned into frames with the camera's frame rate
+    Seconds(String),
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RecordingSchedule {
+    // IANA name (e.g. Europe/Berlin, UTC). Required so times never silently follow the host clock.
+    pub timezone: String,
+    pub ranges: Vec<ScheduleRange>,
+}
+
+// start/end are HH:MM; an end before the start runs past midnight
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleRange {
+    // mon, tue, ...; empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingCodec {
+    #[default]
+    H264,
+    // Roughly half the storage of H.264 at the same quality, at more CPU cost
+    H265,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingContainer {
+    #[default]
+    Mkv,
+    // Plays almost anywhere, but a segment the server didn't get to finish has no index
+    Mp4,
+}
+
+impl RecordingContainer {
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordingContainer::Mkv => "mkv",
+            RecordingContainer::Mp4 => "mp4",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraMode {
+    #[default]
+    Video,
+    // Only the camera's audio track, Opus packets at /ws/audio/<name>. Nothing is decoded
+    // for video, so there's no tile, recording or restream.
+    Audio,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleMode {
+    // The whole picture, with black bars where the aspect ratios differ
+    #[default]
+    Contain,
+    // Fill the preview, cropping the picture's edges
+    Cover,
+    // Fill the preview, distorting the picture
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationMode {
+    // Rotate the decoded video, for every branch
+    #[default]
+    Pixels,
+    // Only tag preview JPEGs with an EXIF orientation and let the browser rotate them.
+    // Recording and restreaming still rotate pixels. Motion boxes stay in camera orientation.
+    Metadata,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodeMode {
+    // Pick the depayloader and software decoder for the codec the camera announces
+    // (H.264, H.265 or MJPEG), falling back to decodebin for anything else
+    #[default]
+    Auto,
+    // Like auto, but prefer a hardware decoder (VA-API, NVDEC, V4L2) when one is installed
+    Hardware,
+    // Leave the choice to decodebin
+    Decodebin,
+}
+
+// Some cameras switch between H.264 and H.265 on their own (firmware updates, smart codec
+// settings). Only seen with decoder auto or hardware, decodebin stays on the first codec.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CodecChange {
+    // Swap the depayloader and decoder for the new codec, the rest of the pipeline runs on
+    #[default]
+    Rebuild,
+    // Restart the whole pipeline, for cameras whose switch confuses the running session
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DiskFullAction {
+    // Stop recording until space is freed by someone else
+    #[default]
+    Pause,
+    // Delete the oldest segments until there's enough space again, pausing only if that
+    // isn't enough
+    Prune,
+}
+
+impl std::str::FromStr for DiskFullAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pause" => Ok(DiskFullAction::Pause),
+            "prune" => Ok(DiskFullAction::Prune),
+            _ => Err(format!("unknown disk_full_action {:?}, expected pause or prune", s)),
+        }
+    }
+}
+
+// How many cameras have to come online at startup for the server to keep running. For
+// orchestrators that would rather restart the service than have it serve an empty grid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RequireStreams {
+    // Keep running and retry the cameras in the background
+    #[default]
+    None,
+    // Exit unless at least one camera delivers a frame in time
+    Any,
+    // Exit unless every camera does
+    All,
+}
+
+impl std::str::FromStr for RequireStreams {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RequireStreams::None),
+            "any" => Ok(RequireStreams::Any),
+            "all" => Ok(RequireStreams::All),
+            _ => Err(format!("unknown require_streams {:?}, expected none, any or all", s)),
+        }
+    }
+}
+
+// Where an effective setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Cli,
+    Env,
+    File,
+    Default,
+}
+
+// Fully resolved settings the server runs with
+pub struct Settings {
+    pub debug: bool,
+    pub port: u16,
+    pub bind_address: IpAddr,
+    // Separate listener for viewer streams, None serves them on `port` too
+    pub stream_address: Option<SocketAddr>,
+    pub rtsp_relay_address: SocketAddr,
+    // Normalized to "" (served at the root) or "/segment[/segment...]" without a trailing slash
+    pub base_path: String,
+    pub server_id: String,
+    pub max_streams: usize,
+    pub frame_queue_size: usize,
+    // 0 gives every stream a broadcaster thread of its own
+    pub broadcast_threads: usize,
+    pub debug_frames: usize,
+    pub debug_dir: PathBuf,
+    pub recordings_dir: PathBuf,
+    pub hls_dir: PathBuf,
+    pub page_dir: PathBuf,
+    pub log_client_ips: bool,
+    pub no_signal_frames: bool,
+    pub reconnect_grace_secs: u64,
+    pub cameras_per_page: usize,
+    pub prefer_mse: bool,
+    pub pipeline_stats: bool,
+    pub drop_log_interval_secs: u64,
+    pub ws_heartbeat_secs: u64,
+    pub ws_max_send_buffer_kb: usize,
+    // Costs this many seconds of preview JPEGs in memory per camera, 0 disables
+    pub seek_buffer_secs: u64,
+    // Per camera, 0 disables GET /api/events
+    pub event_history: usize,
+    // Cameras that accept the connection but never finish RTSP negotiation, 0 disables
+    pub pipeline_start_timeout_secs: u64,
+    pub require_streams: RequireStreams,
+    pub require_streams_secs: u64,
+    pub min_free_disk_mb: u64,
+    pub disk_full_action: DiskFullAction,
+    // CPU percentage that triggers load shedding, 0 disables
+    pub load_shed_cpu: u32,
+    pub load_shed_quality: u32,
+    // 0 leaves the preview frame rate alone while shedding load
+    pub load_shed_fps: u32,
+    pub qos_stats: bool,
+    // Hardware decoders that are installed but can't initialize are skipped, see decode::probe_hardware
+    pub probe_hw_decoders: bool,
+    pub access_log: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_log_max_mb: u64,
+    pub audit_log_keep: usize,
+    // Line protocol write URL, None disables the InfluxDB exporter
+    pub influx_url: Option<String>,
+    // Sent as "Authorization: Token ...", only read from NVR_INFLUX_TOKEN
+    pub influx_token: Option<String>,
+    pub influx_interval_secs: u64,
+    pub influx_measurement: String,
+    pub influx_tags: Option<String>,
+    pub ws_rate_limit: f64,
+    pub ws_rate_burst: u32,
+    pub ws_rate_allow: Vec<IpAddr>,
+    // Added to snapshot/thumbnail and SSE responses, a camera's own entries win
+    pub response_headers: BTreeMap<String, String>,
+    pub credentials: BTreeMap<String, CredentialSet>,
+    pub default_credentials: Option<String>,
+    pub default_user: String,
+    pub default_pass: String,
+    pub cameras: Vec<CameraConfig>,
+    // (setting, displayed value, source) for the debug summary
+    pub sources: Vec<(String, String, Source)>,
+}
+
+fn default_width() -> u32 {
+    640
+}
+
+fn default_height() -> u32 {
+    360
+}
+
+fn default_jpeg_quality() -> u32 {
+    70
+}
+
+fn default_static_threshold() -> f64 {
+    0.02
+}
+
+fn default_snapshot_cooldown_secs() -> u64 {
+    30
+}
+
+fn default_snapshot_burst() -> u32 {
+    1
+}
+
+fn default_tamper_threshold() -> f64 {
+    0.25
+}
+
+fn default_tamper_secs() -> u64 {
+    10
+}
+
+fn default_reconnect_delay_secs() -> f64 {
+    5.0
+}
+
+fn default_reconnect_max_delay_secs() -> f64 {
+    300.0
+}
+
+fn default_reconnect_multiplier() -> f64 {
+    1.0
+}
+
+impl CameraConfig {
+    fn from_env(name: String, url: String) -> Self {
+        CameraConfig {
+            name,
+            display_name: None,
+            tags: Vec::new(),
+            url,
+            source: None,
+            mode: CameraMode::default(),
+            user: None,
+            pass: None,
+            credentials: None,
+            width: default_width(),
+            height: default_height(),
+            crop: None,
+            scale_mode: ScaleMode::default(),
+            jpeg_quality: default_jpeg_quality(),
+            quality_ladder: Vec::new(),
+            roi_max_clients: 0,
+            rotation: 0,
+            rotation_mode: RotationMode::default(),
+            preview_fps: None,
+            preview_on_demand: false,
+            priority: 0,
+            sync: None,
+            interpolate_frames: false,
+            substream_url: None,
+            restream_url: None,
+            rtsp_relay: false,
+            decoder: DecodeMode::default(),
+            codec_change: CodecChange::default(),
+            capture_timestamps: false,
+            timestamp_fps: None,
+            motion_detection: false,
+            skip_static_frames: false,
+            skip_duplicate_frames: false,
+            static_threshold: default_static_threshold(),
+            drop_corrupt_frames: false,
+            snapshot_on_motion: false,
+            snapshot_cooldown_secs: default_snapshot_cooldown_secs(),
+            snapshot_burst: default_snapshot_burst(),
+            tamper_detection: false,
+            tamper_threshold: default_tamper_threshold(),
+            tamper_secs: default_tamper_secs(),
+            onvif_url: None,
+            snapshot_uri: None,
+            response_headers: BTreeMap::new(),
+            socks_proxy: None,
+            multicast_iface: None,
+            local_address: None,
+            max_connections: None,
+            reconnect: ReconnectPolicy::default(),
+            record: false,
+            recording_codec: RecordingCodec::default(),
+            recording_container: RecordingContainer::default(),
+            recording_bitrate: None,
+            recording_crf: None,
+            keyframe_interval: None,
+            recording_sync: false,
+            recording_text_track: false,
+            recording_indicator: false,
+            recording_schedule: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        serde_yaml::from_str(&contents).with_context(|| format!("parsing config {}", path.display()))
+    }
+}
+
+// test:<resolution>@<fps> for --bench, checked like any other source by validate()
+pub fn bench_source(cli: &Cli) -> String {
+    format!("test:{}@{}", cli.bench_resolution.as_deref().unwrap_or("1280x720"), cli.bench_fps.unwrap_or(15))
+}
+
+// Every CCTV_* variable except the CCTV_CRED_* ones is a camera URL
+fn cameras_from_env() -> Vec<CameraConfig> {
+    let mut cameras: Vec<CameraConfig> = env::vars()
+        .filter(|(key, _)| key.starts_with("CCTV_") && !key.starts_with("CCTV_CRED_"))
+        .map(|(key, value)| CameraConfig::from_env(key, value))
+        .collect();
+    cameras.sort_by(|a, b| a.name.cmp(&b.name));
+    cameras
+}
+
+#[derive(Default)]
+struct Resolver {
+    sources: Vec<(String, String, Source)>,
+}
+
+impl Resolver {
+    // Pick the highest-precedence value that is set, recording where it came from
+    fn layer<T>(&mut self, name: &str, cli: Option<T>, env_key: &str, file: Option<T>) -> Result<Option<T>>
+    where
+        T: FromStr + Debug,
+        T::Err: Display,
+    {
+        let (value, source) = match (cli, env::var(env_key).ok(), file) {
+            (Some(value), _, _) => (Some(value), Source::Cli),
+            (None, Some(raw), _) => {
+                let value = raw.parse().map_err(|err| anyhow!("{}={:?}: {}", env_key, raw, err))?;
+                (Some(value), Source::Env)
+            }
+            (None, None, Some(value)) => (Some(value), Source::File),
+            (None, None, None) => (None, Source::Default),
+        };
+
+        let shown = value.as_ref().map(|value| format!("{:?}", value)).unwrap_or_else(|| "none".to_string());
+        self.sources.push((name.to_string(), shown, source));
+        Ok(value)
+    }
+
+    fn layer_or<T>(&mut self, name: &str, cli: Option<T>, env_key: &str, file: Option<T>, default: T) -> Result<T>
+    where
+        T: FromStr + Debug,
+        T::Err: Display,
+    {
+        let value = self.layer(name, cli, env_key, file)?;
+        if value.is_none() {
+            self.sources.last_mut().unwrap().1 = format!("{:?}", default);
+        }
+        Ok(value.unwrap_or(default))
+    }
+
+    // Lists are comma-separated in env vars
+    fn layer_list<T>(&mut self, name: &str, cli: Option<Vec<T>>, env_key: &str, file: Option<Vec<T>>) -> Result<Vec<T>>
+    where
+        T: FromStr + Debug,
+        T::Err: Display,
+    {
+        let (value, source) = match (cli, env::var(env_key).ok(), file) {
+            (Some(value), _, _) => (value, Source::Cli),
+            (None, Some(raw), _) => {
+                let value = raw
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| item.parse().map_err(|err| anyhow!("{}={:?}: {}", env_key, raw, err)))
+                    .collect::<Result<Vec<T>>>()?;
+                (value, Source::Env)
+            }
+            (None, None, Some(value)) => (value, Source::File),
+            (None, None, None) => (Vec::new(), Source::Default),
+        };
+
+        self.sources.push((name.to_string(), format!("{:?}", value), source));
+        Ok(value)
+    }
+
+    // Credentials only come from the environment and are never echoed
+    fn secret(&mut self, name: &str, env_key: &str, default: &str) -> String {
+        let (value, source) = match env::var(env_key) {
+            Ok(value) => (value, Source::Env),
+            Err(_) => (default.to_string(), Source::Default),
+        };
+        self.sources.push((name.to_string(), "<hidden>".to_string(), source));
+        value
+    }
+}
+
+// Build the effective settings from the CLI, environment, config file and defaults
+pub fn resolve_config(cli: &Cli) -> Result<Settings> {
+    let file = match &cli.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+    let server = file.server;
+    let mut resolver = Resolver::default();
+
+    let debug = cli.debug || env::var("NVR_DEBUG").is_ok_and(|v| v == "1" || v == "true");
+    let port = resolver.layer_or("port", cli.port, "NVR_PORT", server.port, 3030)?;
+    let bind_address = resolver.layer_or(
+        "bind_address",
+        cli.bind_address,
+        "NVR_BIND_ADDRESS",
+        server.bind_address,
+        IpAddr::from([0, 0, 0, 0]),
+    )?;
+    let stream_address = resolver.layer("stream_address", cli.stream_address, "NVR_STREAM_ADDRESS", server.stream_address)?;
+    let rtsp_relay_address = resolver.layer_or(
+        "rtsp_relay_address",
+        cli.rtsp_relay_address,
+        "NVR_RTSP_RELAY_ADDRESS",
+        server.rtsp_relay_address,
+        SocketAddr::from(([0, 0, 0, 0], 8554)),
+    )?;
+    let base_path = resolver.layer_or("base_path", cli.base_path.clone(), "NVR_BASE_PATH", server.base_path, String::new())?;
+    let base_path = normalize_base_path(&base_path);
+    let server_id = resolver.layer_or("server_id", cli.server_id.clone(), "NVR_SERVER_ID", server.server_id, hostname())?;
+    let max_streams = resolver.layer_or("max_streams", cli.max_streams, "NVR_MAX_STREAMS", server.max_streams, 64)?;
+    let frame_queue_size = resolver.layer_or(
+        "frame_queue_size",
+        cli.frame_queue_size,
+        "NVR_FRAME_QUEUE_SIZE",
+        server.frame_queue_size,
+        4,
+    )?;
+    let broadcast_threads = resolver.layer_or(
+        "broadcast_threads",
+        cli.broadcast_threads,
+        "NVR_BROADCAST_THREADS",
+        server.broadcast_threads,
+        0,
+    )?;
+    let debug_frames = resolver.layer_or("debug_frames", cli.debug_frames, "NVR_DEBUG_FRAMES", server.debug_frames, 0)?;
+    let debug_dir = resolver.layer_or(
+        "debug_dir",
+        cli.debug_dir.clone(),
+        "NVR_DEBUG_DIR",
+        server.debug_dir,
+        PathBuf::from("debug"),
+    )?;
+    let recordings_dir = resolver.layer_or(
+        "recordings_dir",
+        cli.recordings_dir.clone(),
+        "NVR_RECORDINGS_DIR",
+        server.recordings_dir,
+        PathBuf::from("recordings"),
+    )?;
+    let hls_dir = resolver.layer_or("hls_dir", cli.hls_dir.clone(), "NVR_HLS_DIR", server.hls_dir, PathBuf::from("hls"))?;
+    let page_dir = resolver.layer_or("page_dir", cli.page_dir.clone(), "NVR_PAGE_DIR", server.page_dir, PathBuf::from("page"))?;
+    let log_client_ips = resolver.layer_or(
+        "log_client_ips",
+        cli.log_client_ips,
+        "NVR_LOG_CLIENT_IPS",
+        server.log_client_ips,
+        true,
+    )?;
+    let no_signal_frames = resolver.layer_or(
+        "no_signal_frames",
+        cli.no_signal_frames,
+        "NVR_NO_SIGNAL_FRAMES",
+        server.no_signal_frames,
+        false,
+    )?;
+    let reconnect_grace_secs = resolver.layer_or(
+        "reconnect_grace_secs",
+        cli.reconnect_grace_secs,
+        "NVR_RECONNECT_GRACE_SECS",
+        server.reconnect_grace_secs,
+        10,
+    )?;
+    let cameras_per_page = resolver.layer_or("cameras_per_page", cli.cameras_per_page, "NVR_CAMERAS_PER_PAGE", server.cameras_per_page, 0)?;
+    let prefer_mse = resolver.layer_or("prefer_mse", cli.prefer_mse, "NVR_PREFER_MSE", server.prefer_mse, false)?;
+    let pipeline_stats = resolver.layer_or("pipeline_stats", cli.pipeline_stats, "NVR_PIPELINE_STATS", server.pipeline_stats, false)?;
+    let drop_log_interval_secs = resolver.layer_or(
+        "drop_log_interval_secs",
+        cli.drop_log_interval_secs,
+        "NVR_DROP_LOG_INTERVAL_SECS",
+        server.drop_log_interval_secs,
+        10,
+    )?;
+    let ws_heartbeat_secs = resolver.layer_or(
+        "ws_heartbeat_secs",
+        cli.ws_heartbeat_secs,
+        "NVR_WS_HEARTBEAT_SECS",
+        server.ws_heartbeat_secs,
+        20,
+    )?;
+    let ws_max_send_buffer_kb = resolver.layer_or(
+        "ws_max_send_buffer_kb",
+        cli.ws_max_send_buffer_kb,
+        "NVR_WS_MAX_SEND_BUFFER_KB",
+        server.ws_max_send_buffer_kb,
+        4096,
+    )?;
+    let seek_buffer_secs = resolver.layer_or("seek_buffer_secs", cli.seek_buffer_secs, "NVR_SEEK_BUFFER_SECS", server.seek_buffer_secs, 10)?;
+    let event_history = resolver.layer_or("event_history", cli.event_history, "NVR_EVENT_HISTORY", server.event_history, 100)?;
+    let pipeline_start_timeout_secs = resolver.layer_or(
+        "pipeline_start_timeout_secs",
+        cli.pipeline_start_timeout_secs,
+        "NVR_PIPELINE_START_TIMEOUT_SECS",
+        server.pipeline_start_timeout_secs,
+        20,
+    )?;
+    let require_streams = resolver.layer_or(
+        "require_streams",
+        cli.require_streams,
+        "NVR_REQUIRE_STREAMS",
+        server.require_streams,
+        RequireStreams::default(),
+    )?;
+    let require_streams_secs = resolver.layer_or(
+        "require_streams_secs",
+        cli.require_streams_secs,
+        "NVR_REQUIRE_STREAMS_SECS",
+        server.require_streams_secs,
+        60,
+    )?;
+    let min_free_disk_mb = resolver.layer_or("min_free_disk_mb", cli.min_free_disk_mb, "NVR_MIN_FREE_DISK_MB", server.min_free_disk_mb, 1024)?;
+    let disk_full_action = resolver.layer_or(
+        "disk_full_action",
+        cli.disk_full_action,
+        "NVR_DISK_FULL_ACTION",
+        server.disk_full_action,
+        DiskFullAction::default(),
+    )?;
+    let load_shed_cpu = resolver.layer_or("load_shed_cpu", cli.load_shed_cpu, "NVR_LOAD_SHED_CPU", server.load_shed_cpu, 90)?;
+    let load_shed_quality = resolver.layer_or(
+        "load_shed_quality",
+        cli.load_shed_quality,
+        "NVR_LOAD_SHED_QUALITY",
+        server.load_shed_quality,
+        40,
+    )?;
+    let load_shed_fps = resolver.layer_or("load_shed_fps", cli.load_shed_fps, "NVR_LOAD_SHED_FPS", server.load_shed_fps, 0)?;
+    let qos_stats = resolver.layer_or("qos_stats", cli.qos_stats, "NVR_QOS_STATS", server.qos_stats, false)?;
+    let probe_hw_decoders = resolver.layer_or("probe_hw_decoders", cli.probe_hw_decoders, "NVR_PROBE_HW_DECODERS", server.probe_hw_decoders, true)?;
+    let access_log = resolver.layer("access_log", cli.access_log.clone(), "NVR_ACCESS_LOG", server.access_log)?;
+    let audit_log = resolver.layer("audit_log", cli.audit_log.clone(), "NVR_AUDIT_LOG", server.audit_log)?;
+    let audit_log_max_mb = resolver.layer_or("audit_log_max_mb", cli.audit_log_max_mb, "NVR_AUDIT_LOG_MAX_MB", server.audit_log_max_mb, 10)?;
+    let audit_log_keep = resolver.layer_or("audit_log_keep", cli.audit_log_keep, "NVR_AUDIT_LOG_KEEP", server.audit_log_keep, 5)?;
+    let influx_url = resolver.layer("influx_url", cli.influx_url.clone(), "NVR_INFLUX_URL", server.influx_url)?;
+    let influx_token = Some(resolver.secret("influx_token", "NVR_INFLUX_TOKEN", "")).filter(|token| !token.is_empty());
+    let influx_interval_secs = resolver.layer_or(
+        "influx_interval_secs",
+        cli.influx_interval_secs,
+        "NVR_INFLUX_INTERVAL_SECS",
+        server.influx_interval_secs,
+        10,
+    )?;
+    let influx_measurement = resolver.layer_or(
+        "influx_measurement",
+        cli.influx_measurement.clone(),
+        "NVR_INFLUX_MEASUREMENT",
+        server.influx_measurement,
+        "nvr_stream".to_string(),
+    )?;
+    let influx_tags = resolver.layer("influx_tags", cli.influx_tags.clone(), "NVR_INFLUX_TAGS", server.influx_tags)?;
+    let ws_rate_limit = resolver.layer_or("ws_rate_limit", cli.ws_rate_limit, "NVR_WS_RATE_LIMIT", server.ws_rate_limit, 5.0)?;
+    let ws_rate_burst = resolver.layer_or("ws_rate_burst", cli.ws_rate_burst, "NVR_WS_RATE_BURST", server.ws_rate_burst, 50)?;
+    let ws_rate_allow = resolver.layer_list("ws_rate_allow", cli.ws_rate_allow.clone(), "NVR_WS_RATE_ALLOW", server.ws_rate_allow)?;
+    let default_credentials = resolver.layer(
+        "default_credentials",
+        cli.default_credentials.clone(),
+        "NVR_DEFAULT_CREDENTIALS",
+        server.default_credentials,
+    )?;
+    let default_user = resolver.secret("default_user", "CCTV_CRED_USER", "admin");
+    let default_pass = resolver.secret("default_pass", "CCTV_CRED_PASS", "aaaa1111");
+
+    // Cameras: config file first, then CCTV_* env vars on top
+    let mut cameras = file.cameras;
+    let mut camera_sources: Vec<Source> = vec![Source::File; cameras.len()];
+    for env_camera in cameras_from_env() {
+        match cameras.iter().position(|c| c.name.eq_ignore_ascii_case(&env_camera.name)) {
+            Some(i) => {
+                cameras[i].url = env_camera.url;
+                camera_sources[i] = Source::Env;
+            }
+            None => {
+                cameras.push(env_camera);
+                camera_sources.push(Source::Env);
+            }
+        }
+    }
+    #[cfg(feature = "fake-source")]
+    for index in 0..cli.fake_cameras.unwrap_or(0) {
+        cameras.push(CameraConfig::from_env(crate::fake_source::camera_name(index), crate::fake_source::camera_url(index, 0)));
+        camera_sources.push(Source::Cli);
+    }
+    // The benchmark's synthetic cameras replace the configured ones
+    if let Some(count) = cli.bench {
+        let source = bench_source(cli);
+        cameras = (0..count)
+            .map(|index| CameraConfig { source: Some(source.clone()), ..CameraConfig::from_env(format!("bench{}", index), String::new()) })
+            .collect();
+        camera_sources = vec![Source::Cli; count];
+    }
+    for (camera, source) in cameras.iter().zip(camera_sources) {
+        let shown = camera.source.clone().unwrap_or_else(|| camera.url.clone());
+        resolver.sources.push((format!("camera {}", camera.name), shown, source));
+    }
+
+    Ok(Settings {
+        debug,
+        port,
+        bind_address,
+        stream_address,
+        rtsp_relay_address,
+        base_path,
+        server_id,
+        max_streams,
+        frame_queue_size,
+        broadcast_threads,
+        debug_frames,
+        debug_dir,
+        recordings_dir,
+        hls_dir,
+        page_dir,
+        log_client_ips,
+        no_signal_frames,
+        reconnect_grace_secs,
+        cameras_per_page,
+        prefer_mse,
+        pipeline_stats,
+        drop_log_interval_secs,
+        ws_heartbeat_secs,
+        ws_max_send_buffer_kb,
+        seek_buffer_secs,
+        event_history,
+        pipeline_start_timeout_secs,
+        require_streams,
+        require_streams_secs,
+        min_free_disk_mb,
+        disk_full_action,
+        load_shed_cpu,
+        load_shed_quality,
+        load_shed_fps,
+        qos_stats,
+        probe_hw_decoders,
+        access_log,
+        audit_log,
+        audit_log_max_mb,
+        audit_log_keep,
+        influx_url,
+        influx_token,
+        influx_interval_secs,
+        influx_measurement,
+        influx_tags,
+        ws_rate_limit,
+        ws_rate_burst,
+        ws_rate_allow,
+        response_headers: server.response_headers.unwrap_or_default(),
+        credentials: file.credentials,
+        default_credentials,
+        default_user,
+        default_pass,
+        cameras,
+        sources: resolver.sources,
+    })
+}
+
+impl Settings {
+    pub fn print_sources(&self) {
+        println!("Effective settings:");
+        for (name, value, source) in &self.sources {
+            println!("  {} = {} ({:?})", name, value, source);
+        }
+    }
+
+    // Inline user/pass win, then the camera's named credential set, then the default set,
+    // then CCTV_CRED_USER / CCTV_CRED_PASS
+    pub fn camera_credentials(&self, camera: &CameraConfig) -> (String, String) {
+        let set = camera
+            .credentials
+            .as_ref()
+            .or(self.default_credentials.as_ref())
+            .and_then(|name| self.credentials.get(name));
+        let user = camera.user.clone()
+            .or_else(|| set.map(|set| set.user.clone()))
+            .unwrap_or_else(|| self.default_user.clone());
+        let pass = camera.pass.clone()
+            .or_else(|| set.map(|set| set.pass.clone()))
+            .unwrap_or_else(|| self.default_pass.clone());
+        (user, pass)
+    }
+
+    // Ballpark CPU cores all streams need with software decoding. The real cost depends on
+    // the cameras' codec, resolution and framerate, which aren't known until they connect.
+    pub fn estimated_cores(&self) -> f64 {
+        self.cameras
+            .iter()
+            .map(|camera| {
+                let megapixels = camera.width as f64 * camera.height as f64 / 1_000_000.0;
+                let restream = [camera.restream_url.is_some(), camera.rtsp_relay].iter().filter(|on| **on).count() as f64 * RESTREAM_CORES;
+                let pipelines = if camera.substream_url.is_some() { 2.0 } else { 1.0 };
+                // Every zoom slot in use, each another preview-sized encode
+                let roi = camera.roi_max_clients as f64 * megapixels * JPEG_CORES_PER_MEGAPIXEL;
+                pipelines * (DECODE_CORES_PER_STREAM + megapixels * JPEG_CORES_PER_MEGAPIXEL) + restream + roi
+            })
+            .sum()
+    }
+
+    // Hosts that more pipelines connect to than the camera is likely to accept. Entries
+    // sharing a url are fine otherwise, every pipeline has its own RTSP session.
+    pub fn connection_warnings(&self) -> Vec<String> {
+        let mut hosts: BTreeMap<String, (Vec<String>, Option<u32>)> = BTreeMap::new();
+        for camera in &self.cameras {
+            let mut pipelines = vec![(&camera.url, camera.name.clone())];
+            if let Some(substream_url) = &camera.substream_url {
+                pipelines.push((substream_url, format!("{}@sub", camera.name)));
+            }
+            for (url, pipeline) in pipelines {
+                let Some(host) = camera_host(url) else { continue };
+                let (names, limit) = hosts.entry(host).or_default();
+                names.push(pipeline);
+                *limit = match (*limit, camera.max_connections) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+            }
+        }
+
+        hosts
+            .into_iter()
+            .filter_map(|(host, (names, limit))| {
+                let limit = limit.unwrap_or(DEFAULT_CAMERA_CONNECTIONS);
+                (names.len() > limit as usize).then(|| format!(
+                    "{} connections to {} ({}), the camera may only accept {}. Set max_connections if it allows more.",
+                    names.len(), host, names.join(", "), limit
+                ))
+            })
+            .collect()
+    }
+
+    // Collect every problem rather than stopping at the first one
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut names = HashSet::new();
+
+        if self.port == 0 {
+            problems.push("port must be non-zero".to_string());
+        }
+        if let Some(stream_address) = self.stream_address {
+            if stream_address.port() == 0 || stream_address.port() == self.port {
+                problems.push(format!("stream_address {} needs a port of its own, not 0 or port {}", stream_address, self.port));
+            }
+        }
+        if self.cameras.iter().any(|camera| camera.rtsp_relay) {
+            if !cfg!(feature = "rtsp-relay") {
+                problems.push("rtsp_relay is set but this build lacks the rtsp-relay feature".to_string());
+            }
+            let port = self.rtsp_relay_address.port();
+            if port == 0 || port == self.port || self.stream_address.is_some_and(|address| address.port() == port) {
+                problems.push(format!("rtsp_relay_address {} needs a port of its own", self.rtsp_relay_address));
+            }
+        }
+        if self.base_path.split('/').any(|segment| segment == "." || segment == "..") {
+            problems.push(format!("base_path {:?} must not contain '.' or '..' segments", self.base_path));
+        }
+        if self.server_id.trim().is_empty() {
+            problems.push("server_id must not be empty".to_string());
+        }
+        if self.base_path.contains(['?', '#', '\\', '\'', '"', ' ', '<', '>']) {
+            problems.push(format!("base_path {:?} contains characters that aren't allowed in a path", self.base_path));
+        }
+        // Guards against a misgenerated config launching hundreds of pipelines
+        if self.cameras.len() > self.max_streams {
+            problems.push(format!("{} cameras configured, more than max_streams {}", self.cameras.len(), self.max_streams));
+        }
+        if self.frame_queue_size == 0 {
+            problems.push("frame_queue_size must be at least 1".to_string());
+        }
+        if self.audit_log_max_mb == 0 {
+            problems.push("audit_log_max_mb must be at least 1".to_string());
+        }
+        if let Some(influx_url) = &self.influx_url {
+            if !influx_url.starts_with("http://") && !influx_url.starts_with("https://") {
+                problems.push(format!("influx_url {:?} must be an http:// or https:// URL", influx_url));
+            }
+            if self.influx_interval_secs == 0 {
+                problems.push("influx_interval_secs must be at least 1".to_string());
+            }
+            if let Err(problem) = crate::influx::check_measurement(&self.influx_measurement) {
+                problems.push(format!("influx_measurement: {}", problem));
+            }
+            if let Some(Err(problem)) = self.influx_tags.as_deref().map(crate::influx::parse_tags) {
+                problems.push(format!("influx_tags: {}", problem));
+            }
+        }
+        if self.load_shed_cpu > 100 {
+            problems.push(format!("load_shed_cpu {} is outside 0-100", self.load_shed_cpu));
+        }
+        if self.require_streams != RequireStreams::None && self.require_streams_secs == 0 {
+            problems.push("require_streams_secs must be above 0 when require_streams is set".to_string());
+        }
+        if self.load_shed_quality > 100 {
+            problems.push(format!("load_shed_quality {} is outside 0-100", self.load_shed_quality));
+        }
+        if self.ws_max_send_buffer_kb == 0 {
+            problems.push("ws_max_send_buffer_kb must be at least 1".to_string());
+        }
+        if self.drop_log_interval_secs == 0 {
+            problems.push("drop_log_interval_secs must be at least 1".to_string());
+        }
+        if self.seek_buffer_secs > MAX_SEEK_BUFFER_SECS {
+            problems.push(format!("seek_buffer_secs {} is more than {}", self.seek_buffer_secs, MAX_SEEK_BUFFER_SECS));
+        }
+        if self.event_history > MAX_EVENT_HISTORY {
+            problems.push(format!("event_history {} is more than {}", self.event_history, MAX_EVENT_HISTORY));
+        }
+        if self.ws_rate_limit.is_nan() || self.ws_rate_limit < 0.0 {
+            problems.push(format!("ws_rate_limit {} must be >= 0", self.ws_rate_limit));
+        }
+        for (header, value) in &self.response_headers {
+            if let Err(problem) = check_header(header, value) {
+                problems.push(format!("response_headers: {}", problem));
+            }
+        }
+        if let Some(set) = &self.default_credentials {
+            if !self.credentials.contains_key(set) {
+                problems.push(format!("default_credentials refers to unknown credential set {:?}", set));
+            }
+        }
+        for (set_name, set) in &self.credentials {
+            for (field, value) in [("user", &set.user), ("pass", &set.pass)] {
+                match placeholders(value) {
+                    Ok(vars) => {
+                        for var in vars.iter().filter(|var| env::var(var).is_err()) {
+                            problems.push(format!("credentials {}: {} references unset variable ${{{}}}", set_name, field, var));
+                        }
+                    }
+                    Err(problem) => problems.push(format!("credentials {}: {} {}", set_name, field, problem)),
+                }
+            }
+        }
+
+        for camera in &self.cameras {
+            let name = &camera.name;
+
+            if name.is_empty() {
+                problems.push(format!("camera with url {:?} has an empty name", camera.url));
+            } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                problems.push(format!("{}: name may only contain letters, digits, '_' and '-'", name));
+            }
+            if let Some(set) = &camera.credentials {
+                if !self.credentials.contains_key(set) {
+                    problems.push(format!("{}: credentials refers to unknown credential set {:?}", name, set));
+                }
+            }
+            if camera.display_name.as_ref().is_some_and(|display_name| display_name.trim().is_empty()) {
+                problems.push(format!("{}: display_name is empty", name));
+            }
+            if camera.tags.iter().any(|tag| tag.trim().is_empty()) {
+                problems.push(format!("{}: tags must not be empty", name));
+            }
+            // Stream lookups are case-insensitive, so FOO and foo would collide
+            if !names.insert(name.to_lowercase()) {
+                problems.push(format!("{}: duplicate camera name", name));
+            }
+
+            match &camera.source {
+                Some(source) => {
+                    if let Err(problem) = crate::ingest::Ingest::parse(source) {
+                        problems.push(format!("{}: {}", name, problem));
+                    }
+                    if !camera.url.is_empty() {
+                        problems.push(format!("{}: set either url or source, not both", name));
+                    }
+                    if camera.substream_url.is_some() || camera.socks_proxy.is_some() || camera.local_address.is_some() {
+                        problems.push(format!("{}: substream_url, socks_proxy and local_address need a camera url, not a source", name));
+                    }
+                    if camera.multicast_iface.is_some() {
+                        problems.push(format!("{}: multicast_iface needs a camera url, not a source", name));
+                    }
+                    if camera.decoder != DecodeMode::Auto {
+                        problems.push(format!("{}: decoder only applies to a camera url, sources are always JPEG", name));
+                    }
+                }
+                None => {
+                    if let Err(problem) = check_url(&camera.url) {
+                        problems.push(format!("{}: {}", name, problem));
+                    }
+                }
+            }
+            if let Some(substream_url) = &camera.substream_url {
+                if let Err(problem) = check_url(substream_url) {
+                    problems.push(format!("{}: substream_url: {}", name, problem));
+                }
+            }
+
+            if camera.width == 0 || camera.height == 0 {
+                problems.push(format!("{}: resolution {}x{} must be non-zero", name, camera.width, camera.height));
+            } else if camera.width % 2 != 0 || camera.height % 2 != 0 {
+                problems.push(format!("{}: resolution {}x{} must have even dimensions", name, camera.width, camera.height));
+            }
+
+            // Whether it fits the source is only known once the camera's caps are, see crop::attach
+            if let Some(crop) = camera.crop {
+                if crop.width == 0 || crop.height == 0 {
+                    problems.push(format!("{}: crop {}x{} must be non-zero", name, crop.width, crop.height));
+                } else if crop.x as u64 + crop.width as u64 > i32::MAX as u64 || crop.y as u64 + crop.height as u64 > i32::MAX as u64 {
+                    problems.push(format!("{}: crop reaches past the largest possible frame", name));
+                }
+                if camera.snapshot_uri.is_some() {
+                    problems.push(format!("{}: snapshot_uri can't be used with crop, the camera's still would be uncropped", name));
+                }
+            }
+
+            for (header, value) in &camera.response_headers {
+                if let Err(problem) = check_header(header, value) {
+                    problems.push(format!("{}: response_headers: {}", name, problem));
+                }
+            }
+
+            if camera.jpeg_quality > 100 {
+                problems.push(format!("{}: jpeg_quality {} is outside 0-100", name, camera.jpeg_quality));
+            }
+            for (index, step) in camera.quality_ladder.iter().enumerate() {
+                if step.quality > 100 {
+                    problems.push(format!("{}: quality_ladder quality {} is outside 0-100", name, step.quality));
+                }
+                if step.viewers == 0 {
+                    problems.push(format!("{}: quality_ladder viewers must be at least 1", name));
+                }
+                if index > 0 && step.viewers <= camera.quality_ladder[index - 1].viewers {
+                    problems.push(format!("{}: quality_ladder steps must be in order of increasing viewers", name));
+                }
+            }
+            if ![0, 90, 180, 270].contains(&camera.rotation) {
+                problems.push(format!("{}: rotation {} must be 0, 90, 180 or 270", name, camera.rotation));
+            }
+            if camera.preview_fps == Some(0) {
+                problems.push(format!("{}: preview_fps must be at least 1", name));
+            }
+            if let Some(fps) = camera.timestamp_fps {
+                if !(fps > 0.0 && fps <= 240.0) {
+                    problems.push(format!("{}: timestamp_fps {} is outside (0, 240]", name, fps));
+                } else if camera.preview_fps.is_some_and(|cap| fps > cap as f64) {
+                    problems.push(format!("{}: timestamp_fps {} is above preview_fps, frames never come that often", name, fps));
+                }
+            }
+
+            if let Some(restream_url) = &camera.restream_url {
+                if !restream_url.starts_with("rtmp://") && !restream_url.starts_with("rtmps://") {
+                    problems.push(format!("{}: restream_url {:?} must start with rtmp:// or rtmps://", name, restream_url));
+                }
+            }
+
+            if let Some(onvif_url) = &camera.onvif_url {
+                if !onvif_url.starts_with("http://") && !onvif_url.starts_with("https://") {
+                    problems.push(format!("{}: onvif_url {:?} must start with http:// or https://", name, onvif_url));
+                }
+            }
+
+            if let Some(snapshot_uri) = &camera.snapshot_uri {
+                if !snapshot_uri.starts_with("http://") && !snapshot_uri.starts_with("https://") {
+                    problems.push(format!("{}: snapshot_uri {:?} must start with http:// or https://", name, snapshot_uri));
+                }
+            }
+
+            if camera.mode == CameraMode::Audio {
+                let video_only = [
+                    ("source", camera.source.is_some()),
+                    ("substream_url", camera.substream_url.is_some()),
+                    ("restream_url", camera.restream_url.is_some()),
+                    ("rtsp_relay", camera.rtsp_relay),
+                    ("record", camera.record),
+                    ("motion_detection", camera.motion_detection),
+                    ("skip_static_frames", camera.skip_static_frames),
+                    ("skip_duplicate_frames", camera.skip_duplicate_frames),
+                    ("snapshot_on_motion", camera.snapshot_on_motion),
+                    ("drop_corrupt_frames", camera.drop_corrupt_frames),
+                    ("tamper_detection", camera.tamper_detection),
+                    ("interpolate_frames", camera.interpolate_frames),
+                    ("crop", camera.crop.is_some()),
+                    ("priority", camera.priority != 0),
+                    ("quality_ladder", !camera.quality_ladder.is_empty()),
+                    ("roi_max_clients", camera.roi_max_clients > 0),
+                    ("preview_on_demand", camera.preview_on_demand),
+                    ("decoder", camera.decoder != DecodeMode::Auto),
+                    ("codec_change", camera.codec_change != CodecChange::Rebuild),
+                    ("timestamp_fps", camera.timestamp_fps.is_some()),
+                ];
+                for (field, set) in video_only {
+                    if set {
+                        problems.push(format!("{}: {} doesn't apply to mode: audio", name, field));
+                    }
+                }
+            }
+
+            if camera.codec_change != CodecChange::Rebuild && camera.decoder == DecodeMode::Decodebin {
+                problems.push(format!("{}: codec_change doesn't apply to decoder: decodebin", name));
+            }
+
+            if camera.max_connections == Some(0) {
+                problems.push(format!("{}: max_connections must be at least 1", name));
+            }
+
+            let reconnect = camera.reconnect;
+            if !(reconnect.initial_delay_secs > 0.0 && reconnect.initial_delay_secs.is_finite()) {
+                problems.push(format!("{}: reconnect.initial_delay_secs must be above 0", name));
+            } else if !(reconnect.max_delay_secs >= reconnect.initial_delay_secs && reconnect.max_delay_secs.is_finite()) {
+                problems.push(format!("{}: reconnect.max_delay_secs must be at least initial_delay_secs", name));
+            }
+            if !(reconnect.multiplier >= 1.0 && reconnect.multiplier.is_finite()) {
+                problems.push(format!("{}: reconnect.multiplier must be at least 1", name));
+            }
+
+            if let Some(socks_proxy) = &camera.socks_proxy {
+                if let Err(problem) = crate::socks::check_proxy(socks_proxy) {
+                    problems.push(format!("{}: {}", name, problem));
+                }
+                if camera.source.is_none() && !camera.url.starts_with("rtsp://") {
+                    problems.push(format!("{}: socks_proxy only works with rtsp:// urls", name));
+                }
+            }
+
+            if let Some(iface) = &camera.multicast_iface {
+                if let Err(problem) = check_interface(iface) {
+                    problems.push(format!("{}: {}", name, problem));
+                }
+                if camera.socks_proxy.is_some() || camera.local_address.is_some() {
+                    problems.push(format!("{}: multicast_iface doesn't apply with socks_proxy or local_address, both force RTSP over TCP", name));
+                }
+            }
+
+            if let Some(local_address) = camera.local_address {
+                if camera.socks_proxy.is_some() {
+                    problems.push(format!("{}: set either socks_proxy or local_address, not both", name));
+                }
+                if camera.source.is_none() && !camera.url.starts_with("rtsp://") {
+                    problems.push(format!("{}: local_address only works with rtsp:// urls", name));
+                }
+                // Binding fails unless the address belongs to one of this host's interfaces
+                if std::net::TcpListener::bind((local_address, 0)).is_err() {
+                    problems.push(format!("{}: local_address {} isn't an address of this host", name, local_address));
+                }
+            }
+
+            if camera.recording_sync && !camera.record {
+                problems.push(format!("{}: recording_sync is set but record is off", name));
+            }
+            if camera.recording_text_track && !camera.record {
+                problems.push(format!("{}: recording_text_track is set but record is off", name));
+            }
+            if camera.recording_indicator && !camera.record {
+                problems.push(format!("{}: recording_indicator is set but record is off", name));
+            }
+
+            // These look at every preview frame, which an idle on-demand preview doesn't make
+            if camera.preview_on_demand {
+                let per_frame = [
+                    ("motion_detection", camera.motion_detection),
+                    ("skip_static_frames", camera.skip_static_frames),
+                    ("snapshot_on_motion", camera.snapshot_on_motion),
+                    ("tamper_detection", camera.tamper_detection),
+                ];
+                for (field, set) in per_frame {
+                    if set {
+                        problems.push(format!("{}: {} can't be used with preview_on_demand", name, field));
+                    }
+                }
+            }
+
+            if let Some(schedule) = &camera.recording_schedule {
+                if !camera.record {
+                    problems.push(format!("{}: recording_schedule is set but record is off", name));
+                }
+                if let Err(problem) = crate::schedule::Schedule::compile(schedule) {
+                    problems.push(format!("{}: {}", name, problem));
+                }
+            }
+
+            if camera.recording_bitrate == Some(0) {
+                problems.push(format!("{}: recording_bitrate must be non-zero", name));
+            }
+            if camera.recording_crf.is_some_and(|crf| crf > 51) {
+                problems.push(format!("{}: recording_crf {} is outside 0-51", name, camera.recording_crf.unwrap()));
+            }
+            if camera.recording_bitrate.is_some() && camera.recording_crf.is_some() {
+                problems.push(format!("{}: set either recording_bitrate or recording_crf, not both", name));
+            }
+            if let Some(interval) = &camera.keyframe_interval {
+                if !camera.record {
+                    problems.push(format!("{}: keyframe_interval is set but record is off", name));
+                }
+                if let Err(problem) = crate::recorder::check_keyframe_interval(interval) {
+                    problems.push(format!("{}: {}", name, problem));
+                }
+            }
+
+            if !(camera.static_threshold > 0.0 && camera.static_threshold <= 1.0) {
+                problems.push(format!("{}: static_threshold {} is outside (0, 1]", name, camera.static_threshold));
+            }
+            if !(camera.tamper_threshold > 0.0 && camera.tamper_threshold <= 1.0) {
+                problems.push(format!("{}: tamper_threshold {} is outside (0, 1]", name, camera.tamper_threshold));
+            }
+            let max_burst = crate::motion_snapshots::MAX_SNAPSHOT_BURST;
+            if camera.snapshot_burst == 0 || camera.snapshot_burst > max_burst {
+                problems.push(format!("{}: snapshot_burst {} is outside 1-{}", name, camera.snapshot_burst, max_burst));
+            }
+            if camera.tamper_secs == 0 {
+                problems.push(format!("{}: tamper_secs must be at least 1", name));
+            }
+
+            let templated = [
+                ("url", Some(&camera.url)),
+                ("user", camera.user.as_ref()),
+                ("pass", camera.pass.as_ref()),
+                ("substream_url", camera.substream_url.as_ref()),
+                ("restream_url", camera.restream_url.as_ref()),
+                ("onvif_url", camera.onvif_url.as_ref()),
+                ("snapshot_uri", camera.snapshot_uri.as_ref()),
+                ("socks_proxy", camera.socks_proxy.as_ref()),
+            ];
+            for (field, value) in templated {
+                let Some(value) = value else { continue };
+                match placeholders(value) {
+                    Ok(vars) => {
+                        for var in vars.iter().filter(|var| env::var(var).is_err()) {
+                            problems.push(format!("{}: {} references unset variable ${{{}}}", name, field, var));
+                        }
+                    }
+                    Err(problem) => problems.push(format!("{}: {} {}", name, field, problem)),
+                }
+            }
+        }
+
+        problems
+    }
+
+    // Substitute ${VAR} placeholders in urls and credentials from the environment
+    pub fn expand_env(mut self) -> Result<Settings> {
+        for set in self.credentials.values_mut() {
+            set.user = expand(&set.user)?;
+            set.pass = expand(&set.pass)?;
+        }
+        for camera in &mut self.cameras {
+            camera.url = expand(&camera.url)?;
+            for value in [&mut camera.user, &mut camera.pass, &mut camera.substream_url, &mut camera.restream_url, &mut camera.onvif_url, &mut camera.snapshot_uri, &mut camera.socks_proxy] {
+                if let Some(value) = value {
+                    *value = expand(value)?;
+                }
+            }
+        }
+        Ok(self)
+    }
+}
+
+// Default server_id, so instances are told apart without any configuration
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "rtspstream".to_string())
+}
+
+// "", "/" -> ""; "nvr/", "//nvr//cams" -> "/nvr", "/nvr/cams"
+fn normalize_base_path(path: &str) -> String {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("/{}", segment))
+        .collect()
+}
+
+fn check_url(url: &str) -> std::result::Result<(), String> {
+    let rest = url
+        .strip_prefix("rtsp://")
+        .or_else(|| url.strip_prefix("rtsps://"))
+        .ok_or_else(|| format!("url {:?} must start with rtsp:// or rtsps://", url))?;
+
+    let authority = rest.split('/').next().unwrap_or("");
+    let host = authority.rsplit('@').next().unwrap_or("");
+    if host.is_empty() || host.starts_with(':') {
+        return Err(format!("url {:?} has no host", url));
+    }
+    Ok(())
+}
+
+// Network interfaces are listed under /sys/class/net on Linux. Elsewhere the name can't
+// be checked up front and a wrong one shows up as a pipeline error instead.
+fn check_interface(iface: &str) -> std::result::Result<(), String> {
+    if iface.is_empty() || iface.contains(['/', ' ', '\0']) {
+        return Err(format!("multicast_iface {:?} isn't an interface name", iface));
+    }
+    let interfaces = Path::new("/sys/class/net");
+    if interfaces.is_dir() && !interfaces.join(iface).exists() {
+        return Err(format!("multicast_iface {:?} doesn't exist on this host", iface));
+    }
+    Ok(())
+}
+
+// Lowercased host:port of an rtsp(s) url, with the scheme's default port filled in
+fn camera_host(url: &str) -> Option<String> {
+    let (rest, default_port) = match url.strip_prefix("rtsp://") {
+        Some(rest) => (rest, 554),
+        None => (url.strip_prefix("rtsps://")?, 322),
+    };
+    let authority = rest.split('/').next()?;
+    let host_port = authority.rsplit('@').next()?.to_lowercase();
+    // A trailing :port, but not the inside of a bare IPv6 literal
+    let has_port = host_port.rsplit_once(':').is_some_and(|(host, port)| {
+        !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) && (!host.contains(':') || host.ends_with(']'))
+    });
+    match has_port {
+        true => Some(host_port),
+        false => Some(format!("{}:{}", host_port, default_port)),
+    }
+}
+
+// Names of the ${VAR} placeholders in `value`, or an error for malformed ones
+fn placeholders(value: &str) -> std::result::Result<Vec<String>, String> {
+    let mut vars = Vec::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        let end = after.find('}').ok_or_else(|| format!("has an unterminated placeholder in {:?}", value))?;
+        let var = &after[..end];
+        if var.is_empty() || !var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("has an invalid placeholder ${{{}}}", var));
+        }
+        vars.push(var.to_string());
+        rest = &after[end + 1..];
+    }
+
+    Ok(vars)
+}
+
+fn expand(value: &str) -> Result<String> {
+    let mut expanded = value.to_string();
+    for var in placeholders(value).map_err(anyhow::Error::msg)? {
+        let replacement = env::var(&var).with_context(|| format!("variable {} is not set", var))?;
+        expanded = expanded.replace(&format!("${{{}}}", var), &replacement);
+    }
+    Ok(expanded)
+}
+
+// A header name and value hyper will accept
+fn check_header(name: &str, value: &str) -> std::result::Result<(), String> {
+    if warp::http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+        return Err(format!("{:?} isn't a valid header name", name));
+    }
+    if warp::http::HeaderValue::from_str(value).is_err() {
+        return Err(format!("{}: {:?} isn't a valid header value", name, value));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test only touches variables of its own
+    fn set_env(key: &str, value: &str) {
+        // SAFETY: std's own environment lock covers every reader in these tests
+        unsafe { env::set_var(key, value) };
+    }
+
+    fn remove_env(key: &str) {
+        // SAFETY: as in set_env
+        unsafe { env::remove_var(key) };
+    }
+
+    fn sources(resolver: &Resolver) -> Vec<Source> {
+        resolver.sources.iter().map(|(_, _, source)| *source).collect()
+    }
+
+    #[test]
+    fn each_layer_overrides_the_ones_below() {
+        let key = "NVR_TEST_LAYER_PORT";
+        let mut resolver = Resolver::default();
+
+        set_env(key, "2000");
+        assert_eq!(resolver.layer_or("port", Some(1000u16), key, Some(3000), 4000).unwrap(), 1000);
+        assert_eq!(resolver.layer_or("port", None, key, Some(3000), 4000).unwrap(), 2000);
+        remove_env(key);
+        assert_eq!(resolver.layer_or("port", None, key, Some(3000), 4000).unwrap(), 3000);
+        assert_eq!(resolver.layer_or("port", None, key, None, 4000).unwrap(), 4000);
+        assert_eq!(resolver.layer::<u16>("port", None, key, None).unwrap(), None);
+
+        assert_eq!(sources(&resolver), [Source::Cli, Source::Env, Source::File, Source::Default, Source::Default]);
+        // The default is what gets logged for a setting nothing else set
+        assert_eq!(resolver.sources[3].1, "4000");
+    }
+
+    #[test]
+    fn unparsable_env_value_is_an_error_not_a_fallback() {
+        let key = "NVR_TEST_LAYER_BAD";
+        set_env(key, "lots");
+        let result = Resolver::default().layer_or("max_streams", None, key, Some(8usize), 64);
+        remove_env(key);
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains(key) && err.contains("lots"), "{}", err);
+    }
+
+    #[test]
+    fn env_lists_are_comma_separated() {
+        let key = "NVR_TEST_LAYER_LIST";
+        let mut resolver = Resolver::default();
+        set_env(key, "10.0.0.1, 10.0.0.2,");
+        let from_env: Vec<IpAddr> = resolver.layer_list("allow", None, key, Some(vec![IpAddr::from([10, 0, 0, 9])])).unwrap();
+        remove_env(key);
+        assert_eq!(from_env, [IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])]);
+        let from_cli = resolver.layer_list("allow", Some(vec![IpAddr::from([10, 0, 0, 3])]), key, None).unwrap();
+        assert_eq!(from_cli, [IpAddr::from([10, 0, 0, 3])]);
+        assert_eq!(sources(&resolver), [Source::Env, Source::Cli]);
+    }
+
+    #[test]
+    fn secrets_come_from_env_and_are_never_shown() {
+        let key = "NVR_TEST_LAYER_SECRET";
+        let mut resolver = Resolver::default();
+        assert_eq!(resolver.secret("pass", key, "fallback"), "fallback");
+        set_env(key, "hunter2");
+        assert_eq!(resolver.secret("pass", key, "fallback"), "hunter2");
+        remove_env(key);
+        assert_eq!(sources(&resolver), [Source::Default, Source::Env]);
+        assert!(resolver.sources.iter().all(|(_, shown, _)| shown == "<hidden>"));
+    }
+
+    // Two named sets, CCTV_CRED_USER / CCTV_CRED_PASS standing in as env-user / env-pass
+    fn credential_settings(default_credentials: Option<&str>) -> Settings {
+        let mut settings = resolve_config(&Cli::parse_from(["rtspstream"])).unwrap();
+        settings.credentials = BTreeMap::from([
+            ("hik".to_string(), CredentialSet { user: "hik-user".to_string(), pass: "hik-pass".to_string() }),
+            ("axis".to_string(), CredentialSet { user: "axis-user".to_string(), pass: "axis-pass".to_string() }),
+        ]);
+        settings.default_credentials = default_credentials.map(str::to_string);
+        settings.default_user = "env-user".to_string();
+        settings.default_pass = "env-pass".to_string();
+        settings
+    }
+
+    fn camera(credentials: Option<&str>) -> CameraConfig {
+        CameraConfig { credentials: credentials.map(str::to_string), ..CameraConfig::from_env("cam".to_string(), "rtsp://cam/".to_string()) }
+    }
+
+    fn pair(user: &str, pass: &str) -> (String, String) {
+        (user.to_string(), pass.to_string())
+    }
+
+    #[test]
+    fn camera_credential_set_overrides_the_default_set() {
+        let settings = credential_settings(Some("hik"));
+        assert_eq!(settings.camera_credentials(&camera(Some("axis"))), pair("axis-user", "axis-pass"));
+    }
+
+    #[test]
+    fn default_set_applies_to_cameras_without_one() {
+        let settings = credential_settings(Some("hik"));
+        assert_eq!(settings.camera_credentials(&camera(None)), pair("hik-user", "hik-pass"));
+    }
+
+    #[test]
+    fn env_credentials_are_the_last_fallback() {
+        let settings = credential_settings(None);
+        assert_eq!(settings.camera_credentials(&camera(None)), pair("env-user", "env-pass"));
+    }
+
+    #[test]
+    fn inline_user_and_pass_win_field_by_field() {
+        let settings = credential_settings(Some("hik"));
+        let inline = CameraConfig { user: Some("me".to_string()), ..camera(Some("axis")) };
+        assert_eq!(settings.camera_credentials(&inline), pair("me", "axis-pass"));
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::{anyhow, Result};
+
+// Whole-machine CPU usage from /proc/stat, as the share of non-idle time between two samples
+#[derive(Default)]
+pub struct CpuSampler {
+    // (idle, total) jiffies at the previous sample
+    previous: Option<(u64, u64)>,
+}
+
+impl CpuSampler {
+    // Percent busy since the previous call, None on the first call
+    pub fn sample(&mut self) -> Result<Option<f64>> {
+        let (idle, total) = read_proc_stat()?;
+        let usage = self.previous.and_then(|(previous_idle, previous_total)| {
+            let total = total.checked_sub(previous_total).filter(|&total| total > 0)?;
+            let idle = idle.saturating_sub(previous_idle).min(total);
+            Some(100.0 * (total - idle) as f64 / total as f64)
+        });
+        self.previous = Some((idle, total));
+        Ok(usage)
+    }
+}
+
+// cpu  user nice system idle iowait irq softirq steal guest guest_nice
+// guest time is already counted in user/nice, so only the first eight columns are summed
+fn read_proc_stat() -> Result<(u64, u64)> {
+    let stat = std::fs::read_to_string("/proc/stat")?;
+    let line = stat.lines().find(|line| line.starts_with("cpu ")).ok_or_else(|| anyhow!("no cpu line in /proc/stat"))?;
+    let values: Vec<u64> = line.split_whitespace().skip(1).take(8).map(|value| value.parse().unwrap_or(0)).collect();
+    if values.len() < 5 {
+        return Err(anyhow!("short cpu line in /proc/stat"));
+    }
+    Ok((values[3] + values[4], values.iter().sum()))
+}
+
+// USER_HZ, what /proc reports times in. 100 on every mainstream Linux architecture.
+const CLOCK_TICKS_PER_SEC: f64 = 100.0;
+
+// CPU time this process has used so far, user plus system, in seconds
+pub fn process_cpu_secs() -> Result<f64> {
+    let stat = std::fs::read_to_string("/proc/self/stat")?;
+    // The command name in parentheses may contain spaces, count fields from after it.
+    // utime and stime are fields 14 and 15, the 12th and 13th after the name.
+    let (_, rest) = stat.rsplit_once(')').ok_or_else(|| anyhow!("malformed /proc/self/stat"))?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    let ticks: u64 = fields
+        .get(11..13)
+        .ok_or_else(|| anyhow!("short /proc/self/stat"))?
+        .iter()
+        .map(|field| field.parse::<u64>().unwrap_or(0))
+        .sum();
+    Ok(ticks as f64 / CLOCK_TICKS_PER_SEC)
+}
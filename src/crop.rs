@@ -0,0 +1,64 @@
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use gst::prelude::*;
+
+use crate::config::CropRect;
+use crate::error::{NvrError, Result};
+
+// videocrop element for the camera's crop, with a trailing " ! ". It goes right after
+// decoding, ahead of rotation and the tee, so every branch gets the cropped picture.
+pub fn videocrop(crop: Option<CropRect>) -> String {
+    match crop {
+        Some(_) => "videocrop name=crop ! ".to_string(),
+        None => String::new(),
+    }
+}
+
+// videocrop takes margins rather than a rectangle, and those depend on the source size.
+// Set them from every caps event on the way in, so they follow resolution switches too.
+pub fn attach(pipeline: &gst::Pipeline, stream_name: &str, crop: CropRect) -> Result<()> {
+    let videocrop = pipeline.by_name("crop").ok_or_else(|| NvrError::ElementMissing("videocrop".to_string()))?;
+    let sink = videocrop.static_pad("sink").expect("videocrop without sink pad");
+
+    let stream_name = stream_name.to_string();
+    sink.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        let Some(gst::PadProbeData::Event(ref event)) = info.data else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let gst::EventView::Caps(caps) = event.view() else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let Ok(video_info) = gst_video::VideoInfo::from_caps(caps.caps()) else {
+            return gst::PadProbeReturn::Ok;
+        };
+
+        let (width, height) = (video_info.width(), video_info.height());
+        let (left, top, right, bottom) = margins(crop, width, height);
+        if !fits(crop, width, height) {
+            println!(
+                "{}: crop {}x{} at {},{} doesn't fit the {}x{} source, keeping only the part that does",
+                stream_name, crop.width, crop.height, crop.x, crop.y, width, height
+            );
+        }
+        videocrop.set_property("left", left as i32);
+        videocrop.set_property("top", top as i32);
+        videocrop.set_property("right", right as i32);
+        videocrop.set_property("bottom", bottom as i32);
+        gst::PadProbeReturn::Ok
+    });
+    Ok(())
+}
+
+fn fits(crop: CropRect, width: u32, height: u32) -> bool {
+    crop.x as u64 + crop.width as u64 <= width as u64 && crop.y as u64 + crop.height as u64 <= height as u64
+}
+
+// Left, top, right and bottom margins. A crop that doesn't fit is clipped to the source,
+// always leaving at least one pixel.
+fn margins(crop: CropRect, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let left = crop.x.min(width.saturating_sub(1));
+    let top = crop.y.min(height.saturating_sub(1));
+    let right_edge = crop.x.saturating_add(crop.width).clamp(left + 1, width.max(1));
+    let bottom_edge = crop.y.saturating_add(crop.height).clamp(top + 1, height.max(1));
+    (left, top, width.saturating_sub(right_edge), height.saturating_sub(bottom_edge))
+}
@@ -0,0 +1,288 @@
+use anyhow::Result;
+use gstreamer as gst;
+use gst::prelude::*;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{CodecChange, DecodeMode};
+use crate::error::{self, NvrError};
+
+// Video codecs with a dedicated depay/decode branch, by the SDP encoding-name rtspsrc
+// puts in its pad caps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    H264,
+    H265,
+    Jpeg,
+}
+
+impl Codec {
+    fn from_encoding_name(name: &str) -> Option<Codec> {
+        match name.to_ascii_uppercase().as_str() {
+            "H264" => Some(Codec::H264),
+            "H265" => Some(Codec::H265),
+            "JPEG" => Some(Codec::Jpeg),
+            _ => None,
+        }
+    }
+
+    fn depay(self) -> &'static str {
+        match self {
+            Codec::H264 => "rtph264depay ! h264parse",
+            Codec::H265 => "rtph265depay ! h265parse",
+            Codec::Jpeg => "rtpjpegdepay ! jpegparse",
+        }
+    }
+
+    // In order of preference, the first one installed is used
+    fn decoders(self, mode: DecodeMode) -> &'static [&'static str] {
+        match (self, mode) {
+            (Codec::H264, DecodeMode::Hardware) => {
+                &["vah264dec", "vaapih264dec", "nvh264dec", "v4l2h264dec", "avdec_h264", "openh264dec"]
+            }
+            (Codec::H264, _) => &["avdec_h264", "openh264dec"],
+            (Codec::H265, DecodeMode::Hardware) => {
+                &["vah265dec", "vaapih265dec", "nvh265dec", "v4l2h265dec", "avdec_h265", "libde265dec"]
+            }
+            (Codec::H265, _) => &["avdec_h265", "libde265dec"],
+            // MJPEG frames are plain JPEGs, decoding them is cheap and needs no hardware.
+            // They can't be passed through untouched, every tee branch takes raw video.
+            (Codec::Jpeg, _) => &["jpegdec"],
+        }
+    }
+}
+
+// Every hardware decoder decoders() may pick, for probe_hardware
+const HARDWARE_DECODERS: &[&str] = &[
+    "vah264dec", "vaapih264dec", "nvh264dec", "v4l2h264dec",
+    "vah265dec", "vaapih265dec", "nvh265dec", "v4l2h265dec",
+];
+
+// Installed hardware decoders that failed probe_hardware, passed over by select_decoder
+static UNUSABLE: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+
+// How one installed hardware decoder did in probe_hardware, for the startup log and
+// /api/manifest
+#[derive(Clone, Debug, Serialize)]
+pub struct DecoderProbe {
+    pub element: &'static str,
+    pub usable: bool,
+    pub error: Option<String>,
+}
+
+// Create each installed hardware decoder and take it to Ready, where it opens its device
+// (a VA display, a CUDA context, the V4L2 node). The ones that fail are left out of
+// decoder: hardware and decodebin from then on, which fall back to the next candidate or
+// software.
+// Call once after gst::init, before any pipeline is built.
+pub fn probe_hardware() -> Vec<DecoderProbe> {
+    let mut probes = Vec::new();
+    for &element in HARDWARE_DECODERS {
+        if gst::ElementFactory::find(element).is_none() {
+            continue;
+        }
+        let error = match gst::ElementFactory::make(element).build() {
+            Ok(decoder) => {
+                let ready = decoder.set_state(gst::State::Ready);
+                let _ = decoder.set_state(gst::State::Null);
+                ready.err().map(|err| format!("can't reach Ready: {}", err))
+            }
+            Err(err) => Some(format!("can't be created: {}", err)),
+        };
+        // decodebin goes by rank, so it passes over the element too
+        if error.is_some() {
+            if let Some(factory) = gst::ElementFactory::find(element) {
+                factory.set_rank(gst::Rank::NONE);
+            }
+        }
+        probes.push(DecoderProbe { element, usable: error.is_none(), error });
+    }
+    *UNUSABLE.lock().unwrap() = probes.iter().filter(|probe| !probe.usable).map(|probe| probe.element).collect();
+    probes
+}
+
+fn select_decoder(codec: Codec, mode: DecodeMode) -> Option<&'static str> {
+    let unusable = UNUSABLE.lock().unwrap();
+    codec
+        .decoders(mode)
+        .iter()
+        .copied()
+        .filter(|factory| !unusable.contains(factory))
+        .find(|factory| gst::ElementFactory::find(factory).is_some())
+}
+
+// What follows `rtspsrc ...` in the pipeline string. Unless decodebin is asked for, the
+// camera is left unlinked and `decoded` stands in for the decoder until attach links them.
+pub fn source_element(rtspsrc: &str, mode: DecodeMode) -> String {
+    match mode {
+        DecodeMode::Decodebin => format!("{} ! decodebin", rtspsrc),
+        DecodeMode::Auto | DecodeMode::Hardware => format!("{} name=camera identity name=decoded", rtspsrc),
+    }
+}
+
+// Add a decoding branch for the codec of the camera's video once rtspsrc exposes it, and
+// replace it if the camera switches codecs mid-session (see CodecChange). Counts the RTP
+// bytes the camera sends into `source_bytes`.
+pub fn attach(
+    pipeline: &gst::Pipeline,
+    stream_name: &str,
+    mode: DecodeMode,
+    on_change: CodecChange,
+    source_bytes: Arc<AtomicU64>,
+) -> error::Result<()> {
+    if mode == DecodeMode::Decodebin {
+        return Ok(());
+    }
+    let camera = pipeline.by_name("camera").ok_or_else(|| NvrError::ElementMissing("rtspsrc".to_string()))?;
+    let decoded = pipeline.by_name("decoded").ok_or_else(|| NvrError::ElementMissing("decoded".to_string()))?;
+
+    let decoding = Arc::new(Decoding {
+        pipeline: pipeline.downgrade(),
+        decoded,
+        stream_name: stream_name.to_string(),
+        mode,
+        on_change,
+        encoding: Mutex::new(None),
+        source_bytes,
+    });
+    camera.connect_pad_added(move |_, pad| {
+        if let Err(err) = decoding.pad_added(pad) {
+            println!("{}: Failed to set up decoding: {:?}", decoding.stream_name, err);
+        }
+    });
+    Ok(())
+}
+
+// The camera's decode branch, shared by every pad rtspsrc adds over the pipeline's life
+struct Decoding {
+    pipeline: gst::glib::WeakRef<gst::Pipeline>,
+    decoded: gst::Element,
+    stream_name: String,
+    mode: DecodeMode,
+    on_change: CodecChange,
+    // Encoding name the current branch was built for, upper case
+    encoding: Mutex<Option<String>>,
+    source_bytes: Arc<AtomicU64>,
+}
+
+impl Decoding {
+    fn pad_added(self: &Arc<Self>, pad: &gst::Pad) -> Result<()> {
+        let Some(pipeline) = self.pipeline.upgrade() else { return Ok(()) };
+        let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+        let Some(structure) = caps.structure(0) else { return Ok(()) };
+        if structure.get::<&str>("media").ok() != Some("video") {
+            return Ok(());
+        }
+        let encoding = structure.get::<&str>("encoding-name").unwrap_or("unknown").to_ascii_uppercase();
+
+        let sink = self.decoded.static_pad("sink").expect("identity without sink pad");
+        if let Some(branch) = sink.peer().and_then(|peer| peer.parent_element()) {
+            if branch.static_pad("sink").is_some_and(|pad| pad.is_linked()) {
+                // Another video track of the same camera, the first one wins as with decodebin.
+                // A new payload type gets a pad of its own though, so a different codec on a
+                // new pad is the camera switching over rather than a second track.
+                let current = self.encoding.lock().unwrap().clone();
+                if current.as_deref() == Some(encoding.as_str()) || !self.changed(&pipeline, current.as_deref(), &encoding) {
+                    return Ok(());
+                }
+            }
+            // Left over from before a reconnect (rtspsrc replaced the pad that fed it) or
+            // built for the codec the camera just left
+            let _ = branch.set_state(gst::State::Null);
+            pipeline.remove(&branch)?;
+        }
+
+        self.link(&pipeline, pad, &encoding)?;
+
+        // RTP headers included, a few percent over the video's own bitrate
+        let source_bytes = self.source_bytes.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+            if let Some(buffer) = info.buffer() {
+                source_bytes.fetch_add(buffer.size() as u64, Ordering::Relaxed);
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        // The same pad renegotiating to another codec, caught as its caps go by
+        let decoding = Arc::downgrade(self);
+        pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+            let Some(gst::PadProbeData::Event(ref event)) = info.data else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let gst::EventView::Caps(caps) = event.view() else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let Some(decoding) = decoding.upgrade() else { return gst::PadProbeReturn::Remove };
+            let Some(pipeline) = decoding.pipeline.upgrade() else { return gst::PadProbeReturn::Remove };
+            let Some(encoding) = caps.caps().structure(0).and_then(|s| s.get::<&str>("encoding-name").ok()) else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let encoding = encoding.to_ascii_uppercase();
+            let current = decoding.encoding.lock().unwrap().clone();
+            if current.as_deref() == Some(encoding.as_str()) || !decoding.changed(&pipeline, current.as_deref(), &encoding) {
+                return gst::PadProbeReturn::Ok;
+            }
+            // Swapped from the streaming thread before the caps event goes on, which then
+            // reaches the new branch
+            if let Err(err) = decoding.relink(&pipeline, pad, &encoding) {
+                println!("{}: Failed to rebuild decoding for {}: {:?}", decoding.stream_name, encoding, err);
+            }
+            gst::PadProbeReturn::Ok
+        });
+        Ok(())
+    }
+
+    // Log a codec switch and, for CodecChange::Restart, ask the bus watch for a restart.
+    // True if the decode branch should be rebuilt in place.
+    fn changed(&self, pipeline: &gst::Pipeline, from: Option<&str>, to: &str) -> bool {
+        let from = from.unwrap_or("unknown");
+        match self.on_change {
+            CodecChange::Rebuild => {
+                println!("{}: Camera switched from {} to {}, rebuilding the decoder", self.stream_name, from, to);
+                true
+            }
+            CodecChange::Restart => {
+                println!("{}: Camera switched from {} to {}, restarting the pipeline", self.stream_name, from, to);
+                gst::element_error!(pipeline, gst::StreamError::Format, ("Camera switched codec from {} to {}", from, to));
+                false
+            }
+        }
+    }
+
+    // Replace the branch `pad` feeds with one for `encoding`
+    fn relink(&self, pipeline: &gst::Pipeline, pad: &gst::Pad, encoding: &str) -> Result<()> {
+        if let Some(branch) = pad.peer().and_then(|peer| peer.parent_element()) {
+            let _ = branch.set_state(gst::State::Null);
+            pipeline.remove(&branch)?;
+        }
+        self.link(pipeline, pad, encoding)
+    }
+
+    fn link(&self, pipeline: &gst::Pipeline, pad: &gst::Pad, encoding: &str) -> Result<()> {
+        let stream_name = &self.stream_name;
+        let description = match Codec::from_encoding_name(encoding).map(|codec| (codec, select_decoder(codec, self.mode))) {
+            Some((codec, Some(decoder))) => {
+                println!("{}: Detected {} video, decoding with {}", stream_name, encoding, decoder);
+                format!("{} ! {}", codec.depay(), decoder)
+            }
+            Some((_, None)) => {
+                println!("{}: Detected {} video but no decoder for it is installed, falling back to decodebin", stream_name, encoding);
+                "decodebin ! identity".to_string()
+            }
+            None => {
+                println!("{}: Detected {} video, falling back to decodebin", stream_name, encoding);
+                "decodebin ! identity".to_string()
+            }
+        };
+
+        let sink = self.decoded.static_pad("sink").expect("identity without sink pad");
+        let branch = gst::parse::bin_from_description(&description, true)?;
+        pipeline.add(&branch)?;
+        branch.static_pad("src").expect("decode branch without src pad").link(&sink)?;
+        branch.sync_state_with_parent()?;
+        pad.link(&branch.static_pad("sink").expect("decode branch without sink pad"))?;
+        *self.encoding.lock().unwrap() = Some(encoding.to_string());
+        Ok(())
+    }
+}
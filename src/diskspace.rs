@@ -0,0 +1,59 @@
+use anyhow::Result;
+use crate::config::{DiskFullAction, RecordingContainer};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Whether `dir` is still below `min_free` bytes after `action` had a go at it
+pub fn is_full(dir: &Path, min_free: u64, action: DiskFullAction) -> Result<bool> {
+    if free_bytes(dir)? >= min_free {
+        return Ok(false);
+    }
+    if action == DiskFullAction::Prune {
+        prune_oldest(dir, min_free)?;
+    }
+    Ok(free_bytes(dir)? < min_free)
+}
+
+pub fn free_bytes(dir: &Path) -> Result<u64> {
+    Ok(fs2::available_space(dir)?)
+}
+
+// Delete the oldest recorded segments under `dir` until `min_free` bytes are available.
+// Each camera's newest segment is left alone, it may still be being written.
+// Returns the number of files deleted.
+pub fn prune_oldest(dir: &Path, min_free: u64) -> Result<usize> {
+    let mut segments: Vec<(SystemTime, PathBuf)> = Vec::new();
+    for camera_dir in std::fs::read_dir(dir)? {
+        let camera_dir = camera_dir?.path();
+        if !camera_dir.is_dir() {
+            continue;
+        }
+        let mut files: Vec<(SystemTime, PathBuf)> = std::fs::read_dir(&camera_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| is_segment(&entry.path()))
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect();
+        files.sort();
+        files.pop();
+        segments.extend(files);
+    }
+    segments.sort();
+
+    let mut deleted = 0;
+    for (_, path) in segments {
+        if free_bytes(dir)? >= min_free {
+            break;
+        }
+        println!("Disk nearly full, deleting {}", path.display());
+        std::fs::remove_file(&path)?;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+// A recorded segment in either recording_container, whatever the camera uses now
+fn is_segment(path: &Path) -> bool {
+    [RecordingContainer::Mkv, RecordingContainer::Mp4]
+        .iter()
+        .any(|container| path.extension().is_some_and(|ext| ext == container.extension()))
+}
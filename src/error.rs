@@ -0,0 +1,32 @@
+use gstreamer as gst;
+use thiserror::Error;
+
+// What went wrong building or starting a pipeline. Lets the supervisor tell a camera
+// that's unreachable for now, worth retrying, from a setup that will never work.
+#[derive(Debug, Error)]
+pub enum NvrError {
+    #[error("invalid pipeline description: {0}")]
+    PipelineParse(#[from] gst::glib::Error),
+    #[error("GStreamer element {0} is missing")]
+    ElementMissing(String),
+    #[error("can't reach the camera: {0}")]
+    RtspConnect(String),
+    #[error("pipeline failed to change state: {0}")]
+    StateChange(#[from] gst::StateChangeError),
+    #[error("GStreamer: {0}")]
+    Gst(#[from] gst::glib::BoolError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("config: {0}")]
+    Config(String),
+}
+
+impl NvrError {
+    // Network trouble and camera hiccups can clear up by themselves, a bad config, missing
+    // plugin or broken pipeline description won't
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, NvrError::RtspConnect(_) | NvrError::StateChange(_))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, NvrError>;
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::onvif::CameraEvent;
+
+// An event as GET /api/events returns it, stamped with when the server got it. The
+// event's own time is the camera's clock and may be missing.
+#[derive(Clone, Serialize)]
+pub struct LoggedEvent {
+    pub received_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: CameraEvent,
+}
+
+// A stream's most recent events, the oldest forgotten once there are `capacity` of them
+pub struct EventLog {
+    events: Mutex<VecDeque<LoggedEvent>>,
+    capacity: usize,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        EventLog { events: Mutex::new(VecDeque::with_capacity(capacity)), capacity }
+    }
+
+    pub fn push(&self, event: CameraEvent) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(LoggedEvent { received_at: Utc::now(), event });
+    }
+
+    // Oldest first, only the ones received after `since` when given
+    pub fn since(&self, since: Option<DateTime<Utc>>) -> Vec<LoggedEvent> {
+        let events = self.events.lock().unwrap();
+        events
+            .iter()
+            .filter(|logged| since.is_none_or(|since| logged.received_at > since))
+            .cloned()
+            .collect()
+    }
+
+    // Keep the log filled from a stream's events channel for as long as the channel lives
+    pub fn follow(self: &Arc<Self>, events: &broadcast::Sender<CameraEvent>) {
+        let log = self.clone();
+        let mut rx = events.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => log.push(event),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
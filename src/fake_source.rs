@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use gstreamer_rtsp_server as gst_rtsp_server;
+use gst_rtsp_server::prelude::*;
+
+use crate::config::CameraConfig;
+
+// Local RTSP server standing in for real cameras, so the whole frame path (rtspsrc,
+// decode, scale, JPEG, broadcast) can be exercised in CI. Only built with the
+// `fake-source` feature. Frames reaching viewers show up as `frame_seq` in /api/streams.
+// Listens on a port the OS picks, so it never takes rtsp_relay_address's or anyone else's.
+// Keeps the server alive, mounts are dispatched by the supervisor's glib main loop.
+pub struct FakeRtspServer {
+    port: u16,
+    count: usize,
+    _server: gst_rtsp_server::RTSPServer,
+    _source: gst_rtsp_server::glib::SourceId,
+}
+
+pub fn camera_name(index: usize) -> String {
+    format!("fake{}", index)
+}
+
+// resolve_config adds the cameras before the server has a port, with 0 in its place
+pub fn camera_url(index: usize, port: u16) -> String {
+    format!("rtsp://127.0.0.1:{}/{}", port, camera_name(index))
+}
+
+// Serve `count` H.264 test patterns with a running clock at camera_url(0..count)
+pub fn start(count: usize) -> Result<FakeRtspServer> {
+    let server = gst_rtsp_server::RTSPServer::new();
+    server.set_service("0");
+    let mounts = server.mount_points().ok_or_else(|| anyhow!("RTSP server without mount points"))?;
+
+    for index in 0..count {
+        let factory = gst_rtsp_server::RTSPMediaFactory::new();
+        // Different pattern per camera so mixed-up streams are obvious
+        factory.set_launch(&format!(
+            "( videotestsrc is-live=true pattern={} ! video/x-raw,width=1280,height=720,framerate=15/1 ! timeoverlay ! x264enc tune=zerolatency speed-preset=ultrafast key-int-max=30 ! rtph264pay name=pay0 pt=96 )",
+            index % 25
+        ));
+        factory.set_shared(true);
+        mounts.add_factory(&format!("/{}", camera_name(index)), factory);
+    }
+
+    let source = server.attach(None)?;
+    let port = u16::try_from(server.bound_port()).map_err(|_| anyhow!("fake RTSP server didn't bind a port"))?;
+    println!("Fake RTSP server serving {} camera(s) on port {}", count, port);
+    Ok(FakeRtspServer { port, count, _server: server, _source: source })
+}
+
+impl FakeRtspServer {
+    // Point the fake{N} cameras at the port the server got
+    pub fn point_cameras(&self, cameras: &mut [CameraConfig]) {
+        for index in 0..self.count {
+            if let Some(camera) = cameras.iter_mut().find(|camera| camera.name == camera_name(index)) {
+                camera.url = camera_url(index, self.port);
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::Frame;
+
+// The last few seconds of a stream's frames, kept in memory for viewers scrubbing back
+// with the seek command
+#[derive(Default)]
+pub struct FrameHistory {
+    frames: Mutex<VecDeque<(Instant, Frame)>>,
+}
+
+impl FrameHistory {
+    // Add a frame, forgetting the ones older than `keep`
+    pub fn push(&self, frame: Frame, keep: Duration) {
+        let now = Instant::now();
+        let mut frames = self.frames.lock().unwrap();
+        while frames.front().is_some_and(|(at, _)| now.duration_since(*at) > keep) {
+            frames.pop_front();
+        }
+        frames.push_back((now, frame));
+    }
+
+    // Frames from `back` ago up to now, each with its offset from the first so they can be
+    // replayed at the pace they came in. Going back further than the history reaches starts
+    // at its oldest frame; the first value is how far back that actually is.
+    pub fn since(&self, back: Duration) -> (Duration, Vec<(Duration, Frame)>) {
+        let now = Instant::now();
+        let frames = self.frames.lock().unwrap();
+        let start = frames.partition_point(|(at, _)| now.duration_since(*at) > back);
+        let Some((first, _)) = frames.get(start) else { return (Duration::ZERO, Vec::new()) };
+        let replay = frames
+            .range(start..)
+            .map(|(at, frame)| (at.duration_since(*first), frame.clone()))
+            .collect();
+        (now.duration_since(*first), replay)
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::time::Duration;
+
+// A write that takes longer is dropped, the next push sends fresh numbers anyway
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Pushes metrics to an InfluxDB write endpoint (v1 /write or v2 /api/v2/write) as line
+// protocol. Points carry nanosecond timestamps, InfluxDB's default precision.
+pub struct InfluxWriter {
+    url: String,
+    token: Option<String>,
+    measurement: String,
+    // Written on every point after the stream tag, already escaped
+    tags: String,
+}
+
+impl InfluxWriter {
+    pub fn new(url: String, token: Option<String>, measurement: &str, server_id: &str, tags: &[(String, String)]) -> Self {
+        let mut all_tags = format!(",server={}", escape_tag(server_id));
+        for (key, value) in tags {
+            all_tags.push_str(&format!(",{}={}", escape_tag(key), escape_tag(value)));
+        }
+        InfluxWriter { url, token, measurement: escape_measurement(measurement), tags: all_tags }
+    }
+
+    // One point for a stream. Every number and bool in `fields` becomes a field, nested
+    // objects flattened with '_' (qos.latency_ms is qos_latency_ms); strings and nulls are
+    // left out. None if there's no field at all.
+    pub fn line(&self, stream: &str, fields: &Value, time: DateTime<Utc>) -> Option<String> {
+        let mut flat = Vec::new();
+        flatten("", fields, &mut flat);
+        if flat.is_empty() {
+            return None;
+        }
+        let fields = flat.iter().map(|(key, value)| format!("{}={}", escape_tag(key), value)).collect::<Vec<_>>().join(",");
+        let time = time.timestamp_nanos_opt()?;
+        Some(format!("{},stream={}{} {} {}", self.measurement, escape_tag(stream), self.tags, fields, time))
+    }
+
+    // Blocking, call from spawn_blocking
+    pub fn write(&self, lines: &[String]) -> Result<()> {
+        let mut request = ureq::post(&self.url).timeout(HTTP_TIMEOUT).set("Content-Type", "text/plain; charset=utf-8");
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Token {}", token));
+        }
+        request.send_string(&lines.join("\n"))?;
+        Ok(())
+    }
+}
+
+// "key=value,key=value", for influx_tags
+pub fn parse_tags(spec: &str) -> Result<Vec<(String, String)>> {
+    let mut tags = Vec::new();
+    for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            bail!("{:?} isn't key=value", pair);
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            bail!("{:?} needs both a key and a value", pair);
+        }
+        if key == "stream" || key == "server" {
+            bail!("{:?} is set on every point already", key);
+        }
+        tags.push((key.to_string(), value.to_string()));
+    }
+    Ok(tags)
+}
+
+pub fn check_measurement(measurement: &str) -> Result<()> {
+    if measurement.trim().is_empty() {
+        bail!("must not be empty");
+    }
+    if measurement.starts_with('_') {
+        bail!("{:?} starts with '_', which InfluxDB reserves", measurement);
+    }
+    Ok(())
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    let key = |name: &str| match prefix {
+        "" => name.to_string(),
+        _ => format!("{}_{}", prefix, name),
+    };
+    match value {
+        Value::Object(object) => {
+            for (name, value) in object {
+                flatten(&key(name), value, out);
+            }
+        }
+        // Integers keep the integer type, so a field's type doesn't change between points
+        Value::Number(number) if number.is_f64() => {
+            if let Some(float) = number.as_f64().filter(|float| float.is_finite()) {
+                out.push((prefix.to_string(), format!("{:?}", float)));
+            }
+        }
+        Value::Number(number) => out.push((prefix.to_string(), format!("{}i", number))),
+        Value::Bool(flag) => out.push((prefix.to_string(), flag.to_string())),
+        Value::String(_) | Value::Null | Value::Array(_) => (),
+    }
+}
+
+// Commas, equals signs and spaces are escaped in tag keys, tag values and field keys
+fn escape_tag(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+fn escape_measurement(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(',', "\\,").replace(' ', "\\ ")
+}
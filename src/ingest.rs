@@ -0,0 +1,136 @@
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gst::prelude::*;
+use std::io::{ErrorKind, Read};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{NvrError, Result};
+
+// How often the socket thread checks whether its pipeline is still around
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Frames pushed in by a local producer instead of pulled from a camera
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ingest {
+    // A GStreamer shmsink, e.g. `... ! jpegenc ! shmsink socket-path=/path`
+    Shm(PathBuf),
+    // A Unix socket created here, producers connect and write back-to-back JPEGs (MJPEG)
+    Unix(PathBuf),
+    // Synthetic moving test pattern, raw frames with no camera decoding, for --bench
+    Test { width: u32, height: u32, fps: u32 },
+}
+
+impl Ingest {
+    // shm:/path, unix:/path or test:<width>x<height>@<fps>
+    pub fn parse(source: &str) -> std::result::Result<Ingest, String> {
+        let (kind, path) = source
+            .split_once(':')
+            .ok_or_else(|| format!("source {:?} must be shm:/path, unix:/path or test:WxH@fps", source))?;
+        if kind == "test" {
+            return parse_test(path).ok_or_else(|| format!("source {:?} must be test:<width>x<height>@<fps>, e.g. test:1280x720@15", source));
+        }
+        if !path.starts_with('/') {
+            return Err(format!("source {:?} needs an absolute path", source));
+        }
+        match kind {
+            "shm" => Ok(Ingest::Shm(PathBuf::from(path))),
+            "unix" => Ok(Ingest::Unix(PathBuf::from(path))),
+            _ => Err(format!("source {:?} must be shm:/path, unix:/path or test:WxH@fps", source)),
+        }
+    }
+
+    // Replaces `rtspsrc ! decodebin`, producing raw video for the tee
+    pub fn source_element(&self) -> String {
+        match self {
+            Ingest::Shm(path) => format!(
+                "shmsrc socket-path={} is-live=true do-timestamp=true ! image/jpeg ! jpegparse ! jpegdec",
+                path.display()
+            ),
+            Ingest::Unix(_) => {
+                "appsrc name=ingest is-live=true do-timestamp=true format=time caps=image/jpeg ! jpegparse ! jpegdec".to_string()
+            }
+            // The ball keeps every frame different, like a busy scene
+            Ingest::Test { width, height, fps } => format!(
+                "videotestsrc is-live=true pattern=ball ! video/x-raw,width={},height={},framerate={}/1",
+                width, height, fps
+            ),
+        }
+    }
+
+    // Start feeding the pipeline's appsrc from the socket, nothing to do for shm
+    pub fn attach(&self, pipeline: &gst::Pipeline, stream_name: &str) -> Result<()> {
+        let Ingest::Unix(path) = self else { return Ok(()) };
+        let appsrc = pipeline
+            .by_name("ingest")
+            .and_then(|ingest| ingest.downcast::<gst_app::AppSrc>().ok())
+            .ok_or_else(|| NvrError::ElementMissing("ingest appsrc".to_string()))?;
+        serve_unix(path, stream_name, appsrc)
+    }
+}
+
+// "1280x720@15"
+fn parse_test(spec: &str) -> Option<Ingest> {
+    let (size, fps) = spec.split_once('@')?;
+    let (width, height) = size.split_once('x')?;
+    let (width, height, fps) = (width.parse().ok()?, height.parse().ok()?, fps.parse().ok()?);
+    if width == 0 || height == 0 || fps == 0 {
+        return None;
+    }
+    Some(Ingest::Test { width, height, fps })
+}
+
+// A previous run (or pipeline restart) leaves the socket file behind, binding needs it gone.
+// The old listener keeps working on the unlinked socket until its thread notices and exits.
+fn serve_unix(path: &Path, stream_name: &str, appsrc: gst_app::AppSrc) -> Result<()> {
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    println!("{}: Waiting for frames on {}", stream_name, path.display());
+
+    let stream_name = stream_name.to_string();
+    let appsrc = appsrc.downgrade();
+    std::thread::spawn(move || {
+        // One producer at a time, jpegparse resyncs on the next SOI after a reconnect
+        while appsrc.upgrade().is_some() {
+            let mut producer = match listener.accept() {
+                Ok((producer, _)) => producer,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(err) => {
+                    println!("{}: Ingest accept failed: {:?}", stream_name, err);
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+            println!("{}: Producer connected", stream_name);
+            let _ = producer.set_nonblocking(false);
+            let _ = producer.set_read_timeout(Some(POLL_INTERVAL));
+
+            let mut chunk = vec![0u8; 64 * 1024];
+            loop {
+                if appsrc.upgrade().is_none() {
+                    return;
+                }
+                let read = match producer.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted) => continue,
+                    Err(err) => {
+                        println!("{}: Ingest read failed: {:?}", stream_name, err);
+                        break;
+                    }
+                };
+                let Some(appsrc) = appsrc.upgrade() else { return };
+                // Refused while the pipeline isn't playing (starting up, reconnecting), the
+                // data is dropped and jpegparse picks up at the next frame
+                let _ = appsrc.push_buffer(gst::Buffer::from_slice(chunk[..read].to_vec()));
+            }
+            println!("{}: Producer disconnected", stream_name);
+        }
+    });
+    Ok(())
+}
@@ -1,23 +1,684 @@
 use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use futures::{SinkExt, StreamExt};
 use gstreamer as gst;
 use gstreamer_app as gst_app;
+use gstreamer_video as gst_video;
 use gst::prelude::*;
-use std::env;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
 use warp::ws::{Message, WebSocket};
 use warp::Filter;
-use lazy_static;
 
-type Clients = Arc<Mutex<HashMap<String, Vec<broadcast::Sender<Vec<u8>>>>>>;
+mod audio;
+mod audit;
+mod backoff;
+mod bench;
+mod branches;
+mod broadcast_pool;
+mod cadence;
+mod config;
+mod cpuload;
+mod crop;
+mod decode;
+mod diskspace;
+mod error;
+mod event_log;
+#[cfg(feature = "fake-source")]
+mod fake_source;
+mod history;
+mod influx;
+mod ingest;
+mod manifest;
+mod motion;
+mod motion_snapshots;
+mod onvif;
+mod outbox;
+mod placeholder;
+mod processor;
+mod qos;
+mod ratelimit;
+mod recorder;
+mod recordings;
+mod roi;
+mod rotation;
+#[cfg(feature = "rtsp-relay")]
+mod rtsp_relay;
+mod schedule;
+mod snapshot;
+mod socks;
+mod status;
+mod supervisor;
+mod tamper;
+mod thumbnail;
 
-// Add this struct to hold pipeline resources
+use audio::AudioPacket;
+use audit::AuditLog;
+use backoff::{Backoff, BackoffState};
+use branches::{BranchKind, LiveOutputs};
+use broadcast_pool::{BroadcastPool, Drain};
+use cadence::CadenceClock;
+use config::{CameraConfig, CameraMode, Cli, ReconnectPolicy, ScaleMode};
+use error::NvrError;
+use event_log::EventLog;
+use history::FrameHistory;
+use influx::InfluxWriter;
+use ingest::Ingest;
+use manifest::ManifestSource;
+use motion::{LumaGrid, MotionBox, MotionDetector, MotionState};
+use motion_snapshots::MotionSnapshots;
+use onvif::CameraEvent;
+use outbox::Outbox;
+use placeholder::NoSignalRenderer;
+use processor::{FrameData, Processors};
+use ratelimit::RateLimiter;
+use snapshot::SnapshotSource;
+use status::{StatusHub, StreamState, StreamStatus};
+use supervisor::{EffectivePreview, PipelineSupervisor, RoiLease, ShedLimits};
+use tamper::TamperDetector;
+use thumbnail::ThumbnailCache;
+
+// Per-frame logging for the hot paths. Without the frame-logging feature the branch is
+// constant false and compiled out, the arguments are still type checked.
+macro_rules! frame_log {
+    ($($arg:tt)*) => {
+        if cfg!(feature = "frame-logging") {
+            println!($($arg)*);
+        }
+    };
+}
+
+type Clients = Arc<Mutex<HashMap<String, StreamChannels>>>;
+type Stats = Arc<Mutex<HashMap<String, Arc<StreamStats>>>>;
+
+// Video bitrate used when restreaming a camera over RTMP
+const RESTREAM_BITRATE_KBPS: u32 = 2500;
+
+// With skip_static_frames or skip_duplicate_frames, still send a frame at least this often so
+// new viewers get a picture
+const STATIC_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often stream status is sampled for /ws/status
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long the first HLS request waits for a just started output to write its playlist
+const HLS_START_WAIT: Duration = Duration::from_secs(8);
+
+// How long POST /api/streams/:name/restart waits for Playing when pipeline_start_timeout_secs is 0
+const RESTART_WAIT: Duration = Duration::from_secs(20);
+
+// How often the supervisor rebuilds pipelines that died for good and retries failed starts
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+// How often recording schedules are re-evaluated
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+
+// How often free space on the recordings disk is checked
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// CPU usage is sampled this often. One more priority level is shed after every
+// LOAD_SHED_SAMPLES busy samples in a row, and one restored after every LOAD_RESTORE_SAMPLES
+// calm ones, so single spikes don't flap it.
+const LOAD_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const LOAD_SHED_SAMPLES: u32 = 2;
+const LOAD_RESTORE_SAMPLES: u32 = 6;
+// Calm means this many percentage points below load_shed_cpu
+const LOAD_RESTORE_MARGIN: f64 = 15.0;
+
+// How often startup checks whether the cameras require_streams asks for are online
+const STREAMS_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// How often viewers get the pipeline's own numbers, with the pipeline_stats option
+const PIPELINE_STATS_INTERVAL: Duration = Duration::from_secs(2);
+
+// How often viewer counts are checked against the cameras' quality_ladder
+const VIEWER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// A stream with no frame for this long is shown as "NO SIGNAL" (when no_signal_frames is on)
+const NO_SIGNAL_AFTER: Duration = Duration::from_secs(3);
+
+// How often the "NO SIGNAL" placeholder is sent while a stream is down
+const NO_SIGNAL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Default static assets compiled into the binary so it can run without a static/ dir
+const EMBEDDED_ASSETS: &[(&str, &str, &[u8])] = &[
+    ("favicon.svg", "image/svg+xml", include_bytes!("../assets/favicon.svg")),
+    ("nvr.css", "text/css; charset=utf-8", include_bytes!("../assets/nvr.css")),
+    ("nvr.js", "application/javascript; charset=utf-8", include_bytes!("../assets/nvr.js")),
+];
+
+// A preview frame as broadcast to viewers
+#[derive(Clone)]
+struct Frame {
+    jpeg: Vec<u8>,
+    // Region that changed since the previous frame, when motion detection is on
+    motion_box: Option<MotionBox>,
+    // Per-stream, increases by one for every frame broadcast. Gaps seen by a client
+    // are delivery-side drops (queue overflow or a lagging client).
+    seq: u64,
+    // When the frame was captured: the camera's own NTP time from RTCP sender reports
+    // when capture_timestamps is on and the camera sends them, else when it got here
+    captured_at: DateTime<Utc>,
+    camera_clock: bool,
+}
+
+// Handed out to WebSocket viewers in the hello message, unique for the server's lifetime
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// Seconds between the NTP epoch (1900) and the Unix epoch
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+// Cheap sanity check for a whole JPEG: SOI first, a start of scan somewhere, and EOI at
+// the end (some encoders pad after it with zeros). Catches truncated and garbled frames,
+// not corrupt entropy-coded data.
+fn is_complete_jpeg(jpeg: &[u8]) -> bool {
+    let end = jpeg.iter().rposition(|&byte| byte != 0).map_or(0, |last| last + 1);
+    let jpeg = &jpeg[..end];
+    jpeg.len() >= 4
+        && jpeg.starts_with(&[0xFF, 0xD8])
+        && jpeg.ends_with(&[0xFF, 0xD9])
+        && jpeg.windows(2).any(|marker| marker == [0xFF, 0xDA])
+}
+
+// Capture time rtspsrc derived from the camera's RTCP sender reports, if any
+fn camera_capture_time(buffer: &gst::BufferRef) -> Option<DateTime<Utc>> {
+    let meta = buffer
+        .iter_meta::<gst::ReferenceTimestampMeta>()
+        .find(|meta| meta.reference().structure(0).is_some_and(|s| s.name() == "timestamp/x-ntp"))?;
+    let unix_nanos = meta.timestamp().nseconds().checked_sub(NTP_UNIX_OFFSET_SECS * 1_000_000_000)?;
+    Some(DateTime::from_timestamp_nanos(i64::try_from(unix_nanos).ok()?))
+}
+
+// Server-wide settings for WebSocket viewers
+#[derive(Clone, Copy)]
+struct ViewerOptions {
+    drop_log_interval: Duration,
+    // Zero disables heartbeats
+    heartbeat: Duration,
+    // Unsent bytes a viewer may fall behind by before it's disconnected
+    max_send_buffer: usize,
+    // Send {"type":"pipeline"} messages every PIPELINE_STATS_INTERVAL
+    pipeline_stats: bool,
+}
+
+#[derive(Deserialize)]
+struct WsQuery {
+    quality: Option<Quality>,
+    // Start zoomed in, "x,y,w,h" as with the roi command
+    roi: Option<String>,
+}
+
+// Which of a camera's streams a viewer watches. Main unless asked otherwise, so
+// clients that don't know about substreams keep getting what they always got.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Quality {
+    #[default]
+    Main,
+    Sub,
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    // RFC 3339, only events received after it
+    since: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ThumbnailQuery {
+    w: Option<u32>,
+}
+
+// Body of PUT /api/streams/:stream_name/quality, and its reply with the quality applied
+#[derive(Deserialize, Serialize)]
+struct JpegQuality {
+    jpeg_quality: u32,
+}
+
+// Commands viewers send over their WebSocket as JSON text messages
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum ControlMessage {
+    // Toggle motion bounding boxes for this client only
+    Overlay { enabled: bool },
+    // Precede every frame with {"type":"frame","seq":N,"captured_at":..,"clock":"camera"|"server"}
+    Sequence { enabled: bool },
+    // Stop sending frames to this client, the subscription stays so resuming is instant
+    Pause { enabled: bool },
+    // Replay buffered frames from `offset_ms` (negative) ago, then return to live.
+    // Zero or positive goes back to live right away.
+    Seek { offset_ms: i64 },
+    // Zoom in on part of the main stream, as fractions of the picture (0.0-1.0), encoded
+    // for this client alone. Without x, y, w and h back to the shared preview. Only for
+    // cameras with roi_max_clients, answered with {"type":"roi","region":..,"error":..}.
+    Roi { x: Option<f64>, y: Option<f64>, w: Option<f64>, h: Option<f64> },
+}
+
+// Settings shared by every pipeline
+#[derive(Clone)]
+struct PipelineOptions {
+    queue_size: usize,
+    // Number of recent frames kept for post-mortem dumps on error (0 disables)
+    debug_frames: usize,
+    debug_dir: PathBuf,
+    recordings_dir: PathBuf,
+    qos_stats: bool,
+    seek_buffer: Option<Duration>,
+    // Restart pipelines that don't reach Playing this long after (re)starting
+    start_timeout: Option<Duration>,
+    // Custom frame processors, run on every camera after the built-in ones
+    processors: Processors,
+    // load_shed_fps is set, every preview needs a videorate for it to adjust
+    shed_fps: bool,
+    // Shared broadcaster threads, None for a thread per pipeline
+    broadcast_pool: Option<Arc<BroadcastPool>>,
+}
+
+// Everything needed to (re)build a camera pipeline
+#[derive(Clone)]
+struct PipelineSpec {
+    camera: CameraConfig,
+    user: String,
+    pass: String,
+    // Created once per stream in main and kept by every restart and rebuild, so viewers
+    // subscribed before a reconnect keep receiving frames after it
+    tx: broadcast::Sender<Frame>,
+    // Set for `mode: audio` cameras, which get an audio-only pipeline instead
+    audio_tx: Option<broadcast::Sender<AudioPacket>>,
+    // The stream's events channel, for events detected in the pipeline
+    events: broadcast::Sender<CameraEvent>,
+    // Where the on-demand MSE and HLS outputs go, None for substreams and audio-only cameras
+    live: Option<Arc<LiveOutputs>>,
+    stats: Arc<StreamStats>,
+    options: PipelineOptions,
+}
+
+// A running camera pipeline. Dropping it stops the pipelines and the broadcaster thread.
 struct PipelineResources {
-    #[allow(dead_code)]
     pipeline: gst::Pipeline,
-    _main_loop: glib::MainLoop,
+    // None for audio-only pipelines, which broadcast straight from the appsink
+    queue: Option<Arc<FrameQueue>>,
+    _bus_watch: gst::bus::BusWatchGuard,
+    restream: Option<(gst::Pipeline, gst::bus::BusWatchGuard)>,
+}
+
+impl PipelineResources {
+    // A pipeline that fell back to Null with no restart pending won't come back by itself.
+    // One whose reconnect attempts ran out is left alone, until restarted through the API.
+    fn is_alive(&self, stats: &StreamStats) -> bool {
+        self.pipeline.current_state() != gst::State::Null || stats.restarting.load(Ordering::SeqCst) || stats.backoff.gave_up()
+    }
+}
+
+impl Drop for PipelineResources {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+        if let Some((restream, _)) = &self.restream {
+            let _ = restream.set_state(gst::State::Null);
+        }
+        if let Some(queue) = &self.queue {
+            queue.close();
+        }
+    }
+}
+
+// Per-stream lifecycle info. Wall-clock times are kept for display only,
+// uptime is computed from the monotonic `Instant` so clock jumps don't skew it.
+#[derive(Default)]
+struct StreamStats {
+    display_name: String,
+    // Size of the preview JPEGs and the preview_fps cap, from the camera config
+    preview_size: (u32, u32),
+    preview_fps: Option<u32>,
+    // Frame rate the camera delivers, from its caps or else measured
+    source_fps: Mutex<Option<f64>>,
+    started: Mutex<Option<(DateTime<Utc>, Instant)>>,
+    stopped_at: Mutex<Option<DateTime<Utc>>>,
+    last_frame_at: Mutex<Option<DateTime<Utc>>>,
+    frames_received: AtomicU64,
+    // Size of those frames (JPEGs, or Opus packets for audio-only cameras)
+    bytes_received: AtomicU64,
+    source_resolution: Mutex<Option<(u32, u32)>>,
+    // Every decoded frame, ahead of the preview's rate cap
+    frames_decoded: AtomicU64,
+    // What the camera sent over RTP, shared with the decode branch (see decode::attach)
+    source_bytes: Arc<AtomicU64>,
+    reconnect_count: AtomicU64,
+    // Delays between restarts, from the camera's reconnect policy
+    backoff: Backoff,
+    queue_dropped: AtomicU64,
+    motion_score: Mutex<f64>,
+    frames_skipped: AtomicU64,
+    // Exact repeats of the last frame sent, see skip_duplicate_frames. Their bytes count
+    // towards bytes_saved too.
+    duplicate_frames: AtomicU64,
+    bytes_saved: AtomicU64,
+    // Last sequence number handed out, see Frame::seq
+    frame_seq: AtomicU64,
+    // Frames the camera never delivered, going by jumps in buffer timestamps
+    source_frames_missed: AtomicU64,
+    source_gaps: AtomicU64,
+    // Frames dropped for not being a complete JPEG, see drop_corrupt_frames
+    corrupt_frames: AtomicU64,
+    // Arrivals against the steady cadence, only with timestamp_fps
+    timestamp_drift: Mutex<Option<cadence::Drift>>,
+    // Frames slow viewers missed because they fell behind the broadcast channel
+    client_frames_dropped: AtomicU64,
+    // Last JPEG broadcast, for thumbnails
+    latest_frame: Mutex<Option<Frame>>,
+    // Recent frames for the seek command, empty when seek_buffer_secs is 0
+    history: FrameHistory,
+    // Only filled in with the qos_stats option
+    qos: qos::QosStats,
+    restarting: AtomicBool,
+}
+
+impl StreamStats {
+    fn mark_started(&self) {
+        *self.started.lock().unwrap() = Some((Utc::now(), Instant::now()));
+        *self.stopped_at.lock().unwrap() = None;
+    }
+
+    fn mark_stopped(&self) {
+        *self.started.lock().unwrap() = None;
+        *self.stopped_at.lock().unwrap() = Some(Utc::now());
+    }
+
+    fn mark_frame(&self, bytes: usize) {
+        *self.last_frame_at.lock().unwrap() = Some(Utc::now());
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.backoff.reset();
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.frame_seq.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    // Down while restarting, or when the camera stopped delivering frames without an error
+    fn signal_lost(&self) -> bool {
+        if self.stopped_at.lock().unwrap().is_some() {
+            return true;
+        }
+        match *self.last_frame_at.lock().unwrap() {
+            Some(last_frame_at) => (Utc::now() - last_frame_at).to_std().unwrap_or_default() >= NO_SIGNAL_AFTER,
+            None => true,
+        }
+    }
+}
+
+// Bounded hand-off between the appsink callback and the broadcaster thread.
+// When full the oldest frame is dropped, so the streaming thread never waits on delivery.
+struct FrameQueue {
+    frames: Mutex<VecDeque<Frame>>,
+    available: Condvar,
+    capacity: usize,
+    closed: AtomicBool,
+}
+
+impl FrameQueue {
+    fn new(capacity: usize) -> Self {
+        FrameQueue {
+            frames: Mutex::new(VecDeque::with_capacity(capacity)),
+            available: Condvar::new(),
+            capacity: capacity.max(1),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    // Returns true if an older frame had to be dropped to make room
+    fn push(&self, frame: Frame) -> bool {
+        let mut frames = self.frames.lock().unwrap();
+        let dropped = frames.len() >= self.capacity;
+        if dropped {
+            frames.pop_front();
+        }
+        frames.push_back(frame);
+        self.available.notify_one();
+        dropped
+    }
+
+    // Blocks until a frame is available, None once the queue is closed
+    fn pop(&self) -> Option<Frame> {
+        let mut frames = self.frames.lock().unwrap();
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(frame) = frames.pop_front() {
+                return Some(frame);
+            }
+            frames = self.available.wait(frames).unwrap();
+        }
+    }
+
+    // None when empty or closed, for the shared broadcast pool which mustn't block
+    fn try_pop(&self) -> Option<Frame> {
+        if self.closed.load(Ordering::SeqCst) {
+            return None;
+        }
+        self.frames.lock().unwrap().pop_front()
+    }
+
+    fn has_frames(&self) -> bool {
+        !self.closed.load(Ordering::SeqCst) && !self.frames.lock().unwrap().is_empty()
+    }
+
+    fn close(&self) {
+        // Hold the lock so a concurrent pop can't miss the wakeup
+        let _frames = self.frames.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        self.available.notify_all();
+    }
+}
+
+// Hands a stream's queued frames to its viewers, from a thread of its own or, with
+// broadcast_threads, the shared pool
+struct Broadcaster {
+    queue: Arc<FrameQueue>,
+    scheduled: AtomicBool,
+    stream_name: String,
+    stats: Arc<StreamStats>,
+    seek_buffer: Option<Duration>,
+    tx: broadcast::Sender<Frame>,
+}
+
+impl Broadcaster {
+    fn deliver(&self, frame: Frame) {
+        *self.stats.latest_frame.lock().unwrap() = Some(frame.clone());
+        if let Some(keep) = self.seek_buffer {
+            self.stats.history.push(frame.clone(), keep);
+        }
+        let sent = self.tx.send(frame);
+        frame_log!("{}: Frame sent to {} receivers", self.stream_name, sent.unwrap_or(0));
+    }
+}
+
+impl Drain for Broadcaster {
+    fn drain(&self) {
+        while let Some(frame) = self.queue.try_pop() {
+            self.deliver(frame);
+        }
+    }
+
+    fn scheduled(&self) -> &AtomicBool {
+        &self.scheduled
+    }
+
+    fn has_work(&self) -> bool {
+        self.queue.has_frames()
+    }
+}
+
+// Spots frames lost before they reached us (camera or network) from jumps in buffer PTS.
+// Fed at the tee, before preview_fps or a leaky queue can drop anything, so any gap
+// seen there is source-side.
+#[derive(Default)]
+struct GapDetector {
+    last_pts: Option<gst::ClockTime>,
+    // Smoothed frame interval in ns, for cameras whose caps don't carry a framerate
+    average_interval: Option<f64>,
+}
+
+impl GapDetector {
+    // Number of frames missing between the previous buffer and this one
+    fn observe(&mut self, pts: Option<gst::ClockTime>, discont: bool, framerate: Option<f64>) -> u64 {
+        let Some(pts) = pts else { return 0 };
+        let previous = self.last_pts.replace(pts);
+        
+        // A discontinuity or timestamps going backwards (pipeline restart) starts over
+        let Some(delta) = previous.filter(|_| !discont).and_then(|previous| pts.checked_sub(previous)) else {
+            return 0;
+        };
+        let delta = delta.nseconds() as f64;
+        if delta <= 0.0 {
+            return 0;
+        }
+        
+        let interval = framerate.map(|fps| 1e9 / fps).or(self.average_interval);
+        
+        // Only learn from deltas that don't look like gaps themselves
+        self.average_interval = match self.average_interval {
+            Some(average) if delta < average * 1.5 => Some(average * 0.9 + delta * 0.1),
+            Some(average) => Some(average),
+            None => Some(delta),
+        };
+        
+        match interval {
+            Some(interval) => ((delta / interval).round() as u64).saturating_sub(1),
+            None => 0,
+        }
+    }
+}
+
+// Who connected to which stream and when. IPs can be left out for privacy,
+// in which case clients show up as "-".
+struct AccessLog {
+    log_ips: bool,
+    file: Option<Mutex<File>>,
+    // Structured counterpart for compliance, see audit
+    audit: Arc<AuditLog>,
+}
+
+impl AccessLog {
+    fn client_label(&self, addr: Option<SocketAddr>) -> String {
+        match addr {
+            Some(addr) if self.log_ips => addr.ip().to_string(),
+            _ => "-".to_string(),
+        }
+    }
+
+    fn record(&self, client: &str, stream_name: &str, event: &str) {
+        let Some(file) = &self.file else { return };
+        let line = format!("{} {} {} {}\n", Utc::now().to_rfc3339(), client, stream_name, event);
+        if let Err(err) = file.lock().unwrap().write_all(line.as_bytes()) {
+            println!("Failed to write access log: {:?}", err);
+        }
+    }
+}
+
+// Rejection for clients over the WebSocket connection rate limit, answered with 429
+#[derive(Debug)]
+struct RateLimited;
+
+impl warp::reject::Reject for RateLimited {}
+
+// What viewers of one stream subscribe to. Entries are inserted complete before any
+// route is served, so a stream is either fully present or not found at all.
+#[derive(Clone)]
+struct StreamChannels {
+    frames: broadcast::Sender<Frame>,
+    // Frames of the camera's substream, when it has one
+    sub_frames: Option<broadcast::Sender<Frame>>,
+    // Events the camera reports itself
+    events: broadcast::Sender<CameraEvent>,
+    // The last event_history of them, None when that's 0
+    event_log: Option<Arc<EventLog>>,
+    // Opus packets of `mode: audio` cameras
+    audio: Option<broadcast::Sender<AudioPacket>>,
+    // MSE and HLS outputs of video cameras
+    live: Option<Arc<LiveOutputs>>,
+    stats: Arc<StreamStats>,
+}
+
+// JSON shape returned by GET /api/streams
+#[derive(Serialize)]
+struct StreamInfo {
+    name: String,
+    display_name: String,
+    running: bool,
+    started_at: Option<DateTime<Utc>>,
+    stopped_at: Option<DateTime<Utc>>,
+    uptime_secs: Option<u64>,
+    last_frame_at: Option<DateTime<Utc>>,
+    source_resolution: Option<(u32, u32)>,
+    reconnect_count: u64,
+    reconnect: BackoffState,
+    queue_dropped: u64,
+    motion_score: f64,
+    frames_skipped: u64,
+    duplicate_frames: u64,
+    bytes_saved: u64,
+    // Sequence number of the last frame broadcast
+    frame_seq: u64,
+    // Source-side loss, as opposed to delivery-side `queue_dropped`
+    source_frames_missed: u64,
+    source_gaps: u64,
+    subscribers: usize,
+}
+
+// JSON shape returned by GET /api/metrics/:stream_name
+#[derive(Serialize)]
+struct StreamMetrics {
+    name: String,
+    // Null while the pipeline isn't running
+    uptime_secs: Option<u64>,
+    // Delivery side, from the appsink onwards
+    frames_received: u64,
+    bytes_received: u64,
+    frame_seq: u64,
+    queue_dropped: u64,
+    frames_skipped: u64,
+    duplicate_frames: u64,
+    source_frames_missed: u64,
+    // Malformed JPEGs, dropped with drop_corrupt_frames
+    corrupt_frames: u64,
+    // How far frame arrivals are off the timestamp_fps cadence, null without it
+    timestamp_drift: Option<cadence::Drift>,
+    subscribers: usize,
+    // Summed over all viewers, past and present
+    client_frames_dropped: u64,
+    // Pipeline side, null unless qos_stats is enabled
+    qos: Option<qos::QosSnapshot>,
+    // Server-wide: some previews are currently degraded because the CPU is saturated
+    load_shedding: bool,
+    // This stream's priority and preview settings as they are now, after any load
+    // shedding. Null when the preview isn't running.
+    preview: Option<EffectivePreview>,
+}
+
+// JSON shape returned by GET /api/recordings
+#[derive(Serialize)]
+struct RecordingInfo {
+    name: String,
+    // "recording", "disk_full" (should be recording but paused for space) or "idle",
+    // idle also while the pipeline isn't running
+    state: &'static str,
+    scheduled: bool,
+    timezone: Option<String>,
+    next_change: Option<DateTime<Utc>>,
 }
 
 #[tokio::main]
@@ -25,110 +686,1010 @@ async fn main() -> Result<()> {
     // Load .env file
     dotenv::dotenv().ok();
     
+    let cli = Cli::parse();
+    
+    // For editors and CI, nothing else is read or started
+    if cli.print_schema {
+        let schema = schemars::schema_for!(config::Config);
+        println!("{}", serde_json::to_string_pretty(&schema)?);
+        return Ok(());
+    }
+    
+    // Resolve and check the config before touching GStreamer
+    let settings = match config::resolve_config(&cli) {
+        Ok(settings) => settings,
+        Err(err) if cli.validate_config => {
+            eprintln!("Config is invalid: {:#}", err);
+            std::process::exit(1);
+        }
+        Err(err) => return Err(err),
+    };
+    if settings.debug {
+        settings.print_sources();
+    }
+    
+    // Sanity-check large deployments before they thrash the box
+    let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let estimate = settings.estimated_cores();
+    println!(
+        "Estimated load: {:.1} of {} CPU core(s) for {} stream(s)",
+        estimate, cores, settings.cameras.len()
+    );
+    if estimate > cores as f64 {
+        println!(
+            "Warning: the configured streams likely need more CPU than this machine has. \
+             Install a hardware decoder (VA-API, NVDEC, V4L2) and set decoder: hardware, \
+             lower preview resolutions or run fewer streams."
+        );
+    }
+    
+    let problems = settings.validate();
+    for problem in &problems {
+        eprintln!("Config problem: {}", problem);
+    }
+    if cli.validate_config {
+        if !problems.is_empty() {
+            eprintln!("Config is invalid: {} problem(s)", problems.len());
+            std::process::exit(1);
+        }
+        println!("Config is valid: {} camera(s)", settings.cameras.len());
+        return Ok(());
+    }
+    if !problems.is_empty() {
+        anyhow::bail!("Refusing to start with {} config problem(s)", problems.len());
+    }
+    let settings = settings.expand_env()?;
+    for warning in settings.connection_warnings() {
+        println!("Warning: {}", warning);
+    }
+    
     // Initialize GStreamer
     gst::init()?;
     
-    // Get credentials
-    let user = env::var("CCTV_CRED_USER").unwrap_or_else(|_| "admin".to_string());
-    let pass = env::var("CCTV_CRED_PASS").unwrap_or_else(|_| "aaaa1111".to_string());
+    // A hardware decoder can be registered yet unable to open this machine's GPU, find out
+    // before a camera picks it
+    let hardware_decoders = settings.probe_hw_decoders.then(|| {
+        let probes = decode::probe_hardware();
+        if probes.is_empty() {
+            println!("No hardware decoders installed");
+        }
+        for probe in &probes {
+            match &probe.error {
+                None => println!("Hardware decoder {}: usable", probe.element),
+                Some(error) => println!("Hardware decoder {}: unusable, {}; falling back past it", probe.element, error),
+            }
+        }
+        probes
+    });
+    
+    // Stand-in cameras for the fake{N} entries resolve_config added
+    #[cfg(feature = "fake-source")]
+    let mut settings = settings;
+    #[cfg(feature = "fake-source")]
+    let _fake_source = match cli.fake_cameras {
+        Some(count) if count > 0 => {
+            let server = fake_source::start(count)?;
+            server.point_cameras(&mut settings.cameras);
+            Some(server)
+        }
+        _ => None,
+    };
+    
+    // Plain RTSP for the cameras with rtsp_relay, fed from their pipelines' relay branches
+    #[cfg(feature = "rtsp-relay")]
+    let _rtsp_relay = match settings.cameras.iter().any(|camera| camera.rtsp_relay) {
+        true => Some(rtsp_relay::start(settings.rtsp_relay_address, &settings.cameras)?),
+        false => None,
+    };
     
-    // Collect all RTSP URLs from environment
-    let mut rtsp_streams = HashMap::new();
-    for (key, value) in env::vars() {
-        if key.starts_with("CCTV_") && !key.starts_with("CCTV_CRED_") {
-            rtsp_streams.insert(key, value);
+    // Recording cameras need an encoder for their codec, better to find out now than per pipeline
+    for camera in settings.cameras.iter().filter(|camera| camera.record) {
+        match recorder::select_encoder(camera.recording_codec) {
+            Some(encoder) => println!("{}: Recording {:?} with {}", camera.name, camera.recording_codec, encoder),
+            None => anyhow::bail!(
+                "{}: no {:?} encoder available, install the GStreamer plugin for it (x264/x265 or nvcodec)",
+                camera.name,
+                camera.recording_codec
+            ),
         }
     }
     
-    println!("Found {} RTSP streams", rtsp_streams.len());
+    // Client IP logging, optionally mirrored to an append-only access log
+    let access_log_file = match &settings.access_log {
+        Some(path) => Some(Mutex::new(File::options().create(true).append(true).open(path)?)),
+        None => None,
+    };
+    let audit = Arc::new(AuditLog::open(
+        settings.audit_log.as_deref(),
+        settings.audit_log_max_mb * 1024 * 1024,
+        settings.audit_log_keep,
+        settings.log_client_ips,
+    )?);
+    let access_log = Arc::new(AccessLog { log_ips: settings.log_client_ips, file: access_log_file, audit: audit.clone() });
+    
+    // Throttle WebSocket connection attempts per client IP
+    let rate_limiter = Arc::new(RateLimiter::new(
+        settings.ws_rate_limit,
+        settings.ws_rate_burst,
+        settings.ws_rate_allow.clone(),
+    ));
+    
+    // Base directory recorded clips are served from
+    let recordings_dir = settings.recordings_dir.clone();
+    
+    let options = PipelineOptions {
+        queue_size: settings.frame_queue_size,
+        debug_frames: settings.debug_frames,
+        debug_dir: settings.debug_dir.clone(),
+        recordings_dir: settings.recordings_dir.clone(),
+        qos_stats: settings.qos_stats,
+        seek_buffer: (settings.seek_buffer_secs > 0).then(|| Duration::from_secs(settings.seek_buffer_secs)),
+        start_timeout: (settings.pipeline_start_timeout_secs > 0).then(|| Duration::from_secs(settings.pipeline_start_timeout_secs)),
+        // Register custom processing here, e.g. Arc::new(MyAnalytics::new())
+        processors: Vec::new(),
+        shed_fps: settings.load_shed_fps > 0,
+        broadcast_pool: (settings.broadcast_threads > 0).then(|| BroadcastPool::new(settings.broadcast_threads)),
+    };
+    
+    println!("Found {} RTSP streams", settings.cameras.len());
     
     // Store clients and their broadcast channels
     let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
+    let stats: Stats = Arc::new(Mutex::new(HashMap::new()));
+    let shed_limits = ShedLimits {
+        quality: settings.load_shed_quality,
+        fps: (settings.load_shed_fps > 0).then_some(settings.load_shed_fps),
+    };
+    let supervisor = PipelineSupervisor::new(audit.clone(), shed_limits);
+    let mut snapshot_sources = HashMap::new();
+    
+    // Video streams in config order, for the generated page
+    let mut page_streams = Vec::new();
+    // With --bench the benchmark starts these itself, one at a time
+    let mut bench_specs = Vec::new();
     
     // Create a pipeline for each stream
-    for (name, url) in rtsp_streams {
-        println!("Setting up pipeline for {}: {}", name, url);
+    for camera in settings.cameras.clone() {
+        let name = camera.name.clone();
+        let display_name = camera.display_name.clone().unwrap_or_else(|| name.clone());
+        // Audio-only cameras have no tile, they're listened to at /ws/audio/<name>
+        if camera.mode == CameraMode::Video {
+            page_streams.push(PageStream {
+                name: name.clone(),
+                display_name: display_name.clone(),
+                has_substream: camera.substream_url.is_some(),
+                interpolate: camera.interpolate_frames,
+                size: (camera.width, camera.height),
+                scale_mode: camera.scale_mode,
+            });
+        }
+        println!("Setting up pipeline for {}: {}", name, camera.source.as_deref().unwrap_or(&camera.url));
         
-        // Create broadcast channel for this stream with larger buffer
+        // Create broadcast channels for this stream with larger buffer
         let (tx, _) = broadcast::channel(100); // Increase buffer size
-        {
-            let mut clients_lock = clients.lock().unwrap();
-            clients_lock.insert(name.clone(), vec![tx.clone()]);
-        }
+        let (events_tx, _) = broadcast::channel(100);
+        let sub_tx = camera.substream_url.as_ref().map(|_| broadcast::channel(100).0);
+        let audio_tx = (camera.mode == CameraMode::Audio).then(|| broadcast::channel(100).0);
+        let live = (camera.mode == CameraMode::Video).then(|| Arc::new(LiveOutputs::new(settings.hls_dir.join(&name))));
+        let stream_stats = Arc::new(StreamStats {
+            display_name,
+            preview_size: (camera.width, camera.height),
+            preview_fps: camera.preview_fps,
+            backoff: Backoff::new(camera.reconnect),
+            ..Default::default()
+        });
+        let event_log = (settings.event_history > 0).then(|| {
+            let event_log = Arc::new(EventLog::new(settings.event_history));
+            event_log.follow(&events_tx);
+            event_log
+        });
+        clients.lock().unwrap().insert(name.clone(), StreamChannels {
+            frames: tx.clone(),
+            sub_frames: sub_tx.clone(),
+            events: events_tx.clone(),
+            event_log,
+            audio: audio_tx.clone(),
+            live: live.clone(),
+            stats: stream_stats.clone(),
+        });
+        stats.lock().unwrap().insert(name.clone(), stream_stats.clone());
         
         // Clone for closure
         let tx_clone = tx.clone();
-        let stream_name = name.clone();
-        let user_clone = user.clone();
-        let pass_clone = pass.clone();
+        let (user_clone, pass_clone) = settings.camera_credentials(&camera);
+        let options_clone = options.clone();
         
-        // Setup pipeline in a separate thread
-        std::thread::spawn(move || {
-            if let Err(e) = setup_pipeline(&url, &user_clone, &pass_clone, tx_clone, stream_name) {
+        if let Some(onvif_url) = &camera.onvif_url {
+            onvif::spawn_pull_point(name.clone(), onvif_url.clone(), user_clone.clone(), pass_clone.clone(), events_tx.clone());
+        }
+        
+        if let Some(snapshot_uri) = &camera.snapshot_uri {
+            snapshot_sources.insert(name.clone(), SnapshotSource {
+                uri: snapshot_uri.clone(),
+                user: user_clone.clone(),
+                pass: pass_clone.clone(),
+            });
+        }
+        
+        if settings.no_signal_frames && camera.mode == CameraMode::Video {
+            spawn_no_signal(&camera, tx.clone(), stream_stats.clone());
+        }
+        
+        // The substream only feeds viewers, recording and restreaming stay on the main stream
+        if let (Some(substream_url), Some(sub_tx)) = (&camera.substream_url, sub_tx) {
+            let sub_camera = CameraConfig {
+                name: format!("{}@sub", name),
+                url: substream_url.clone(),
+                restream_url: None,
+                rtsp_relay: false,
+                onvif_url: None,
+                snapshot_uri: None,
+                // In the main stream's pixels, which the substream doesn't share
+                crop: None,
+                tamper_detection: false,
+                snapshot_on_motion: false,
+                record: false,
+                recording_schedule: None,
+                // Zoomed views are cut from the main stream
+                roi_max_clients: 0,
+                ..camera.clone()
+            };
+            let sub_spec = PipelineSpec {
+                camera: sub_camera,
+                user: user_clone.clone(),
+                pass: pass_clone.clone(),
+                tx: sub_tx,
+                audio_tx: None,
+                events: events_tx.clone(),
+                live: None,
+                stats: Arc::new(StreamStats { backoff: Backoff::new(camera.reconnect), ..Default::default() }),
+                options: options_clone.clone(),
+            };
+            let supervisor_start = supervisor.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = supervisor_start.start(sub_spec) {
+                    eprintln!("Substream pipeline error: {:?}", e);
+                }
+            });
+        }
+        
+        // Pipeline setup blocks on state changes, keep it off the async workers
+        let spec = PipelineSpec {
+            camera,
+            user: user_clone,
+            pass: pass_clone,
+            tx: tx_clone,
+            audio_tx,
+            events: events_tx,
+            live,
+            stats: stream_stats,
+            options: options_clone,
+        };
+        if cli.bench.is_some() {
+            bench_specs.push(spec);
+            continue;
+        }
+        let supervisor_start = supervisor.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = supervisor_start.start(spec) {
                 eprintln!("Pipeline error: {:?}", e);
             }
         });
     }
     
+    // Measure how many of the synthetic cameras this machine keeps up with, then exit
+    if cli.bench.is_some() {
+        let source = config::bench_source(&cli);
+        let label = format!("{} fps", source.trim_start_matches("test:"));
+        let step = Duration::from_secs(cli.bench_step_secs.unwrap_or(10).max(1));
+        return bench::run(bench_specs, supervisor, cli.bench_fps.unwrap_or(15), step, &label).await;
+    }
+    
+    // Give up now if the cameras require_streams asks for don't come online
+    if settings.require_streams != config::RequireStreams::None {
+        if let Err(err) = wait_for_streams(&settings, &stats).await {
+            supervisor.shutdown();
+            return Err(err);
+        }
+    }
+    
+    // Sample every stream's state for /ws/status subscribers
+    let status_hub = Arc::new(StatusHub::new());
+    spawn_status_monitor(stats.clone(), status_hub.clone());
+    
+    // Periodically rebuild pipelines that are gone for good, off the async workers
+    // since setting up a pipeline blocks
+    let supervisor_reap = supervisor.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let supervisor_reap = supervisor_reap.clone();
+            let _ = tokio::task::spawn_blocking(move || supervisor_reap.reap()).await;
+        }
+    });
+    
+    // Start and pause scheduled recordings
+    let supervisor_schedule = supervisor.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+        loop {
+            interval.tick().await;
+            supervisor_schedule.apply_recording_state(Utc::now());
+        }
+    });
+    
+    // Pause recording (or prune old segments) while the recordings disk is low on space
+    if settings.cameras.iter().any(|camera| camera.record) {
+        spawn_disk_monitor(&settings, supervisor.clone(), clients.clone());
+    }
+    
+    // Lower preview quality, by camera priority, while the CPU is saturated
+    if settings.load_shed_cpu > 0 {
+        spawn_load_monitor(&settings, supervisor.clone());
+    }
+    
+    // Lower preview quality as a camera's viewer count grows, and run on-demand previews
+    // only while watched
+    if settings.cameras.iter().any(|camera| !camera.quality_ladder.is_empty() || camera.preview_on_demand) {
+        spawn_viewer_monitor(&settings, supervisor.clone(), clients.clone());
+    }
+    
+    // Push per-stream metrics to InfluxDB
+    if let Some(url) = &settings.influx_url {
+        spawn_influx_exporter(&settings, url.clone(), clients.clone(), stats.clone(), supervisor.clone())?;
+    }
+    
     // Create HTML file with video elements for each stream
-    create_html_file(&clients.lock().unwrap().keys().cloned().collect::<Vec<_>>())?;
+    create_html_file(&page_streams, &settings)?;
     
     // Create WS handler for streams
     let clients_filter = warp::any().map(move || clients.clone());
+    let stats_filter = warp::any().map(move || stats.clone());
+    let access_log_filter = warp::any().map(move || access_log.clone());
     
     // GET /stream => HTML page
     let stream_route = warp::path("stream")
         .and(warp::get())
-        .and(warp::fs::file("src/index.html"));
+        .and(warp::fs::file(settings.page_dir.join("index.html")));
     
-    // GET /static/... => static files
+    // GET /static/... => embedded assets, falling back to files in static/
     let static_route = warp::path("static")
-        .and(warp::fs::dir("static"));
-    
-    // GET /ws/:stream_name => websocket upgrade
-    let ws_route = warp::path("ws")
         .and(warp::path::param::<String>())
-        .and(warp::ws())
-        .and(clients_filter)
-        .map(|stream_name: String, ws: warp::ws::Ws, clients: Clients| {
-            ws.on_upgrade(move |socket| handle_ws_client(socket, clients, stream_name))
+        .and(warp::path::end())
+        .and_then(|name: String| async move {
+            embedded_asset(&name).ok_or_else(warp::reject::not_found)
+        })
+        .or(warp::path("static").and(warp::fs::dir("static")));
+    
+    // GET /favicon.ico => embedded favicon, for browsers that ignore the <link rel="icon">
+    let favicon_route = warp::path("favicon.ico")
+        .and(warp::path::end())
+        .and_then(|| async {
+            embedded_asset("favicon.svg").ok_or_else(warp::reject::not_found)
         });
     
-    // Combine routes
-    let routes = stream_route
-        .or(static_route)
-        .or(ws_route);
+    // GET /api/streams => per-stream status as JSON
+    let api_streams_route = warp::path!("api" / "streams")
+        .and(warp::get())
+        .and(clients_filter.clone())
+        .and(stats_filter.clone())
+        .map(|clients: Clients, stats: Stats| warp::reply::json(&stream_infos(&clients, &stats)));
     
-    println!("Web server starting on http://localhost:3030");
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+    // GET /api/manifest => this instance and all its streams, for dashboards aggregating several NVRs
+    let manifest = Arc::new(ManifestSource::new(&settings, hardware_decoders));
+    let api_manifest_route = warp::path!("api" / "manifest")
+        .and(warp::get())
+        .and(stats_filter.clone())
+        .map(move |stats: Stats| warp::reply::json(&manifest.render(&stats)));
     
-    Ok(())
-}
-
-fn setup_pipeline(url: &str, user: &str, pass: &str, tx: broadcast::Sender<Vec<u8>>, stream_name: String) -> Result<()> {
-    println!("{}: Setting up new pipeline", stream_name);
+    // PUT /api/streams/:stream_name/quality {"jpeg_quality": n} => change preview quality live
+    let supervisor_quality = supervisor.clone();
+    let api_quality_route = warp::path!("api" / "streams" / String / "quality")
+        .and(warp::put())
+        .and(warp::body::content_length_limit(1024))
+        .and(warp::body::json::<JpegQuality>())
+        .map(move |stream_name: String, request: JpegQuality| {
+            use warp::Reply;
+            if request.jpeg_quality > 100 {
+                let message = format!("jpeg_quality {} is outside 0-100", request.jpeg_quality);
+                return warp::reply::with_status(message, warp::http::StatusCode::BAD_REQUEST).into_response();
+            }
+            match supervisor_quality.set_jpeg_quality(&stream_name, request.jpeg_quality) {
+                Some(jpeg_quality) => warp::reply::json(&JpegQuality { jpeg_quality }).into_response(),
+                None => warp::reply::with_status("No running preview for that stream", warp::http::StatusCode::NOT_FOUND).into_response(),
+            }
+        });
     
-    // Build a much simpler pipeline
-    let pipeline_str = format!(
-        "rtspsrc location={} user-id={} user-pw={} ! decodebin ! videoconvert ! videoscale ! video/x-raw,width=640,height=360 ! jpegenc quality=70 ! appsink name=sink emit-signals=true sync=false",
-        url, user, pass
-    );
+    // POST /api/streams/:stream_name/restart => rebuild a stuck pipeline, viewers stay connected
+    let supervisor_restart = supervisor.clone();
+    let audit_restart = audit.clone();
+    let restart_wait = options.start_timeout.unwrap_or(RESTART_WAIT);
+    let api_restart_route = warp::path!("api" / "streams" / String / "restart")
+        .and(warp::post())
+        .and(clients_filter.clone())
+        .and(warp::addr::remote())
+        .and_then(move |stream_name: String, clients: Clients, addr: Option<SocketAddr>| {
+            let supervisor = supervisor_restart.clone();
+            let audit = audit_restart.clone();
+            async move {
+                use warp::Reply;
+                let Some((key, _)) = find_stream(&clients, &stream_name) else {
+                    return Err(warp::reject::not_found());
+                };
+                audit.record("restart", Some(&key), addr.map(|addr| addr.ip()), None);
+                let name = key.clone();
+                let restarted = tokio::task::spawn_blocking(move || supervisor.restart(&name, restart_wait)).await.ok().flatten();
+                let reply = match restarted {
+                    Some(Ok(true)) => warp::reply::json(&serde_json::json!({ "name": key, "state": "playing" })).into_response(),
+                    Some(Ok(false)) => {
+                        let error = format!("not playing after {}s, still retrying", restart_wait.as_secs());
+                        let body = warp::reply::json(&serde_json::json!({ "name": key, "error": error }));
+                        warp::reply::with_status(body, warp::http::StatusCode::GATEWAY_TIMEOUT).into_response()
+                    }
+                    Some(Err(err)) => {
+                        // Unreachable cameras are the camera's fault, broken setups ours
+                        let status = match err.is_retryable() {
+                            true => warp::http::StatusCode::BAD_GATEWAY,
+                            false => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                        };
+                        let body = warp::reply::json(&serde_json::json!({ "name": key, "error": err.to_string() }));
+                        warp::reply::with_status(body, status).into_response()
+                    }
+                    None => return Err(warp::reject::not_found()),
+                };
+                Ok(reply)
+            }
+        });
     
-    println!("{}: Pipeline string: {}", stream_name, pipeline_str);
+    // GET /api/metrics/:stream_name => delivery metrics plus GStreamer's QoS numbers
+    let qos_stats = settings.qos_stats;
+    let supervisor_metrics = supervisor.clone();
+    let api_metrics_route = warp::path!("api" / "metrics" / String)
+        .and(warp::get())
+        .and(clients_filter.clone())
+        .and(stats_filter.clone())
+        .and_then(move |stream_name: String, clients: Clients, stats: Stats| {
+            let supervisor = supervisor_metrics.clone();
+            async move {
+                match stream_metrics(&clients, &stats, &stream_name, qos_stats, &supervisor) {
+                    Some(metrics) => Ok(warp::reply::json(&metrics)),
+                    None => Err(warp::reject::not_found()),
+                }
+            }
+        });
     
-    // Parse and create the pipeline
-    let pipeline = gst::parse::launch(&pipeline_str)?;
-    let pipeline = pipeline.downcast::<gst::Pipeline>().unwrap();
+    // GET /api/events/:stream_name?since=<RFC 3339> => the stream's recent events, oldest first
+    let api_events_route = warp::path!("api" / "events" / String)
+        .and(warp::get())
+        .and(warp::query::<EventsQuery>())
+        .and(clients_filter.clone())
+        .and_then(|stream_name: String, query: EventsQuery, clients: Clients| async move {
+            let Some((key, channels)) = find_stream(&clients, &stream_name) else {
+                return Err(warp::reject::not_found());
+            };
+            let Some(event_log) = channels.event_log else {
+                return Err(warp::reject::not_found());
+            };
+            let body = serde_json::json!({ "stream": key, "events": event_log.since(query.since) });
+            Ok(warp::reply::json(&body))
+        });
     
-    // Get the appsink element
-    let appsink = pipeline
-        .by_name("sink")
-        .expect("Couldn't find appsink")
-        .downcast::<gst_app::AppSink>()
-        .unwrap();
+    // Configured extra headers for the snapshot/thumbnail and SSE responses, by stream
+    let extra_headers = Arc::new(response_headers(&settings));
     
-    // Create a clone for the closure
-    let stream_name_sample = stream_name.clone();
+    // GET /api/thumbnail/:stream?w=160 => small JPEG of the camera's snapshot URI, or the latest frame
+    let thumbnails = Arc::new(ThumbnailCache::default());
+    let snapshot_sources = Arc::new(snapshot_sources);
+    let audit_thumbnail = audit.clone();
+    let headers_thumbnail = extra_headers.clone();
+    let api_thumbnail_route = warp::path!("api" / "thumbnail" / String)
+        .and(warp::get())
+        .and(warp::query::<ThumbnailQuery>())
+        .and(stats_filter)
+        .and(warp::addr::remote())
+        .and_then(move |stream_name: String, query: ThumbnailQuery, stats: Stats, addr: Option<SocketAddr>| {
+            let width = query.w.unwrap_or(thumbnail::DEFAULT_WIDTH);
+            let audit = audit_thumbnail.clone();
+            let headers = headers_thumbnail.clone();
+            let thumbnail = serve_thumbnail(thumbnails.clone(), snapshot_sources.clone(), stats, stream_name.clone(), width);
+            async move {
+                let mut reply = thumbnail.await?;
+                if reply.status().is_success() {
+                    audit.record("snapshot", Some(&stream_name), addr.map(|addr| addr.ip()), None);
+                    add_headers(&mut reply, headers.get(&stream_name.to_lowercase()));
+                }
+                Ok::<_, warp::Rejection>(reply)
+            }
+        });
+    
+    // GET /api/recordings => recording state and schedule of each recording camera
+    let recording_cameras: Vec<CameraConfig> = settings.cameras.iter().filter(|camera| camera.record).cloned().collect();
+    let supervisor_recordings = supervisor.clone();
+    let api_recordings_route = warp::path!("api" / "recordings")
+        .and(warp::get())
+        .map(move || warp::reply::json(&recording_infos(&recording_cameras, &supervisor_recordings)));
+    
+    // GET /recordings/:stream/:file => recorded clip, honouring Range requests
+    let audit_recordings = audit.clone();
+    let recordings_route = warp::path!("recordings" / String / String)
+        .and(warp::get())
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::addr::remote())
+        .and_then(move |stream: String, file: String, range: Option<String>, addr: Option<SocketAddr>| {
+            // Players fetch a clip in many ranges, only the first one counts as an export
+            let export = range.as_deref().is_none_or(|range| range.trim().starts_with("bytes=0-"));
+            let audit = audit_recordings.clone();
+            let clip = recordings::serve_recording(recordings_dir.clone(), stream.clone(), file.clone(), range);
+            async move {
+                let reply = clip.await?;
+                if export {
+                    audit.record("clip_export", Some(&stream), addr.map(|addr| addr.ip()), Some(&file));
+                }
+                Ok::<_, warp::Rejection>(reply)
+            }
+        });
+    
+    // Rejects connection attempts over the per-IP rate before the upgrade
+    let rate_limit = warp::addr::remote()
+        .and_then(move |addr: Option<SocketAddr>| {
+            let rate_limiter = rate_limiter.clone();
+            async move {
+                match addr {
+                    Some(addr) if !rate_limiter.check(addr.ip()) => {
+                        println!("Rate limiting WebSocket connections from {}", addr.ip());
+                        Err(warp::reject::custom(RateLimited))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one();
+    
+    // GET /sse/:stream_name => frames as base64 Server-Sent Events, for proxies that block WebSockets
+    let sse_route = warp::path!("sse" / String)
+        .and(warp::get())
+        .and(rate_limit.clone())
+        .and(clients_filter.clone())
+        .and(warp::addr::remote())
+        .and(access_log_filter.clone())
+        .and_then(move |stream_name: String, clients: Clients, addr: Option<SocketAddr>, access_log: Arc<AccessLog>| {
+            let headers = extra_headers.get(&stream_name.to_lowercase()).cloned();
+            handle_sse_client(clients, stream_name, addr, access_log, headers)
+        });
+    
+    // GET /ws/status => status changes of all streams, as JSON text messages
+    let status_route = warp::path!("ws" / "status")
+        .and(rate_limit.clone())
+        .and(warp::ws())
+        .map(move |ws: warp::ws::Ws| {
+            let status_hub = status_hub.clone();
+            ws.on_upgrade(move |socket| handle_status_client(socket, status_hub))
+        });
+    
+    // GET /ws/audio/:stream_name => Opus packets of a `mode: audio` camera
+    let audio_route = warp::path!("ws" / "audio" / String)
+        .and(rate_limit.clone())
+        .and(warp::ws())
+        .and(clients_filter.clone())
+        .and(warp::addr::remote())
+        .and(access_log_filter.clone())
+        .map(|stream_name: String, ws: warp::ws::Ws, clients: Clients, addr: Option<SocketAddr>, access_log: Arc<AccessLog>| {
+            ws.on_upgrade(move |socket| handle_audio_client(socket, clients, stream_name, addr, access_log))
+        });
+    
+    // GET /ws/mse/:stream_name => fragmented MP4 for Media Source Extensions players
+    let supervisor_mse = supervisor.clone();
+    let mse_route = warp::path!("ws" / "mse" / String)
+        .and(rate_limit.clone())
+        .and(warp::ws())
+        .and(clients_filter.clone())
+        .and(warp::addr::remote())
+        .and(access_log_filter.clone())
+        .map(move |stream_name: String, ws: warp::ws::Ws, clients: Clients, addr: Option<SocketAddr>, access_log: Arc<AccessLog>| {
+            let supervisor = supervisor_mse.clone();
+            ws.on_upgrade(move |socket| handle_mse_client(socket, clients, supervisor, stream_name, addr, access_log))
+        });
+    
+    // GET /hls/:stream_name/playlist.m3u8 (and its segments) => HLS for mobile players
+    let supervisor_hls = supervisor.clone();
+    let hls_route = warp::path!("hls" / String / String)
+        .and(warp::get())
+        .and(clients_filter.clone())
+        .and(warp::addr::remote())
+        .and(access_log_filter.clone())
+        .and_then(move |stream_name: String, file: String, clients: Clients, addr: Option<SocketAddr>, access_log: Arc<AccessLog>| {
+            handle_hls_request(clients, supervisor_hls.clone(), stream_name, file, addr, access_log)
+        });
+    
+    // GET /ws/:stream_name?quality=main|sub => websocket upgrade
+    let viewer_options = ViewerOptions {
+        drop_log_interval: Duration::from_secs(settings.drop_log_interval_secs),
+        heartbeat: Duration::from_secs(settings.ws_heartbeat_secs),
+        max_send_buffer: settings.ws_max_send_buffer_kb * 1024,
+        pipeline_stats: settings.pipeline_stats,
+    };
+    let supervisor_ws = supervisor.clone();
+    let ws_route = warp::path("ws")
+        .and(warp::path::param::<String>())
+        .and(warp::query::<WsQuery>())
+        .and(rate_limit)
+        .and(warp::ws())
+        .and(clients_filter)
+        .and(warp::addr::remote())
+        .and(access_log_filter)
+        .map(move |stream_name: String, query: WsQuery, ws: warp::ws::Ws, clients: Clients, addr: Option<SocketAddr>, access_log: Arc<AccessLog>| {
+            let quality = query.quality.unwrap_or_default();
+            let supervisor = supervisor_ws.clone();
+            ws.on_upgrade(move |socket| handle_ws_client(socket, clients, supervisor, stream_name, quality, query.roi, viewer_options, addr, access_log))
+        });
+    
+    // GET / => the stream page, so the bare base path (with or without a trailing slash) works
+    let stream_page = format!("{}/stream", settings.base_path);
+    let index_route = warp::path::end()
+        .and(warp::get())
+        .map(move || warp::redirect::found(stream_page.parse::<warp::http::Uri>().unwrap()));
+    
+    // Mount everything under the configured base path
+    let mut base = warp::any().boxed();
+    for segment in settings.base_path.split('/').filter(|segment| !segment.is_empty()) {
+        base = base.and(warp::path(segment.to_string())).boxed();
+    }
+    
+    // Combine routes: the page, API and recordings, and what viewers stream from
+    let ui_routes = base.clone().and(
+        index_route
+            .or(stream_route)
+            .or(static_route)
+            .or(favicon_route)
+            .or(api_streams_route)
+            .or(api_manifest_route)
+            .or(api_quality_route)
+            .or(api_restart_route)
+            .or(api_metrics_route)
+            .or(api_events_route)
+            .or(api_thumbnail_route)
+            .or(api_recordings_route)
+            .or(recordings_route),
+    );
+    let viewer_routes = base.and(
+        hls_route
+            .or(sse_route)
+            .or(status_route)
+            .or(audio_route)
+            .or(mse_route)
+            .or(ws_route),
+    );
+    
+    // Both servers stop on the same Ctrl-C
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(());
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.ok();
+        println!("Shutting down");
+        let _ = shutdown_tx.send(());
+    });
+    let shutdown = |mut shutdown_rx: tokio::sync::watch::Receiver<()>| async move {
+        let _ = shutdown_rx.changed().await;
+    };
+    
+    let ui_address = SocketAddr::new(settings.bind_address, settings.port);
+    println!("Web server starting on http://localhost:{}{}/stream", settings.port, settings.base_path);
+    match settings.stream_address {
+        None => {
+            let routes = ui_routes.or(viewer_routes).recover(handle_rejection);
+            let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(ui_address, shutdown(shutdown_rx));
+            server.await;
+        }
+        Some(stream_address) => {
+            // The page is loaded from the other port, a different origin as far as
+            // EventSource and HLS players are concerned
+            println!("Viewer streams served on {}", stream_address);
+            let cors = warp::cors().allow_any_origin().allow_method("GET");
+            let (_, ui_server) = warp::serve(ui_routes.recover(handle_rejection))
+                .bind_with_graceful_shutdown(ui_address, shutdown(shutdown_rx.clone()));
+            let (_, stream_server) = warp::serve(viewer_routes.recover(handle_rejection).with(cors))
+                .bind_with_graceful_shutdown(stream_address, shutdown(shutdown_rx));
+            tokio::join!(ui_server, stream_server);
+        }
+    }
+    
+    // Stop every pipeline and the glib main loop so the runtime can exit
+    supervisor.shutdown();
+    
+    Ok(())
+}
+
+fn setup_pipeline(spec: &PipelineSpec) -> error::Result<PipelineResources> {
+    if let Some(audio_tx) = &spec.audio_tx {
+        return audio::setup_audio_pipeline(spec, audio_tx.clone());
+    }
+    let PipelineSpec { camera, user, pass, tx, events, stats, options, .. } = spec.clone();
+    let stream_name = camera.name.clone();
+    println!("{}: Setting up new pipeline", stream_name);
+    
+    // Extra branches hanging off the decoded tee
+    let mut branches = String::new();
+    if camera.restream_url.is_some() {
+        branches.push_str(&format!(
+            " t. ! queue leaky=downstream max-size-buffers=5 ! {}intervideosink channel=restream-{}",
+            rotation::branch_flip(&camera),
+            stream_name
+        ));
+    }
+    #[cfg(feature = "rtsp-relay")]
+    if camera.rtsp_relay {
+        branches.push_str(&rtsp_relay::relay_branch(&stream_name, &rotation::branch_flip(&camera)));
+    }
+    
+    // Segmented recording, in whatever codec the camera is configured for
+    let recording_dir = options.recordings_dir.join(&stream_name);
+    let mut recording_indicator = String::new();
+    if camera.record {
+        let encoder = recorder::select_encoder(camera.recording_codec)
+            .ok_or_else(|| NvrError::ElementMissing(format!("{:?} encoder", camera.recording_codec)))?;
+        std::fs::create_dir_all(&recording_dir)?;
+        let recording = schedule::should_record(&camera, Utc::now());
+        branches.push_str(&recorder::recording_branch(&camera, encoder, recording));
+        if camera.recording_text_track {
+            branches.push_str(&recorder::text_track_branch());
+        }
+        if camera.recording_indicator {
+            recording_indicator = recorder::indicator_element(recording);
+        }
+    }
+    
+    // Through a SOCKS or local_address tunnel only TCP gets across, so RTP has to be interleaved
+    let protocols = match camera.socks_proxy.is_some() || camera.local_address.is_some() {
+        true => " protocols=tcp",
+        false => "",
+    };
+    
+    // Join multicast groups on the camera network's interface, not the default route's
+    let multicast_iface = match &camera.multicast_iface {
+        Some(iface) => format!(" multicast-iface={}", iface),
+        None => String::new(),
+    };
+    
+    // Preview-only frame rate cap, dropping frames rather than duplicating any. The
+    // supervisor lowers it while shedding load (see apply_preview).
+    let preview_rate = match (camera.preview_fps, options.shed_fps) {
+        (Some(fps), _) => format!("videorate name=preview-rate drop-only=true max-rate={} ! ", fps),
+        (None, true) => format!("videorate name=preview-rate drop-only=true max-rate={} ! ", i32::MAX),
+        (None, false) => String::new(),
+    };
+    
+    // rtspsrc tags buffers with the NTP time from RTCP sender reports, which survives
+    // decoding and scaling as a reference timestamp meta (see camera_capture_time)
+    let reference_timestamps = match camera.capture_timestamps {
+        true => " add-reference-timestamp-meta=true",
+        false => "",
+    };
+    
+    // Cameras are pulled over RTSP, local producers push JPEGs in (see ingest)
+    let ingest = camera.source.as_deref().map(Ingest::parse).transpose().map_err(NvrError::Config)?;
+    let source = match &ingest {
+        Some(ingest) => ingest.source_element(),
+        None => decode::source_element(
+            &format!(
+                "rtspsrc location={} user-id={} user-pw={}{}{}{}",
+                camera.url, user, pass, protocols, multicast_iface, reference_timestamps
+            ),
+            camera.decoder,
+        ),
+    };
+    
+    // Held shut while nobody watches a preview_on_demand camera (see spawn_viewer_monitor).
+    // Caps still go through, so the preview is ready to run at the camera's resolution.
+    let preview_valve = match camera.preview_on_demand {
+        true => "valve name=preview-valve drop=true drop-mode=forward-sticky-events ! ",
+        false => "",
+    };
+    
+    // How the camera's picture is fitted to the preview size when the aspect ratios differ
+    let fit = match camera.scale_mode {
+        ScaleMode::Contain => "videoscale name=scale add-borders=true".to_string(),
+        ScaleMode::Cover => format!("aspectratiocrop aspect-ratio={}/{} ! videoscale name=scale", camera.width, camera.height),
+        ScaleMode::Stretch => "videoscale name=scale add-borders=false".to_string(),
+    };
+    
+    // Build a much simpler pipeline. Only the output size is fixed, videoscale adapts
+    // whatever the camera sends (e.g. day/night resolution switches) to it.
+    // Every branch hangs off the tee right after decoding: the preview's rate cap and
+    // scaling only apply to the preview, recording and restreaming get the full source.
+    let pipeline_str = format!(
+        "{} ! {}{}tee name=t ! queue ! {}{}videoconvert name=preview ! {} ! video/x-raw,format=I420,width={},height={},pixel-aspect-ratio=1/1 ! {}jpegenc name=encoder quality={} ! appsink name=sink emit-signals=true{}",
+        source, crop::videocrop(camera.crop), rotation::source_flip(&camera), preview_valve, preview_rate, fit, camera.width, camera.height, recording_indicator, camera.jpeg_quality, branches
+    );
+    
+    println!("{}: Pipeline string: {}", stream_name, pipeline_str);
+    
+    // Parse and create the pipeline
+    let pipeline = gst::parse::launch(&pipeline_str)?;
+    let pipeline = pipeline.downcast::<gst::Pipeline>().unwrap();
+    recorder::name_segments(&pipeline, &stream_name, &recording_dir, camera.recording_container);
+    recorder::keyframe_interval(&pipeline, &stream_name, &camera);
+    if camera.recording_sync {
+        recorder::sync_to_clock(&pipeline)?;
+    }
+    match &ingest {
+        Some(ingest) => ingest.attach(&pipeline, &stream_name)?,
+        None => decode::attach(&pipeline, &stream_name, camera.decoder, camera.codec_change, stats.source_bytes.clone())?,
+    }
+    if let Some(crop) = camera.crop {
+        crop::attach(&pipeline, &stream_name, crop)?;
+    }
+    
+    // Get the appsink element
+    let appsink = pipeline
+        .by_name("sink")
+        .expect("Couldn't find appsink")
+        .downcast::<gst_app::AppSink>()
+        .unwrap();
+    
+    // QoS is only measured against the clock, so the preview sink has to sync for
+    // elements to report lateness and drops. Costs the rtspsrc jitterbuffer latency.
+    appsink.set_property("sync", camera.sync.unwrap_or(options.qos_stats));
+    appsink.set_property("qos", options.qos_stats);
+    
+    // Track the source resolution and ask the scaling branch to renegotiate when it changes.
+    // Watched ahead of any cropping for scale_mode: cover.
+    let scale_sink = pipeline
+        .by_name("preview")
+        .expect("Couldn't find preview videoconvert")
+        .static_pad("sink")
+        .expect("videoconvert without sink pad");
+    let appsink_pad = appsink.static_pad("sink").expect("appsink without sink pad");
+    let stream_name_caps = stream_name.clone();
+    let stats_caps = stats.clone();
+    scale_sink.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        let Some(gst::PadProbeData::Event(ref event)) = info.data else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let gst::EventView::Caps(caps) = event.view() else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let Ok(video_info) = gst_video::VideoInfo::from_caps(caps.caps()) else {
+            return gst::PadProbeReturn::Ok;
+        };
+        
+        let resolution = (video_info.width(), video_info.height());
+        let previous = stats_caps.source_resolution.lock().unwrap().replace(resolution);
+        match previous {
+            Some(previous) if previous != resolution => {
+                println!(
+                    "{}: Source resolution changed {}x{} -> {}x{}, renegotiating",
+                    stream_name_caps, previous.0, previous.1, resolution.0, resolution.1
+                );
+                appsink_pad.send_event(gst::event::Reconfigure::new());
+            }
+            None => println!("{}: Source resolution {}x{}", stream_name_caps, resolution.0, resolution.1),
+            _ => (),
+        }
+        
+        gst::PadProbeReturn::Ok
+    });
+    
+    // Watch for source-side frame loss on every decoded frame, ahead of any preview dropping
+    let tee_sink = pipeline
+        .by_name("t")
+        .expect("Couldn't find tee")
+        .static_pad("sink")
+        .expect("tee without sink pad");
+    let gaps = Mutex::new(GapDetector::default());
+    let stream_name_gaps = stream_name.clone();
+    let stats_gaps = stats.clone();
+    let preview_on_demand = camera.preview_on_demand;
+    tee_sink.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+        let Some(buffer) = info.buffer() else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let framerate = pad
+            .current_caps()
+            .and_then(|caps| caps.structure(0).and_then(|s| s.get::<gst::Fraction>("framerate").ok()))
+            .filter(|fps| fps.numer() > 0 && fps.denom() > 0)
+            .map(|fps| fps.numer() as f64 / fps.denom() as f64);
+        stats_gaps.frames_decoded.fetch_add(1, Ordering::Relaxed);
+        // An idle on-demand preview sends nothing, decoded frames show the camera is there
+        if preview_on_demand {
+            *stats_gaps.last_frame_at.lock().unwrap() = Some(Utc::now());
+            stats_gaps.backoff.reset();
+        }
+        let discont = buffer.flags().contains(gst::BufferFlags::DISCONT);
+        let mut detector = gaps.lock().unwrap();
+        let missed = detector.observe(buffer.pts(), discont, framerate);
+        *stats_gaps.source_fps.lock().unwrap() = framerate.or_else(|| detector.average_interval.map(|interval| 1e9 / interval));
+        if missed > 0 {
+            println!("{}: Source gap, {} frame(s) missing", stream_name_gaps, missed);
+            stats_gaps.source_frames_missed.fetch_add(missed, Ordering::Relaxed);
+            stats_gaps.source_gaps.fetch_add(1, Ordering::Relaxed);
+        }
+        gst::PadProbeReturn::Ok
+    });
+    
+    // Hand each raw preview frame to the frame processors just before it is encoded,
+    // motion detection first so it sees the camera's own pixels
+    let motion_state = Arc::new(Mutex::new(MotionState::default()));
+    let mut processors: Processors = Vec::new();
+    if camera.motion_detection || camera.skip_static_frames || camera.snapshot_on_motion {
+        let motion_events = camera.motion_detection.then(|| events.clone());
+        processors.push(Arc::new(MotionDetector::new(motion_state.clone(), stats.clone(), motion_events)));
+    }
+    if camera.tamper_detection {
+        let duration = Duration::from_secs(camera.tamper_secs);
+        processors.push(Arc::new(TamperDetector::new(stream_name.clone(), camera.tamper_threshold, duration, events.clone())));
+    }
+    let label = camera.display_name.as_deref().unwrap_or(&stream_name);
+    recorder::text_track(&pipeline, &stream_name, label, &events, motion_state.clone())?;
+    processors.extend(options.processors.iter().cloned());
+    if !processors.is_empty() {
+        let encoder_sink = pipeline
+            .by_name("encoder")
+            .expect("Couldn't find jpegenc")
+            .static_pad("sink")
+            .expect("jpegenc without sink pad");
+        encoder_sink.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+            let (Some(buffer), Some(caps)) = (info.buffer(), pad.current_caps()) else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let Ok(video_info) = gst_video::VideoInfo::from_caps(&caps) else {
+                return gst::PadProbeReturn::Ok;
+            };
+            
+            let frame = FrameData { buffer: buffer.clone(), info: video_info };
+            if let Some(processed) = processor::run(&processors, frame) {
+                info.data = Some(gst::PadProbeData::Buffer(processed.buffer));
+            }
+            
+            gst::PadProbeReturn::Ok
+        });
+    }
+    
+    // Broadcast from another thread so a slow send never stalls the streaming thread:
+    // one of the stream's own, or the shared pool which is woken for each frame
+    let queue = Arc::new(FrameQueue::new(options.queue_size));
+    let queue_sample = queue.clone();
+    let broadcaster = Arc::new(Broadcaster {
+        queue: queue.clone(),
+        scheduled: AtomicBool::new(false),
+        stream_name: stream_name.clone(),
+        stats: stats.clone(),
+        seek_buffer: options.seek_buffer,
+        tx,
+    });
+    let broadcast_pool = options.broadcast_pool.clone();
+    let broadcaster_sample = broadcaster.clone();
+    if broadcast_pool.is_none() {
+        std::thread::spawn(move || {
+            // Ends when the pipeline is dropped and closes the queue
+            while let Some(frame) = broadcaster.queue.pop() {
+                broadcaster.deliver(frame);
+            }
+        });
+    }
+    
+    // Recent frames kept around for post-mortem dumps
+    let recent_frames = Arc::new(Mutex::new(VecDeque::<Vec<u8>>::with_capacity(options.debug_frames)));
+    let recent_frames_sample = recent_frames.clone();
+    let debug_frames = options.debug_frames;
+    
+    // Static-scene skipping compares against the last frame actually sent
+    let motion_detection = camera.motion_detection;
+    let skip_static_frames = camera.skip_static_frames;
+    let skip_duplicate_frames = camera.skip_duplicate_frames;
+    let static_threshold = camera.static_threshold;
+    let drop_corrupt_frames = camera.drop_corrupt_frames;
+    let mut cadence = camera.timestamp_fps.map(CadenceClock::new);
+    let exif_orientation = rotation::exif_orientation(&camera);
+    let mut last_sent: Option<(LumaGrid, Instant)> = None;
+    let mut last_digest: Option<(u64, Instant)> = None;
+    let motion_snapshots = camera.snapshot_on_motion.then(|| {
+        let cooldown = Duration::from_secs(camera.snapshot_cooldown_secs);
+        MotionSnapshots::new(stream_name.clone(), recording_dir.clone(), cooldown, camera.snapshot_burst)
+    });
+    
+    // Create a clone for the closure
+    let stream_name_sample = stream_name.clone();
+    let stats_sample = stats.clone();
     
     // Setup appsink to collect frames
     appsink.set_callbacks(
@@ -158,302 +1719,1609 @@ fn setup_pipeline(url: &str, user: &str, pass: &str, tx: broadcast::Sender<Vec<u
                 }
             };
             
-            // Log frame sizes
-            println!("{}: Frame received - size: {} bytes", stream_name_sample, map.len());
+            frame_log!("{}: Frame received - size: {} bytes", stream_name_sample, map.len());
+            stats_sample.mark_frame(map.len());
+            
+            // Every frame reaching the sink keeps the cadence in step, whether it's sent or not
+            let camera_time = camera_capture_time(buffer);
+            let mut captured_at = camera_time.unwrap_or_else(Utc::now);
+            if let Some(cadence) = &mut cadence {
+                captured_at = cadence.stamp(captured_at);
+                *stats_sample.timestamp_drift.lock().unwrap() = Some(cadence.drift());
+            }
+            
+            // Spare viewers a glitching camera's broken frames, they keep the previous one
+            if drop_corrupt_frames && !is_complete_jpeg(&map) {
+                let corrupt = stats_sample.corrupt_frames.fetch_add(1, Ordering::Relaxed) + 1;
+                println!("{}: Dropped malformed JPEG of {} bytes ({} so far)", stream_name_sample, map.len(), corrupt);
+                return Ok(gst::FlowSuccess::Ok);
+            }
+            
+            if debug_frames > 0 {
+                let mut recent = recent_frames_sample.lock().unwrap();
+                if recent.len() >= debug_frames {
+                    recent.pop_front();
+                }
+                recent.push_back(map.to_vec());
+            }
+            
+            // A camera repeating its last picture gives the same pixels, so the same JPEG
+            let digest = skip_duplicate_frames.then(|| {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                map.as_slice().hash(&mut hasher);
+                hasher.finish()
+            });
+            if let (Some(digest), Some((sent_digest, sent_at))) = (digest, &last_digest) {
+                if digest == *sent_digest && sent_at.elapsed() < STATIC_REFRESH_INTERVAL {
+                    stats_sample.duplicate_frames.fetch_add(1, Ordering::Relaxed);
+                    stats_sample.bytes_saved.fetch_add(map.len() as u64, Ordering::Relaxed);
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+            }
+            
+            // Skip frames that barely differ from the last one sent, clients keep showing it
+            if skip_static_frames {
+                let grid = motion_state.lock().unwrap().grid.clone();
+                if let (Some(grid), Some((sent_grid, sent_at))) = (&grid, &last_sent) {
+                    if grid.score(sent_grid) < static_threshold && sent_at.elapsed() < STATIC_REFRESH_INTERVAL {
+                        stats_sample.frames_skipped.fetch_add(1, Ordering::Relaxed);
+                        stats_sample.bytes_saved.fetch_add(map.len() as u64, Ordering::Relaxed);
+                        return Ok(gst::FlowSuccess::Ok);
+                    }
+                }
+                last_sent = grid.map(|grid| (grid, Instant::now()));
+            }
+            if let Some(digest) = digest {
+                last_digest = Some((digest, Instant::now()));
+            }
             
-            // Send the JPEG data to all connected clients
-            let sent = tx.send(map.to_vec());
-            println!("{}: Frame sent to {} receivers", stream_name_sample, sent.map(|r| r).unwrap_or(0));
+            // Hand the JPEG data to the broadcaster
+            let detected = motion_state.lock().unwrap().motion_box;
+            let motion_box = detected.filter(|_| motion_detection);
+            let seq = stats_sample.next_seq();
+            let jpeg = match exif_orientation {
+                Some(orientation) => rotation::tag_orientation(&map, orientation),
+                None => map.to_vec(),
+            };
+            if let Some(motion_snapshots) = &motion_snapshots {
+                motion_snapshots.offer(&jpeg, detected.is_some(), *stats_sample.motion_score.lock().unwrap());
+            }
+            let frame = Frame {
+                jpeg,
+                motion_box,
+                seq,
+                captured_at,
+                camera_clock: camera_time.is_some(),
+            };
+            if queue_sample.push(frame) {
+                stats_sample.queue_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            if let Some(pool) = &broadcast_pool {
+                pool.wake(broadcaster_sample.clone());
+            }
             
             Ok(gst::FlowSuccess::Ok)
         })
         .build()
     );
     
-    // Start the pipeline
-    println!("{}: Setting pipeline to Playing state", stream_name);
-    pipeline.set_state(gst::State::Playing)?;
+    // Start the pipeline
+    println!("{}: Setting pipeline to Playing state", stream_name);
+    pipeline.set_state(gst::State::Playing)?;
+    stats.mark_started();
+    
+    // Watch the bus so errors and EOS restart the pipeline instead of leaving it dead
+    let bus = pipeline.bus().expect("Pipeline without bus");
+    let pipeline_weak = pipeline.downgrade();
+    let stream_name_bus = stream_name.clone();
+    let start_attempts = Arc::new(AtomicU64::new(0));
+    let bus_watch = bus.add_watch(move |_, msg| {
+        use gst::MessageView;
+        
+        match msg.view() {
+            // Each start and restart takes the pipeline out of Null. The attempt number tells
+            // a timeout apart from the ones of earlier attempts.
+            MessageView::StateChanged(change) if msg.src().is_some_and(|src| src.is::<gst::Pipeline>()) => {
+                match (change.old(), change.current()) {
+                    (gst::State::Null, gst::State::Ready) => {
+                        if let Some(timeout) = options.start_timeout {
+                            let attempt = start_attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                            watch_start(pipeline_weak.clone(), stream_name_bus.clone(), stats.clone(), start_attempts.clone(), attempt, timeout);
+                        }
+                    }
+                    (_, gst::State::Playing) => println!("{}: Pipeline is playing", stream_name_bus),
+                    _ => (),
+                }
+                return gst::glib::ControlFlow::Continue;
+            }
+            MessageView::Error(err) => {
+                println!(
+                    "{}: Error from {:?}: {} ({:?})",
+                    stream_name_bus,
+                    err.src().map(|s| s.path_string()),
+                    err.error(),
+                    err.debug()
+                );
+                
+                // If the scaling branch couldn't follow a source caps change, the restart
+                // below renegotiates the whole chain from the new source caps
+                let not_negotiated = err.error().matches(gst::CoreError::Negotiation)
+                    || err.debug().is_some_and(|debug| debug.contains("not-negotiated"));
+                if not_negotiated {
+                    println!("{}: Caps renegotiation failed, restarting pipeline", stream_name_bus);
+                }
+                
+                if options.debug_frames > 0 {
+                    let frames: Vec<Vec<u8>> = recent_frames.lock().unwrap().drain(..).collect();
+                    dump_debug_frames(&options.debug_dir, &stream_name_bus, frames);
+                }
+            }
+            MessageView::Eos(..) => {
+                println!("{}: End of stream", stream_name_bus);
+            }
+            MessageView::Qos(qos) if options.qos_stats => {
+                stats.qos.record(qos);
+                return gst::glib::ControlFlow::Continue;
+            }
+            MessageView::Latency(..) if options.qos_stats => {
+                if let Some(pipeline) = pipeline_weak.upgrade() {
+                    let _ = pipeline.recalculate_latency();
+                    stats.qos.update_latency(&pipeline);
+                }
+                return gst::glib::ControlFlow::Continue;
+            }
+            _ => return gst::glib::ControlFlow::Continue,
+        }
+        
+        if let Some(pipeline) = pipeline_weak.upgrade() {
+            restart_pipeline(pipeline, stream_name_bus.clone(), stats.clone());
+        }
+        
+        gst::glib::ControlFlow::Continue
+    })?;
+    
+    // The restream runs as its own pipeline so RTMP drops never interrupt local viewers
+    let restream = match &camera.restream_url {
+        Some(restream_url) => Some(setup_restream(&stream_name, restream_url)?),
+        None => None,
+    };
+    
+    // The supervisor keeps these alive, bus watches are dispatched by its main loop
+    Ok(PipelineResources {
+        pipeline,
+        queue: Some(queue),
+        _bus_watch: bus_watch,
+        restream,
+    })
+}
+
+// Re-encode a camera to H.264 (plus a silent AAC track, which most ingest servers expect)
+// and push it over RTMP. Frames come from the camera pipeline's intervideosink, so this
+// pipeline can fail and reconnect on its own.
+fn setup_restream(stream_name: &str, restream_url: &str) -> error::Result<(gst::Pipeline, gst::bus::BusWatchGuard)> {
+    let label = format!("{} restream", stream_name);
+    println!("{}: Restreaming to {}", label, restream_url);
+    
+    let pipeline_str = format!(
+        "intervideosrc channel=restream-{} ! videoconvert ! x264enc tune=zerolatency speed-preset=veryfast bitrate={} key-int-max=60 ! video/x-h264,profile=main ! h264parse ! flvmux name=mux streamable=true ! rtmpsink location=\"{} live=1\" \
+         audiotestsrc wave=silence is-live=true ! audioconvert ! voaacenc ! aacparse ! mux.",
+        stream_name, RESTREAM_BITRATE_KBPS, restream_url
+    );
+    
+    let pipeline = gst::parse::launch(&pipeline_str)?;
+    let pipeline = pipeline.downcast::<gst::Pipeline>().unwrap();
+    
+    // No frames pass through here to reset a growing backoff, and rtmpsink only connects
+    // once it is already playing, so every reconnect waits the same initial delay
+    let policy = ReconnectPolicy { multiplier: 1.0, ..ReconnectPolicy::default() };
+    let stats = Arc::new(StreamStats { backoff: Backoff::new(policy), ..Default::default() });
+    pipeline.set_state(gst::State::Playing)?;
+    stats.mark_started();
+    
+    // Reconnect to the RTMP server whenever it drops us
+    let bus = pipeline.bus().expect("Pipeline without bus");
+    let pipeline_weak = pipeline.downgrade();
+    let bus_watch = bus.add_watch(move |_, msg| {
+        use gst::MessageView;
+        
+        match msg.view() {
+            MessageView::Error(err) => println!("{}: Error: {} ({:?})", label, err.error(), err.debug()),
+            MessageView::Eos(..) => println!("{}: End of stream", label),
+            _ => return gst::glib::ControlFlow::Continue,
+        }
+        
+        if let Some(pipeline) = pipeline_weak.upgrade() {
+            restart_pipeline(pipeline, label.clone(), stats.clone());
+        }
+        
+        gst::glib::ControlFlow::Continue
+    })?;
+    
+    Ok((pipeline, bus_watch))
+}
+
+// Tear the pipeline down and bring it back up after the camera's reconnect delay, or leave
+// it down once its reconnect attempts ran out.
+// A single failure usually posts several error messages, so only the first one schedules a restart.
+// The restart is in place: the appsink callbacks and the broadcaster thread, and with them
+// the stream's broadcast sender, stay the same.
+fn restart_pipeline(pipeline: gst::Pipeline, stream_name: String, stats: Arc<StreamStats>) {
+    if stats.restarting.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    stats.mark_stopped();
+    let delay = stats.backoff.next_delay();
+    
+    std::thread::spawn(move || {
+        let _ = pipeline.set_state(gst::State::Null);
+        let Some(delay) = delay else {
+            println!(
+                "{}: ERROR: Giving up after {} restarts without a frame, restart it through the API to try again",
+                stream_name, stats.backoff.state().attempts
+            );
+            stats.restarting.store(false, Ordering::SeqCst);
+            return;
+        };
+        
+        // Only hold a weak ref while waiting, the supervisor may drop the pipeline meanwhile
+        let pipeline_weak = pipeline.downgrade();
+        drop(pipeline);
+        println!("{}: Restarting pipeline in {:?}", stream_name, delay);
+        std::thread::sleep(delay);
+        stats.backoff.retrying();
+        
+        let Some(pipeline) = pipeline_weak.upgrade() else {
+            println!("{}: Pipeline was stopped, not restarting", stream_name);
+            stats.restarting.store(false, Ordering::SeqCst);
+            return;
+        };
+        match pipeline.set_state(gst::State::Playing) {
+            Ok(_) => {
+                stats.reconnect_count.fetch_add(1, Ordering::SeqCst);
+                stats.mark_started();
+            }
+            Err(err) => println!("{}: Failed to restart pipeline: {:?}", stream_name, err),
+        }
+        stats.restarting.store(false, Ordering::SeqCst);
+    });
+}
+
+// Restart the pipeline if this start attempt hasn't reached Playing after `timeout`, e.g.
+// a camera that accepts the TCP connection but never completes RTSP negotiation, which
+// otherwise sits in Paused forever without an error. Runs on the supervisor's main loop.
+fn watch_start(
+    pipeline_weak: gst::glib::WeakRef<gst::Pipeline>,
+    stream_name: String,
+    stats: Arc<StreamStats>,
+    attempts: Arc<AtomicU64>,
+    attempt: u64,
+    timeout: Duration,
+) {
+    gst::glib::timeout_add_once(timeout, move || {
+        let Some(pipeline) = pipeline_weak.upgrade() else { return };
+        // Superseded by a restart, or already being restarted
+        if attempts.load(Ordering::SeqCst) != attempt || stats.restarting.load(Ordering::SeqCst) {
+            return;
+        }
+        if pipeline.current_state() == gst::State::Playing {
+            return;
+        }
+        println!(
+            "{}: ERROR: Pipeline didn't start within {:?} (stuck in {:?}), the camera may not be completing RTSP negotiation",
+            stream_name, timeout, pipeline.current_state()
+        );
+        restart_pipeline(pipeline, stream_name, stats);
+    });
+}
+
+// Turn the per-stream stats into connected/stalled/disconnected and fps once a second.
+// The hub only passes on changes, so idle dashboards get no traffic.
+fn spawn_status_monitor(stats: Stats, status_hub: Arc<StatusHub>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(STATUS_INTERVAL);
+        let mut last_counts: HashMap<String, (u64, Instant)> = HashMap::new();
+        loop {
+            interval.tick().await;
+            
+            let streams: Vec<(String, Arc<StreamStats>)> = stats.lock().unwrap()
+                .iter()
+                .map(|(name, stream_stats)| (name.clone(), stream_stats.clone()))
+                .collect();
+            for (name, stream_stats) in streams {
+                let count = stream_stats.frames_received.load(Ordering::Relaxed);
+                let now = Instant::now();
+                let fps = match last_counts.insert(name.clone(), (count, now)) {
+                    Some((last_count, last_at)) => {
+                        let elapsed = now.duration_since(last_at).as_secs_f64();
+                        (count.saturating_sub(last_count) as f64 / elapsed.max(0.001)).round() as u32
+                    }
+                    None => 0,
+                };
+                
+                let running = stream_stats.started.lock().unwrap().is_some();
+                let last_frame_at = *stream_stats.last_frame_at.lock().unwrap();
+                let state = match (running, last_frame_at) {
+                    (false, _) => StreamState::Disconnected,
+                    (true, None) => StreamState::Connecting,
+                    (true, Some(_)) if stream_stats.signal_lost() => StreamState::Stalled,
+                    (true, Some(_)) => StreamState::Connected,
+                };
+                
+                status_hub.update(StreamStatus { stream: name, state, fps });
+            }
+        }
+    });
+}
+
+// Check free space on the recordings disk and pause or resume recording everywhere.
+// Viewers of recording cameras get a "Recorder/DiskFull" event when that changes.
+fn spawn_disk_monitor(settings: &config::Settings, supervisor: Arc<PipelineSupervisor>, clients: Clients) {
+    let dir = settings.recordings_dir.clone();
+    let min_free = settings.min_free_disk_mb * 1024 * 1024;
+    let action = settings.disk_full_action;
+    let recording_cameras: Vec<String> = settings.cameras.iter()
+        .filter(|camera| camera.record)
+        .map(|camera| camera.name.clone())
+        .collect();
+    
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DISK_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            
+            // Pruning can delete a lot of files, keep it off the async workers
+            let dir_check = dir.clone();
+            let full = match tokio::task::spawn_blocking(move || diskspace::is_full(&dir_check, min_free, action)).await {
+                Ok(Ok(full)) => full,
+                Ok(Err(err)) => {
+                    println!("Checking free space in {} failed: {:?}", dir.display(), err);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            if !supervisor.set_disk_full(full) {
+                continue;
+            }
+            
+            match full {
+                true => println!("WARNING: Less than {} MB free in {}, pausing recording", min_free / 1024 / 1024, dir.display()),
+                false => println!("Free space in {} recovered, resuming recording", dir.display()),
+            }
+            supervisor.apply_recording_state(Utc::now());
+            
+            let clients_lock = clients.lock().unwrap();
+            for name in &recording_cameras {
+                if let Some(channels) = clients_lock.get(name) {
+                    let _ = channels.events.send(CameraEvent {
+                        source: "recorder",
+                        topic: "Recorder/DiskFull".to_string(),
+                        active: full,
+                        time: Some(Utc::now().to_rfc3339()),
+                    });
+                }
+            }
+        }
+    });
+}
+
+fn spawn_load_monitor(settings: &config::Settings, supervisor: Arc<PipelineSupervisor>) {
+    let threshold = settings.load_shed_cpu as f64;
+    
+    tokio::spawn(async move {
+        let mut sampler = cpuload::CpuSampler::default();
+        let (mut busy, mut calm) = (0, 0);
+        let mut interval = tokio::time::interval(LOAD_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let usage = match sampler.sample() {
+                Ok(Some(usage)) => usage,
+                Ok(None) => continue,
+                Err(err) => {
+                    println!("Reading CPU usage failed, load shedding disabled: {:?}", err);
+                    return;
+                }
+            };
+            
+            busy = if usage >= threshold { busy + 1 } else { 0 };
+            calm = if usage < threshold - LOAD_RESTORE_MARGIN { calm + 1 } else { 0 };
+            // Least important cameras are degraded first and restored last
+            if busy >= LOAD_SHED_SAMPLES {
+                busy = 0;
+                if let Some(priority) = supervisor.shed_next() {
+                    println!("WARNING: CPU at {:.0}%, lowering preview quality of priority {} cameras", usage, priority);
+                }
+            } else if calm >= LOAD_RESTORE_SAMPLES {
+                calm = 0;
+                if let Some(priority) = supervisor.restore_next() {
+                    println!("CPU back at {:.0}%, restoring preview quality of priority {} cameras", usage, priority);
+                }
+            }
+        }
+    });
+}
+
+// Wait up to require_streams_secs for any or all cameras to deliver a frame, an error once
+// that time is up without them
+async fn wait_for_streams(settings: &config::Settings, stats: &Stats) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(settings.require_streams_secs);
+    let names: Vec<&String> = settings.cameras.iter().map(|camera| &camera.name).collect();
+    println!("Waiting up to {}s for {:?} cameras to come online", settings.require_streams_secs, settings.require_streams);
+    loop {
+        let offline: Vec<&String> = {
+            let stats_lock = stats.lock().unwrap();
+            names
+                .iter()
+                .copied()
+                // Decoded frames count too, an idle preview_on_demand camera sends no JPEGs
+                .filter(|name| {
+                    stats_lock.get(*name).is_none_or(|stream_stats| {
+                        stream_stats.frames_received.load(Ordering::Relaxed) == 0 && stream_stats.frames_decoded.load(Ordering::Relaxed) == 0
+                    })
+                })
+                .collect()
+        };
+        let satisfied = match settings.require_streams {
+            config::RequireStreams::None => true,
+            config::RequireStreams::Any => offline.len() < names.len(),
+            config::RequireStreams::All => offline.is_empty(),
+        };
+        if satisfied {
+            println!("{} of {} cameras online", names.len() - offline.len(), names.len());
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            anyhow::bail!(
+                "require_streams: {:?}, but {} of {} cameras didn't come online within {}s: {}",
+                settings.require_streams,
+                offline.len(),
+                names.len(),
+                settings.require_streams_secs,
+                offline.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(", ")
+            );
+        }
+        tokio::time::sleep(STREAMS_CHECK_INTERVAL).await;
+    }
+}
+
+// Moves each camera with a quality_ladder to the step for its current number of viewers,
+// and opens a preview_on_demand pipeline's preview while it has any
+fn spawn_viewer_monitor(settings: &config::Settings, supervisor: Arc<PipelineSupervisor>, clients: Clients) {
+    let names: Vec<String> = settings
+        .cameras
+        .iter()
+        .filter(|camera| !camera.quality_ladder.is_empty())
+        .map(|camera| camera.name.clone())
+        .collect();
+    // (pipeline, camera, substream), a substream's viewers watch its own pipeline
+    let on_demand: Vec<(String, String, bool)> = settings
+        .cameras
+        .iter()
+        .filter(|camera| camera.preview_on_demand)
+        .flat_map(|camera| {
+            let sub = camera.substream_url.as_ref().map(|_| (format!("{}@sub", camera.name), camera.name.clone(), true));
+            std::iter::once((camera.name.clone(), camera.name.clone(), false)).chain(sub)
+        })
+        .collect();
+    
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(VIEWER_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let (counts, watched): (Vec<(&String, usize)>, Vec<(&String, bool)>) = {
+                let clients_lock = clients.lock().unwrap();
+                let counts = names
+                    .iter()
+                    .filter_map(|name| clients_lock.get(name).map(|channels| (name, channels.frames.receiver_count())))
+                    .collect();
+                let watched = on_demand
+                    .iter()
+                    .filter_map(|(pipeline, camera, sub)| {
+                        let channels = clients_lock.get(camera)?;
+                        let frames = if *sub { channels.sub_frames.as_ref()? } else { &channels.frames };
+                        Some((pipeline, frames.receiver_count() > 0))
+                    })
+                    .collect();
+                (counts, watched)
+            };
+            for (pipeline, watched) in watched {
+                if supervisor.set_preview_active(pipeline, watched) {
+                    println!("{}: Preview {}", pipeline, if watched { "started, someone is watching" } else { "stopped, nobody is watching" });
+                }
+            }
+            for (name, viewers) in counts {
+                match supervisor.set_viewers(name, viewers) {
+                    Some((Some(tier), quality)) => println!("{}: {} viewers, JPEG quality {} (quality_ladder step {})", name, viewers, quality, tier),
+                    Some((None, quality)) => println!("{}: {} viewers, JPEG quality back to {}", name, viewers, quality),
+                    None => (),
+                }
+            }
+        }
+    });
+}
+
+// Every influx_interval_secs, one point per stream: the /api/metrics numbers plus the frame
+// rate and bitrate since the last push. A failed push is logged and skipped, not retried.
+fn spawn_influx_exporter(settings: &config::Settings, url: String, clients: Clients, stats: Stats, supervisor: Arc<PipelineSupervisor>) -> Result<()> {
+    let tags = settings.influx_tags.as_deref().map(influx::parse_tags).transpose()?.unwrap_or_default();
+    let writer = Arc::new(InfluxWriter::new(url, settings.influx_token.clone(), &settings.influx_measurement, &settings.server_id, &tags));
+    let qos_stats = settings.qos_stats;
+    let period = Duration::from_secs(settings.influx_interval_secs);
+    
+    tokio::spawn(async move {
+        // (frames, bytes, when) per stream at the previous push, for the rates
+        let mut previous: HashMap<String, (u64, u64, Instant)> = HashMap::new();
+        let mut failing = false;
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let now = Utc::now();
+            let names: Vec<String> = stats.lock().unwrap().keys().cloned().collect();
+            let mut lines = Vec::new();
+            for name in names {
+                let Some(metrics) = stream_metrics(&clients, &stats, &name, qos_stats, &supervisor) else { continue };
+                let Ok(mut fields) = serde_json::to_value(&metrics) else { continue };
+                let sampled = Instant::now();
+                if let Some((frames, bytes, at)) = previous.insert(name.clone(), (metrics.frames_received, metrics.bytes_received, sampled)) {
+                    let secs = sampled.duration_since(at).as_secs_f64();
+                    // Counters restart with the process only, but don't report nonsense if one went back
+                    if secs > 0.0 && metrics.frames_received >= frames && metrics.bytes_received >= bytes {
+                        fields["fps"] = serde_json::json!((metrics.frames_received - frames) as f64 / secs);
+                        fields["bitrate_kbps"] = serde_json::json!((metrics.bytes_received - bytes) as f64 * 8.0 / 1000.0 / secs);
+                    }
+                }
+                lines.extend(writer.line(&name, &fields, now));
+            }
+            if lines.is_empty() {
+                continue;
+            }
+            
+            let writer = writer.clone();
+            match tokio::task::spawn_blocking(move || writer.write(&lines)).await {
+                Ok(Ok(())) => {
+                    if failing {
+                        println!("InfluxDB writes are working again");
+                        failing = false;
+                    }
+                }
+                // Only the first failure of a run is logged, not one per interval
+                Ok(Err(err)) if !failing => {
+                    println!("Writing metrics to InfluxDB failed: {:#}", err);
+                    failing = true;
+                }
+                Ok(Err(_)) => (),
+                Err(err) => println!("InfluxDB write task failed: {:?}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+// While the camera is down, broadcast a generated "NO SIGNAL" frame with the camera name
+// and time on the stream's own channel, so viewers get a picture like any other frame.
+fn spawn_no_signal(camera: &CameraConfig, tx: broadcast::Sender<Frame>, stats: Arc<StreamStats>) {
+    let stream_name = camera.name.clone();
+    let renderer = match NoSignalRenderer::new(camera.width, camera.height, camera.jpeg_quality) {
+        Ok(renderer) => renderer,
+        Err(err) => {
+            println!("{}: Failed to set up no-signal placeholder: {:?}", stream_name, err);
+            return;
+        }
+    };
+    
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(NO_SIGNAL_INTERVAL);
+            if !stats.signal_lost() {
+                continue;
+            }
+            
+            let text = format!(
+                "NO SIGNAL\n{}\n{}",
+                stats.display_name,
+                Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+            );
+            match renderer.render(&text) {
+                Ok(jpeg) => {
+                    let frame = Frame { jpeg, motion_box: None, seq: stats.next_seq(), captured_at: Utc::now(), camera_clock: false };
+                    *stats.latest_frame.lock().unwrap() = Some(frame.clone());
+                    let _ = tx.send(frame);
+                }
+                Err(err) => println!("{}: Failed to render no-signal placeholder: {:?}", stream_name, err),
+            }
+        }
+    });
+}
+
+async fn handle_rejection(err: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::Reply;
+    
+    if err.find::<RateLimited>().is_some() {
+        let reply = warp::reply::with_status("Too many connections", warp::http::StatusCode::TOO_MANY_REQUESTS);
+        return Ok(reply.into_response());
+    }
+    Err(err)
+}
+
+// Write frames to `<dir>/<stream>_<timestamp>_<n>.jpg` off the bus thread
+fn dump_debug_frames(dir: &Path, stream_name: &str, frames: Vec<Vec<u8>>) {
+    if frames.is_empty() {
+        return;
+    }
+    
+    let dir = dir.to_path_buf();
+    let prefix = format!("{}_{}", stream_name, Utc::now().format("%Y%m%d_%H%M%S"));
+    std::thread::spawn(move || {
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            println!("Failed to create debug dir {}: {:?}", dir.display(), err);
+            return;
+        }
+        
+        for (i, frame) in frames.iter().enumerate() {
+            let path = dir.join(format!("{}_{}.jpg", prefix, i));
+            if let Err(err) = std::fs::write(&path, frame) {
+                println!("Failed to write debug frame {}: {:?}", path.display(), err);
+            }
+        }
+        println!("Dumped {} debug frames to {}/{}_*.jpg", frames.len(), dir.display(), prefix);
+    });
+}
+
+fn embedded_asset(name: &str) -> Option<warp::reply::Response> {
+    let (_, content_type, body) = EMBEDDED_ASSETS.iter().find(|(asset, _, _)| *asset == name)?;
+    
+    let response = warp::http::Response::builder()
+        .header("content-type", *content_type)
+        .header("cache-control", "public, max-age=3600")
+        .body(warp::hyper::Body::from(*body))
+        .unwrap();
+    Some(response)
+}
+
+async fn serve_thumbnail(thumbnails: Arc<ThumbnailCache>, snapshot_sources: Arc<HashMap<String, SnapshotSource>>, stats: Stats, stream_name: String, width: u32) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::Reply;
+    
+    let stream_stats = {
+        let stats_lock = stats.lock().unwrap();
+        stats_lock.iter()
+            .find(|(name, _)| name.to_lowercase() == stream_name.to_lowercase())
+            .map(|(name, stream_stats)| (name.clone(), stream_stats.clone()))
+    };
+    let Some((name, stream_stats)) = stream_stats else {
+        return Err(warp::reject::not_found());
+    };
+    let latest_frame = stream_stats.latest_frame.lock().unwrap().as_ref().map(|frame| frame.jpeg.clone());
+    let snapshot_source = snapshot_sources.get(&name).cloned();
+    if latest_frame.is_none() && snapshot_source.is_none() {
+        let reply = warp::reply::with_status("No frame yet", warp::http::StatusCode::SERVICE_UNAVAILABLE);
+        return Ok(reply.into_response());
+    }
+    
+    // The camera's own still is cheaper for it than keeping a decode going, the decoded
+    // frame is the fallback when it can't be fetched. Fetching, decoding and resizing
+    // all block, keep them off the async workers.
+    let rendered = tokio::task::spawn_blocking(move || {
+        thumbnails.get_or_render(&name, width, || {
+            if let Some(source) = &snapshot_source {
+                match snapshot::fetch(source) {
+                    Ok(jpeg) => return Ok(jpeg),
+                    Err(err) => println!("{}: Snapshot fetch failed, using the latest frame: {:?}", name, err),
+                }
+            }
+            latest_frame.ok_or_else(|| anyhow::anyhow!("no frame yet"))
+        })
+    }).await;
+    match rendered {
+        Ok(Ok(thumbnail)) => {
+            let reply = warp::reply::with_header(thumbnail.to_vec(), "Content-Type", "image/jpeg");
+            Ok(warp::reply::with_header(reply, "Cache-Control", "no-cache").into_response())
+        }
+        Ok(Err(err)) => {
+            println!("{}: Failed to render thumbnail: {:?}", stream_name, err);
+            Ok(warp::http::StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+        Err(err) => {
+            println!("{}: Thumbnail task failed: {:?}", stream_name, err);
+            Ok(warp::http::StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+// Server-wide response_headers with each camera's own on top, by lowercase stream name.
+// Names and values were checked by Settings::validate.
+fn response_headers(settings: &config::Settings) -> HashMap<String, warp::http::HeaderMap> {
+    let parse = |name: &String, value: &String| {
+        let name = warp::http::HeaderName::from_bytes(name.as_bytes()).ok()?;
+        Some((name, warp::http::HeaderValue::from_str(value).ok()?))
+    };
+    settings
+        .cameras
+        .iter()
+        .map(|camera| {
+            let mut headers = warp::http::HeaderMap::new();
+            for (name, value) in settings.response_headers.iter().chain(&camera.response_headers) {
+                if let Some((name, value)) = parse(name, value) {
+                    headers.insert(name, value);
+                }
+            }
+            (camera.name.to_lowercase(), headers)
+        })
+        .filter(|(_, headers)| !headers.is_empty())
+        .collect()
+}
+
+// Replaces the response's own value of a header that is configured, e.g. Cache-Control
+fn add_headers(reply: &mut warp::reply::Response, headers: Option<&warp::http::HeaderMap>) {
+    for (name, value) in headers.into_iter().flatten() {
+        reply.headers_mut().insert(name.clone(), value.clone());
+    }
+}
+
+fn stream_infos(clients: &Clients, stats: &Stats) -> Vec<StreamInfo> {
+    let clients_lock = clients.lock().unwrap();
+    let stats_lock = stats.lock().unwrap();
+    
+    let mut infos: Vec<StreamInfo> = stats_lock.iter().map(|(name, stream_stats)| {
+        let started = *stream_stats.started.lock().unwrap();
+        let subscribers = clients_lock.get(name)
+            .map(|channels| channels.frames.receiver_count())
+            .unwrap_or(0);
+        
+        StreamInfo {
+            name: name.clone(),
+            display_name: stream_stats.display_name.clone(),
+            running: started.is_some(),
+            started_at: started.map(|(at, _)| at),
+            stopped_at: *stream_stats.stopped_at.lock().unwrap(),
+            uptime_secs: started.map(|(_, since)| since.elapsed().as_secs()),
+            last_frame_at: *stream_stats.last_frame_at.lock().unwrap(),
+            source_resolution: *stream_stats.source_resolution.lock().unwrap(),
+            reconnect_count: stream_stats.reconnect_count.load(Ordering::SeqCst),
+            reconnect: stream_stats.backoff.state(),
+            queue_dropped: stream_stats.queue_dropped.load(Ordering::SeqCst),
+            motion_score: *stream_stats.motion_score.lock().unwrap(),
+            frames_skipped: stream_stats.frames_skipped.load(Ordering::SeqCst),
+            duplicate_frames: stream_stats.duplicate_frames.load(Ordering::SeqCst),
+            bytes_saved: stream_stats.bytes_saved.load(Ordering::SeqCst),
+            frame_seq: stream_stats.frame_seq.load(Ordering::SeqCst),
+            source_frames_missed: stream_stats.source_frames_missed.load(Ordering::SeqCst),
+            source_gaps: stream_stats.source_gaps.load(Ordering::SeqCst),
+            subscribers,
+        }
+    }).collect();
+    
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    infos
+}
+
+fn stream_metrics(clients: &Clients, stats: &Stats, stream_name: &str, qos_stats: bool, supervisor: &PipelineSupervisor) -> Option<StreamMetrics> {
+    let (name, stream_stats) = stats.lock().unwrap()
+        .iter()
+        .find(|(k, _)| k.to_lowercase() == stream_name.to_lowercase())
+        .map(|(k, stream_stats)| (k.clone(), stream_stats.clone()))?;
+    let subscribers = clients.lock().unwrap()
+        .get(&name)
+        .map(|channels| channels.frames.receiver_count())
+        .unwrap_or(0);
+    
+    Some(StreamMetrics {
+        uptime_secs: stream_stats.started.lock().unwrap().map(|(_, since)| since.elapsed().as_secs()),
+        frames_received: stream_stats.frames_received.load(Ordering::SeqCst),
+        bytes_received: stream_stats.bytes_received.load(Ordering::SeqCst),
+        frame_seq: stream_stats.frame_seq.load(Ordering::SeqCst),
+        queue_dropped: stream_stats.queue_dropped.load(Ordering::SeqCst),
+        frames_skipped: stream_stats.frames_skipped.load(Ordering::SeqCst),
+        duplicate_frames: stream_stats.duplicate_frames.load(Ordering::SeqCst),
+        source_frames_missed: stream_stats.source_frames_missed.load(Ordering::SeqCst),
+        corrupt_frames: stream_stats.corrupt_frames.load(Ordering::SeqCst),
+        timestamp_drift: *stream_stats.timestamp_drift.lock().unwrap(),
+        subscribers,
+        client_frames_dropped: stream_stats.client_frames_dropped.load(Ordering::SeqCst),
+        qos: qos_stats.then(|| stream_stats.qos.snapshot()),
+        load_shedding: supervisor.is_load_shedding(),
+        preview: supervisor.effective_preview(&name),
+        name,
+    })
+}
+
+fn recording_infos(cameras: &[CameraConfig], supervisor: &PipelineSupervisor) -> Vec<RecordingInfo> {
+    let now = Utc::now();
+    cameras.iter().map(|camera| {
+        let schedule = camera.recording_schedule.as_ref().and_then(|config| schedule::Schedule::compile(config).ok());
+        let state = if supervisor.is_recording(&camera.name) == Some(true) {
+            "recording"
+        } else if supervisor.is_disk_full() && schedule::should_record(camera, now) {
+            "disk_full"
+        } else {
+            "idle"
+        };
+        RecordingInfo {
+            name: camera.name.clone(),
+            state,
+            scheduled: schedule.is_some(),
+            timezone: camera.recording_schedule.as_ref().map(|config| config.timezone.clone()),
+            next_change: schedule.and_then(|schedule| schedule.next_change(now)),
+        }
+    }).collect()
+}
+
+// Stream names in URLs are matched case-insensitively
+fn find_stream(clients: &Clients, stream_name: &str) -> Option<(String, StreamChannels)> {
+    let clients_lock = clients.lock().unwrap();
+    let found = clients_lock.iter()
+        .find(|(k, _)| k.to_lowercase() == stream_name.to_lowercase())
+        .map(|(k, channels)| (k.clone(), channels.clone()));
+    if found.is_none() {
+        println!("{}: Stream not found! Available: {:?}", stream_name, clients_lock.keys().collect::<Vec<_>>());
+    }
+    found
+}
+
+// A JSON `audio` header (codec, sample rate), then one binary message per Opus packet, ready
+// for a WebCodecs AudioDecoder. Listeners that fall behind skip packets rather than lag.
+async fn handle_audio_client(ws: WebSocket, clients: Clients, stream_name: String, addr: Option<SocketAddr>, access_log: Arc<AccessLog>) {
+    let client = access_log.client_label(addr);
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let Some((key, audio)) = find_stream(&clients, &stream_name).and_then(|(key, channels)| Some((key, channels.audio?))) else {
+        println!("{}: No audio-only stream", stream_name);
+        let _ = ws_tx.send(Message::close_with(4404u16, "no audio-only stream")).await;
+        return;
+    };
+    println!("New audio client {} connected to {}", client, key);
+    access_log.record(&client, &key, "connect audio");
+    access_log.audit.record("connect", Some(&key), addr.map(|addr| addr.ip()), Some("audio"));
+    
+    let mut rx = audio.subscribe();
+    let header = serde_json::json!({ "type": "audio", "codec": "opus", "sample_rate": audio::SAMPLE_RATE });
+    
+    let outgoing = async {
+        if ws_tx.send(Message::text(header.to_string())).await.is_err() {
+            return;
+        }
+        loop {
+            match rx.recv().await {
+                Ok(packet) => {
+                    if ws_tx.send(Message::binary(packet.to_vec())).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    };
+    // Nothing to read from the client, just notice when it goes away
+    let incoming = async {
+        while let Some(Ok(message)) = ws_rx.next().await {
+            if message.is_close() {
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        _ = outgoing => (),
+        _ = incoming => (),
+    }
+    
+    println!("Audio client {} disconnected from {}", client, key);
+    access_log.record(&client, &key, "disconnect audio");
+    access_log.audit.record("disconnect", Some(&key), addr.map(|addr| addr.ip()), Some("audio"));
+}
+
+// A JSON `init` message with the MIME type for MediaSource.addSourceBuffer and the init
+// segment, then fragmented MP4 as binary messages, starting at a fragment boundary. The
+// stream's H.264 encoder only runs while at least one of these is open.
+async fn handle_mse_client(ws: WebSocket, clients: Clients, supervisor: Arc<PipelineSupervisor>, stream_name: String, addr: Option<SocketAddr>, access_log: Arc<AccessLog>) {
+    let client = access_log.client_label(addr);
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    let Some((key, live)) = find_stream(&clients, &stream_name).and_then(|(key, channels)| Some((key, channels.live?))) else {
+        println!("{}: No video stream", stream_name);
+        let _ = ws_tx.send(Message::close_with(4404u16, "no video stream")).await;
+        return;
+    };
+    println!("New MSE client {} connected to {}", client, key);
+    access_log.record(&client, &key, "connect mse");
+    access_log.audit.record("connect", Some(&key), addr.map(|addr| addr.ip()), Some("mse"));
+    
+    let mut rx = live.mse.subscribe();
+    let _lease = supervisor.acquire_output(&key, BranchKind::Mse);
+    
+    let outgoing = async {
+        let mut sent_init: Option<Arc<[u8]>> = None;
+        let mut synced = false;
+        loop {
+            match rx.recv().await {
+                Ok(chunk) => {
+                    if chunk.fragment_start {
+                        let Some(init) = live.mse_init.lock().unwrap().clone() else { continue };
+                        // First fragment, or the encoder was restarted and wrote a new moov
+                        if !sent_init.as_ref().is_some_and(|sent| Arc::ptr_eq(sent, &init)) {
+                            let Some(mime) = branches::mse_mime(&init) else {
+                                println!("{}: No H.264 configuration in the MP4 header", key);
+                                return;
+                            };
+                            let header = serde_json::json!({ "type": "init", "mime": mime });
+                            if ws_tx.send(Message::text(header.to_string())).await.is_err()
+                                || ws_tx.send(Message::binary(init.to_vec())).await.is_err()
+                            {
+                                return;
+                            }
+                            sent_init = Some(init);
+                        }
+                        synced = true;
+                    }
+                    if synced && ws_tx.send(Message::binary(chunk.data.to_vec())).await.is_err() {
+                        return;
+                    }
+                }
+                // Fragments build on each other, pick up again at the next one
+                Err(broadcast::error::RecvError::Lagged(_)) => synced = false,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    };
+    // Nothing to read from the client, just notice when it goes away
+    let incoming = async {
+        while let Some(Ok(message)) = ws_rx.next().await {
+            if message.is_close() {
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        _ = outgoing => (),
+        _ = incoming => (),
+    }
+    
+    println!("MSE client {} disconnected from {}", client, key);
+    access_log.record(&client, &key, "disconnect mse");
+    access_log.audit.record("disconnect", Some(&key), addr.map(|addr| addr.ip()), Some("mse"));
+}
+
+// HLS files of a stream. Its output only runs while players keep fetching, so the first
+// request starts it and waits for the playlist to be written.
+async fn handle_hls_request(clients: Clients, supervisor: Arc<PipelineSupervisor>, stream_name: String, file: String, addr: Option<SocketAddr>, access_log: Arc<AccessLog>) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some((key, live)) = find_stream(&clients, &stream_name).and_then(|(key, channels)| Some((key, channels.live?))) else {
+        return Err(warp::reject::not_found());
+    };
+    if file.is_empty() || file.starts_with('.') || file.contains(['/', '\\', '\0']) {
+        return Err(warp::reject::not_found());
+    }
+    if supervisor.touch_hls(&key) {
+        let client = access_log.client_label(addr);
+        println!("HLS client {} started {}", client, key);
+        access_log.record(&client, &key, "connect hls");
+        access_log.audit.record("connect", Some(&key), addr.map(|addr| addr.ip()), Some("hls"));
+    }
+    
+    let path = live.hls_dir.join(&file);
+    let deadline = Instant::now() + HLS_START_WAIT;
+    let body = loop {
+        match tokio::fs::read(&path).await {
+            Ok(body) => break body,
+            // hlssink2 writes the playlist once the first segment is complete
+            Err(_) if file == branches::HLS_PLAYLIST && Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            }
+            Err(_) => return Err(warp::reject::not_found()),
+        }
+    };
+    let content_type = if file.ends_with(".m3u8") { "application/vnd.apple.mpegurl" } else { "video/mp2t" };
+    let response = warp::http::Response::builder()
+        .header(warp::http::header::CONTENT_TYPE, content_type)
+        .header(warp::http::header::CACHE_CONTROL, "no-cache")
+        .body(body.into())
+        .unwrap();
+    Ok(response)
+}
+
+// Logs an SSE client's disconnect once its event stream is dropped
+struct SseDisconnect {
+    client: String,
+    key: String,
+    addr: Option<SocketAddr>,
+    access_log: Arc<AccessLog>,
+}
+
+impl Drop for SseDisconnect {
+    fn drop(&mut self) {
+        println!("SSE client {} disconnected from {}", self.client, self.key);
+        self.access_log.record(&self.client, &self.key, "disconnect sse");
+        self.access_log.audit.record("disconnect", Some(&self.key), self.addr.map(|addr| addr.ip()), Some("sse"));
+    }
+}
+
+// Same frames as the WebSocket, base64-encoded into `frame` events (id = sequence number).
+// Roughly a third bigger on the wire, but plain HTTP gets through restrictive proxies.
+async fn handle_sse_client(
+    clients: Clients,
+    stream_name: String,
+    addr: Option<SocketAddr>,
+    access_log: Arc<AccessLog>,
+    headers: Option<warp::http::HeaderMap>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::Reply;
+    
+    let Some((key, channels)) = find_stream(&clients, &stream_name) else {
+        return Err(warp::reject::not_found());
+    };
+    let rx = channels.frames.subscribe();
     
-    // Create a MainLoop but don't run it immediately
-    let main_loop = glib::MainLoop::new(None, false);
+    let client = access_log.client_label(addr);
+    println!("New SSE client {} connected to {}", client, key);
+    access_log.record(&client, &key, "connect sse");
+    access_log.audit.record("connect", Some(&key), addr.map(|addr| addr.ip()), Some("sse"));
+    access_log.audit.record("subscribe", Some(&key), addr.map(|addr| addr.ip()), Some("main"));
     
-    // Create the resources structure and keep it alive
-    let resources = Arc::new(PipelineResources {
-        pipeline,
-        _main_loop: main_loop.clone(),
+    // warp drops the stream when the client goes away, which is the only sign of it
+    let disconnect = SseDisconnect { client, key, addr, access_log };
+    let frames = futures::stream::unfold((rx, channels.stats, disconnect), |(mut rx, stats, disconnect)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(frame) => {
+                    let event = warp::sse::Event::default()
+                        .event("frame")
+                        .id(frame.seq.to_string())
+                        .data(BASE64.encode(&frame.jpeg));
+                    return Some((Ok::<_, std::convert::Infallible>(event), (rx, stats, disconnect)));
+                }
+                // A slow client just misses frames
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    stats.client_frames_dropped.fetch_add(missed, Ordering::Relaxed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
     });
     
-    // Keep a global reference to resources to prevent them from being dropped
-    {
-        lazy_static::lazy_static! {
-            static ref PIPELINES: Mutex<Vec<Arc<PipelineResources>>> = Mutex::new(Vec::new());
+    let mut reply = warp::sse::reply(warp::sse::keep_alive().stream(frames)).into_response();
+    add_headers(&mut reply, headers.as_ref());
+    Ok(reply)
+}
+
+// {"type":"pipeline","decode_fps":..,"source_kbps":..,"width":..,"height":..}, rates over
+// the time since `previous`. source_kbps is null for local sources and decodebin, which
+// don't expose the camera's RTP. Measured by the server, unlike the page's receive fps.
+fn pipeline_message(stats: &StreamStats, previous: &mut (u64, u64, Instant)) -> serde_json::Value {
+    let now = (stats.frames_decoded.load(Ordering::Relaxed), stats.source_bytes.load(Ordering::Relaxed), Instant::now());
+    let secs = now.2.duration_since(previous.2).as_secs_f64().max(0.001);
+    let decode_fps = now.0.saturating_sub(previous.0) as f64 / secs;
+    let source_kbps = (now.1 > 0).then(|| now.1.saturating_sub(previous.1) as f64 * 8.0 / 1000.0 / secs);
+    *previous = now;
+    let resolution = *stats.source_resolution.lock().unwrap();
+    serde_json::json!({
+        "type": "pipeline",
+        "decode_fps": (decode_fps * 10.0).round() / 10.0,
+        "source_kbps": source_kbps.map(f64::round),
+        "width": resolution.map(|(width, _)| width),
+        "height": resolution.map(|(_, height)| height),
+    })
+}
+
+async fn handle_status_client(ws: WebSocket, status_hub: Arc<StatusHub>) {
+    println!("New status client connected");
+    let (mut ws_tx, mut ws_rx) = ws.split();
+    
+    // Start with the full picture, then only changes
+    let (mut snapshot, mut rx) = status_hub.subscribe();
+    
+    let outgoing = tokio::spawn(async move {
+        loop {
+            for status in snapshot.drain(..) {
+                let message = serde_json::json!({ "type": "status", "status": status });
+                if ws_tx.send(Message::text(message.to_string())).await.is_err() {
+                    return; // Client disconnected
+                }
+            }
+            
+            match rx.recv().await {
+                Ok(status) => snapshot.push(status),
+                // Missed some changes, resend everything
+                Err(broadcast::error::RecvError::Lagged(_)) => (snapshot, rx) = status_hub.subscribe(),
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
         }
-        
-        PIPELINES.lock().unwrap().push(resources.clone());
-    }
+    });
     
-    // Run the MainLoop in a separate thread
-    std::thread::spawn(move || {
-        main_loop.run();
+    // Nothing to read from the client, just notice when it goes away
+    let incoming = tokio::spawn(async move {
+        while let Some(Ok(_)) = ws_rx.next().await {}
     });
     
-    Ok(())
+    tokio::select! {
+        _ = incoming => (),
+        _ = outgoing => (),
+    }
+    println!("Status client disconnected");
 }
 
-async fn handle_ws_client(ws: WebSocket, clients: Clients, stream_name: String) {
-    println!("New client connected to {}", stream_name);
+#[allow(clippy::too_many_arguments)]
+async fn handle_ws_client(
+    ws: WebSocket,
+    clients: Clients,
+    supervisor: Arc<PipelineSupervisor>,
+    stream_name: String,
+    quality: Quality,
+    roi: Option<String>,
+    options: ViewerOptions,
+    addr: Option<SocketAddr>,
+    access_log: Arc<AccessLog>,
+) {
+    let Some((key, channels)) = find_stream(&clients, &stream_name) else { return };
+    let client = access_log.client_label(addr);
+    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+    println!("New client {} (#{}) connected to {}", client, client_id, key);
+    access_log.record(&client, &key, "connect");
+    access_log.audit.record("connect", Some(&key), addr.map(|addr| addr.ip()), Some("ws"));
     
     // Split the websocket
-    let (mut ws_tx, mut ws_rx) = ws.split();
+    let (ws_tx, mut ws_rx) = ws.split();
     
-    // Find the stream name case-insensitively and get its broadcast sender
-    let mut rx = {
-        let clients_lock = clients.lock().unwrap();
-        
-        // Print all available stream names for debugging
-        println!("Available streams: {:?}", clients_lock.keys().collect::<Vec<_>>());
-        
-        // Find the stream name case-insensitively
-        let found_key = clients_lock.keys()
-            .find(|k| k.to_lowercase() == stream_name.to_lowercase())
-            .cloned();
-        
-        if let Some(key) = found_key {
-            println!("Found matching stream: {}", key);
-            if let Some(senders) = clients_lock.get(&key) {
-                if let Some(sender) = senders.first() {
-                    println!("{}: Client successfully subscribed", key);
-                    sender.subscribe()
-                } else {
-                    println!("{}: No senders available", key);
-                    return;
-                }
-            } else {
-                println!("{}: Stream not found (no senders)!", stream_name);
-                return;
-            }
-        } else {
-            println!("{}: Stream not found! Available: {:?}", 
-                stream_name, 
-                clients_lock.keys().collect::<Vec<_>>());
-            return;
+    let (frames, quality) = match (quality, &channels.sub_frames) {
+        (Quality::Sub, Some(sub_frames)) => (sub_frames, Quality::Sub),
+        (Quality::Sub, None) => {
+            println!("{}: No substream, sending the main stream", key);
+            (&channels.frames, Quality::Main)
         }
+        (Quality::Main, _) => (&channels.frames, Quality::Main),
+    };
+    println!("{}: Client successfully subscribed ({:?})", key, quality);
+    let quality_name = match quality {
+        Quality::Main => "main",
+        Quality::Sub => "sub",
     };
+    access_log.audit.record("subscribe", Some(&key), addr.map(|addr| addr.ip()), Some(quality_name));
+    let mut rx = frames.subscribe();
+    let mut events_rx = channels.events.subscribe();
     
-    // Handle incoming messages (mostly ping/pong)
-    let incoming = tokio::spawn(async move {
+    // Per-client options toggled over the control channel
+    let overlay = Arc::new(AtomicBool::new(false));
+    let overlay_incoming = overlay.clone();
+    let sequence = Arc::new(AtomicBool::new(false));
+    let sequence_incoming = sequence.clone();
+    let paused = Arc::new(AtomicBool::new(false));
+    let paused_incoming = paused.clone();
+    let (seek_tx, mut seek_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (roi_tx, mut roi_rx) = tokio::sync::mpsc::unbounded_channel::<std::result::Result<Option<roi::Region>, String>>();
+    if let Some(spec) = roi {
+        let _ = roi_tx.send(roi::Region::parse(&spec).map(Some));
+    }
+    let stream_name_incoming = stream_name.clone();
+    
+    // Handle incoming messages: JSON control commands, everything else is ping/pong
+    let mut incoming = tokio::spawn(async move {
         while let Some(result) = ws_rx.next().await {
-            match result {
-                Ok(_) => (), // Ignore client messages
+            let msg = match result {
+                Ok(msg) => msg,
                 Err(_) => break, // Client disconnected
+            };
+            let Ok(text) = msg.to_str() else { continue };
+            
+            match serde_json::from_str::<ControlMessage>(text) {
+                Ok(ControlMessage::Overlay { enabled }) => overlay_incoming.store(enabled, Ordering::Relaxed),
+                Ok(ControlMessage::Sequence { enabled }) => sequence_incoming.store(enabled, Ordering::Relaxed),
+                Ok(ControlMessage::Pause { enabled }) => {
+                    println!("{}: Client {}", stream_name_incoming, if enabled { "paused" } else { "resumed" });
+                    paused_incoming.store(enabled, Ordering::Relaxed);
+                }
+                Ok(ControlMessage::Seek { offset_ms }) => {
+                    let _ = seek_tx.send(offset_ms);
+                }
+                Ok(ControlMessage::Roi { x, y, w, h }) => {
+                    let region = match (x, y, w, h) {
+                        (Some(x), Some(y), Some(w), Some(h)) => {
+                            let region = roi::Region { x, y, w, h };
+                            region.check().map(|_| Some(region))
+                        }
+                        (None, None, None, None) => Ok(None),
+                        _ => Err("roi needs all of x, y, w and h, or none of them".to_string()),
+                    };
+                    let _ = roi_tx.send(region);
+                }
+                Err(err) => println!("{}: Ignoring control message {:?}: {}", stream_name_incoming, text, err),
             }
         }
     });
     
-    // Send frames to client
-    let outgoing = tokio::spawn(async move {
-        while let Ok(jpeg_data) = rx.recv().await {
-            println!("Sending frame of size {} to client", jpeg_data.len());
-            if let Err(_) = ws_tx.send(Message::binary(jpeg_data)).await {
-                break; // Client disconnected
+    // Send frames to client, preceded by motion metadata when the overlay is on,
+    // and camera events as they arrive
+    let client_outgoing = client.clone();
+    let stream_name_outgoing = stream_name.clone();
+    let key_outgoing = key.clone();
+    let outbox = Outbox::new(ws_tx, options.max_send_buffer, format!("{}: Client {}", stream_name, client));
+    let mut outgoing = tokio::spawn(async move {
+        // Describe the stream before any frame, so the client can size its canvas for it:
+        // {"type":"hello","client_id":N,"stream":..,"quality":"main"|"sub","codec":"jpeg",
+        // "width":..,"height":..,"fps":..|null}. A substream's own frame rate isn't tracked,
+        // only its preview_fps cap is known.
+        let (width, height) = channels.stats.preview_size;
+        let source_fps = match quality {
+            Quality::Main => *channels.stats.source_fps.lock().unwrap(),
+            Quality::Sub => None,
+        };
+        let cap = channels.stats.preview_fps.map(f64::from);
+        let fps = match (source_fps, cap) {
+            (Some(fps), Some(cap)) => Some(fps.min(cap)),
+            (fps, cap) => fps.or(cap),
+        };
+        let hello = serde_json::json!({
+            "type": "hello",
+            "client_id": client_id,
+            "stream": key_outgoing,
+            "quality": quality_name,
+            "codec": "jpeg",
+            "width": width,
+            "height": height,
+            "fps": fps,
+        });
+        if !outbox.send(Message::text(hello.to_string())) {
+            return; // Client disconnected
+        }
+        
+        // Replay the last frame so a reconnecting viewer isn't left waiting for the next one.
+        // Only kept for the main stream, a substream viewer just waits a frame.
+        let latest = match quality {
+            Quality::Main => channels.stats.latest_frame.lock().unwrap().clone(),
+            Quality::Sub => None,
+        };
+        // The subscription was made before the replayed frame was read, so frames up to
+        // and including it may still be waiting in `rx`. Sending them after it would show
+        // the viewer an older picture after a newer one.
+        let mut replayed_seq = 0;
+        if let Some(frame) = latest {
+            replayed_seq = frame.seq;
+            if !outbox.send(Message::binary(frame.jpeg)) {
+                return; // Client disconnected
+            }
+        }
+        
+        let mut shown_box = None;
+        
+        // Buffered frames still to be replayed after a seek, each due at its offset from
+        // replay_start. Live frames are skipped until it runs out.
+        let mut replay: VecDeque<(Duration, Frame)> = VecDeque::new();
+        let mut replay_start = tokio::time::Instant::now();
+        
+        // A client that can't keep up lags on every frame, so drops are summed and
+        // logged once per interval rather than once per lag
+        let mut dropped = 0;
+        let mut drop_log = tokio::time::interval_at(tokio::time::Instant::now() + options.drop_log_interval, options.drop_log_interval);
+        
+        // Static scenes with frame skipping can go quiet for a long time, a ping keeps
+        // proxies from closing the connection. The browser's pong comes back as an
+        // ordinary incoming message and is ignored there.
+        let mut last_sent = tokio::time::Instant::now();
+        
+        // Decode rate and source bitrate since the previous pipeline message. Only the main
+        // stream's pipeline is counted, a substream viewer gets its numbers too.
+        let mut pipeline_tick = tokio::time::interval(PIPELINE_STATS_INTERVAL);
+        let mut pipeline_previous = (channels.stats.frames_decoded.load(Ordering::Relaxed), channels.stats.source_bytes.load(Ordering::Relaxed), Instant::now());
+        
+        // While zoomed in, this client's own encode replaces the shared preview frames
+        let mut zoom: Option<(RoiLease, tokio::sync::mpsc::Receiver<Vec<u8>>, roi::Region)> = None;
+        loop {
+            tokio::select! {
+                _ = pipeline_tick.tick(), if options.pipeline_stats => {
+                    let message = pipeline_message(&channels.stats, &mut pipeline_previous);
+                    if !outbox.send(Message::text(message.to_string())) {
+                        break; // Client disconnected
+                    }
+                }
+                _ = drop_log.tick() => {
+                    if dropped > 0 {
+                        println!("{}: Client {} dropped {} frames in the last {:?}", stream_name_outgoing, client_outgoing, dropped, options.drop_log_interval);
+                        dropped = 0;
+                    }
+                }
+                _ = tokio::time::sleep_until(last_sent + options.heartbeat), if !options.heartbeat.is_zero() => {
+                    if !outbox.send(Message::ping(Vec::new())) {
+                        break; // Client disconnected
+                    }
+                    last_sent = tokio::time::Instant::now();
+                }
+                frame = rx.recv() => {
+                    let frame = match frame {
+                        Ok(frame) => frame,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            dropped += missed;
+                            channels.stats.client_frames_dropped.fetch_add(missed, Ordering::Relaxed);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if frame.seq <= replayed_seq {
+                        continue;
+                    }
+                    // Keep draining the channel while paused (or replaying, or zoomed in) so
+                    // resuming starts at the live edge
+                    if paused.load(Ordering::Relaxed) || !replay.is_empty() || zoom.is_some() {
+                        continue;
+                    }
+                    if !send_frame(&outbox, frame, overlay.load(Ordering::Relaxed), sequence.load(Ordering::Relaxed), &mut shown_box) {
+                        break; // Client disconnected
+                    }
+                    last_sent = tokio::time::Instant::now();
+                }
+                _ = tokio::time::sleep_until(replay_start + replay.front().map_or(Duration::ZERO, |(at, _)| *at)), if !replay.is_empty() => {
+                    let Some((_, frame)) = replay.pop_front() else { continue };
+                    if !paused.load(Ordering::Relaxed) {
+                        if !send_frame(&outbox, frame, overlay.load(Ordering::Relaxed), sequence.load(Ordering::Relaxed), &mut shown_box) {
+                            break; // Client disconnected
+                        }
+                        last_sent = tokio::time::Instant::now();
+                    }
+                    if replay.is_empty() {
+                        let message = serde_json::json!({ "type": "seek", "live": true });
+                        if !outbox.send(Message::text(message.to_string())) {
+                            break; // Client disconnected
+                        }
+                    }
+                }
+                Some(jpeg) = async { zoom.as_mut()?.1.recv().await }, if zoom.is_some() => {
+                    if paused.load(Ordering::Relaxed) || !replay.is_empty() {
+                        continue;
+                    }
+                    if !outbox.send(Message::binary(jpeg)) {
+                        break; // Client disconnected
+                    }
+                    last_sent = tokio::time::Instant::now();
+                }
+                Some(region) = roi_rx.recv() => {
+                    // The reply carries the region in effect afterwards, null for the shared preview
+                    let error = match region {
+                        Err(error) => Some(error),
+                        Ok(None) => {
+                            zoom = None;
+                            None
+                        }
+                        Ok(Some(region)) => match &mut zoom {
+                            Some((lease, _, current)) => {
+                                lease.set(region);
+                                *current = region;
+                                None
+                            }
+                            None => {
+                                let (frames_tx, frames_rx) = tokio::sync::mpsc::channel(roi::ROI_CHANNEL_FRAMES);
+                                match supervisor.acquire_roi(&key_outgoing, region, frames_tx) {
+                                    Some(lease) => {
+                                        zoom = Some((lease, frames_rx, region));
+                                        None
+                                    }
+                                    None => Some("zoom isn't enabled for this camera, or too many viewers are using it".to_string()),
+                                }
+                            }
+                        },
+                    };
+                    let region = zoom.as_ref().map(|(_, _, region)| *region);
+                    let message = serde_json::json!({ "type": "roi", "region": region, "error": error });
+                    if !outbox.send(Message::text(message.to_string())) {
+                        break; // Client disconnected
+                    }
+                }
+                Some(offset_ms) = seek_rx.recv() => {
+                    replay.clear();
+                    let message = match (offset_ms < 0, quality) {
+                        (false, _) => serde_json::json!({ "type": "seek", "live": true }),
+                        // The history only holds the main stream
+                        (true, Quality::Sub) => serde_json::json!({ "type": "seek", "live": true, "error": "no history for the substream" }),
+                        (true, Quality::Main) => {
+                            let wanted = Duration::from_millis(offset_ms.unsigned_abs());
+                            let (reached, frames) = channels.stats.history.since(wanted);
+                            replay.extend(frames);
+                            replay_start = tokio::time::Instant::now();
+                            match replay.is_empty() {
+                                true => serde_json::json!({ "type": "seek", "live": true, "error": "nothing buffered" }),
+                                // Less than asked for when the request reaches past the buffer
+                                false => serde_json::json!({ "type": "seek", "live": false, "offset_ms": -(reached.as_millis() as i64) }),
+                            }
+                        }
+                    };
+                    if !outbox.send(Message::text(message.to_string())) {
+                        break; // Client disconnected
+                    }
+                }
+                event = events_rx.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let message = serde_json::json!({
+                        "type": "event",
+                        "source": event.source,
+                        "topic": event.topic,
+                        "active": event.active,
+                        "time": event.time,
+                    });
+                    if !outbox.send(Message::text(message.to_string())) {
+                        break; // Client disconnected
+                    }
+                }
             }
         }
     });
     
-    // Wait for either task to complete (client disconnect)
+    // Wait for either task to complete (client disconnect), then stop the other one so the
+    // socket is closed even if the client is still there but not reading
     tokio::select! {
-        _ = incoming => println!("Incoming task completed"),
-        _ = outgoing => println!("Outgoing task completed"),
+        _ = &mut incoming => println!("Incoming task completed"),
+        _ = &mut outgoing => println!("Outgoing task completed"),
+    }
+    incoming.abort();
+    outgoing.abort();
+    
+    println!("Client {} disconnected from {}", client, key);
+    access_log.record(&client, &key, "disconnect");
+    access_log.audit.record("disconnect", Some(&key), addr.map(|addr| addr.ip()), Some("ws"));
+}
+
+// Queue one frame for a viewer, preceded by its motion box when the overlay is on and by its
+// sequence metadata when the client asked for it. False once the viewer should be dropped.
+fn send_frame(
+    outbox: &Outbox,
+    frame: Frame,
+    overlay: bool,
+    sequence: bool,
+    shown_box: &mut Option<MotionBox>,
+) -> bool {
+    if overlay {
+        // Also send one `null` box when motion stops so the client clears it
+        if frame.motion_box.is_some() || shown_box.is_some() {
+            let event = serde_json::json!({ "type": "motion", "box": frame.motion_box });
+            if !outbox.send(Message::text(event.to_string())) {
+                return false;
+            }
+        }
+        *shown_box = frame.motion_box;
+    }
+    
+    if sequence {
+        let meta = serde_json::json!({
+            "type": "frame",
+            "seq": frame.seq,
+            "captured_at": frame.captured_at.to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "clock": if frame.camera_clock { "camera" } else { "server" },
+        });
+        if !outbox.send(Message::text(meta.to_string())) {
+            return false;
+        }
     }
     
-    println!("Client disconnected from {}", stream_name);
+    frame_log!("Sending frame of size {} to client", frame.jpeg.len());
+    outbox.send(Message::binary(frame.jpeg))
+}
+
+// For text and attribute values on the generated page
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+// A quoted JavaScript string literal for an inline <script>. JSON takes care of quotes,
+// backslashes and line breaks; '<', '>' and '&' are escaped too, so a value can't close
+// the script element or open a comment in it.
+fn js_string(text: &str) -> String {
+    serde_json::to_string(text)
+        .unwrap_or_default()
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+        .replace('\u{2028}', "\\u2028")
+        .replace('\u{2029}', "\\u2029")
+}
+
+// Footer update for {"type":"pipeline"} messages, only put on the page with pipeline_stats
+const PIPELINE_STATS_JS: &str = r#"// Ground truth from the server: decode rate, camera bitrate, resolution
+            window.nvrPipelineStats = function(tile, message) {
+                const footer = tile.querySelector('.stream-footer .pipeline');
+                if (!footer) {
+                    return;
+                }
+                const parts = [];
+                if (message.width && message.height) {
+                    parts.push(message.width + 'x' + message.height);
+                }
+                parts.push(message.decode_fps.toFixed(1) + ' fps decoded');
+                if (message.source_kbps !== null) {
+                    parts.push(message.source_kbps + ' kbps');
+                }
+                footer.textContent = parts.join(' · ');
+            };"#;
+
+// A tile on the generated page. The internal name is used for element IDs and WebSocket
+// paths, the display name only for what the viewer reads.
+struct PageStream {
+    name: String,
+    display_name: String,
+    has_substream: bool,
+    interpolate: bool,
+    // Preview size, the canvas starts out at it
+    size: (u32, u32),
+    scale_mode: ScaleMode,
 }
 
-fn create_html_file(stream_names: &[String]) -> Result<()> {
-    use std::fs::File;
-    use std::io::Write;
+// Write page_dir/index.html, unless the one already there is the same page. The page's
+// hash is stored in the page itself, so a restart with an unchanged setup and template
+// leaves the file (and its modification time) alone. True if the file was written.
+fn create_html_file(streams: &[PageStream], settings: &config::Settings) -> error::Result<bool> {
+    let path = settings.page_dir.join("index.html");
+    let stamp = format!(r#"<meta name="nvr-page" content="{:016x}">"#, page_hash(&page_html(streams, settings, "")));
+    if std::fs::read_to_string(&path).is_ok_and(|page| page.contains(&stamp)) {
+        println!("Viewer page unchanged, keeping {}", path.display());
+        return Ok(false);
+    }
+    
+    let html = page_html(streams, settings, &stamp);
+    std::fs::create_dir_all(&settings.page_dir)?;
+    let mut file = File::create(&path)?;
+    file.write_all(html.as_bytes())?;
+    println!("Wrote viewer page for {} streams to {}", streams.len(), path.display());
     
+    Ok(true)
+}
+
+// Of the page as rendered without its stamp, so any change to the streams, settings or
+// template changes it. DefaultHasher may change between Rust releases, which only costs
+// one rewrite.
+fn page_hash(html: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    html.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn page_html(streams: &[PageStream], settings: &config::Settings, stamp: &str) -> String {
+    let base_path = settings.base_path.as_str();
     let mut html = r#"
     <!DOCTYPE html>
     <html>
     <head>
         <title>CCTV Surveillance System</title>
         <meta name="viewport" content="width=device-width, initial-scale=1.0">
-        <style>
-            body {
-                font-family: 'Segoe UI', Tahoma, Geneva, Verdana, sans-serif;
-                margin: 0;
-                padding: 0;
-                background-color: #1e1e1e;
-                color: #e0e0e0;
-                overflow: hidden;
-            }
-            .header {
-                background-color: #333;
-                color: white;
-                padding: 10px 20px;
-                display: flex;
-                justify-content: space-between;
-                align-items: center;
-                border-bottom: 1px solid #444;
-            }
-            .header h1 {
-                margin: 0;
-                font-size: 18px;
-                font-weight: 500;
-            }
-            .datetime {
-                font-size: 14px;
-                text-align: right;
-            }
-            .container {
-                display: grid;
-                grid-template-columns: repeat(3, 1fr);
-                gap: 8px;
-                padding: 8px;
-                height: calc(100vh - 60px);
-            }
-            .stream {
-                background: #2a2a2a;
-                border-radius: 4px;
-                overflow: hidden;
-                position: relative;
-                box-shadow: 0 2px 4px rgba(0,0,0,0.3);
-            }
-            .stream-header {
-                background: rgba(0,0,0,0.7);
-                color: white;
-                padding: 5px 10px;
-                position: absolute;
-                top: 0;
-                left: 0;
-                right: 0;
-                z-index: 10;
-                display: flex;
-                justify-content: space-between;
-                font-size: 12px;
-            }
-            .stream-name {
-                font-weight: bold;
-            }
-            .status {
-                display: flex;
-                align-items: center;
-            }
-            .status-dot {
-                height: 8px;
-                width: 8px;
-                border-radius: 50%;
-                background-color: #4CAF50;
-                margin-right: 5px;
-            }
-            .status-text {
-                font-size: 11px;
-            }
-            canvas {
-                width: 100%;
-                height: 100%;
-                background: #000;
-                display: block;
-                object-fit: cover;
-            }
-            .stream-footer {
-                background: rgba(0,0,0,0.7);
-                color: white;
-                padding: 5px 10px;
-                position: absolute;
-                bottom: 0;
-                left: 0;
-                right: 0;
-                z-index: 10;
-                display: flex;
-                justify-content: space-between;
-                font-size: 11px;
-            }
-            .controls {
-                position: absolute;
-                top: 50%;
-                left: 50%;
-                transform: translate(-50%, -50%);
-                display: flex;
-                gap: 10px;
-                opacity: 0;
-                transition: opacity 0.3s;
-                z-index: 5;
-            }
-            .stream:hover .controls {
-                opacity: 1;
-            }
-            .control-btn {
-                width: 36px;
-                height: 36px;
-                border-radius: 50%;
-                background: rgba(0,0,0,0.7);
-                border: 1px solid rgba(255,255,255,0.3);
-                color: white;
-                display: flex;
-                align-items: center;
-                justify-content: center;
-                cursor: pointer;
-            }
-            .control-btn:hover {
-                background: rgba(0,0,0,0.9);
-            }
-            .toolbar {
-                background: #333;
-                padding: 5px 10px;
-                display: flex;
-                justify-content: center;
-                gap: 20px;
-                border-top: 1px solid #444;
-            }
-            .toolbar-btn {
-                background: transparent;
-                border: none;
-                color: #ddd;
-                cursor: pointer;
-                padding: 5px 10px;
-                font-size: 13px;
-                display: flex;
-                align-items: center;
-                gap: 5px;
-            }
-            .toolbar-btn:hover {
-                color: white;
-                background: #444;
-                border-radius: 3px;
-            }
-            .stats {
-                position: absolute;
-                bottom: 25px;
-                right: 10px;
-                background: rgba(0,0,0,0.5);
-                color: #aaa;
-                font-size: 10px;
-                padding: 2px 5px;
-                border-radius: 3px;
-                z-index: 15;
-            }
-            svg {
-                width: 16px;
-                height: 16px;
-                fill: currentColor;
-            }
-            .control-btn svg {
-                width: 20px;
-                height: 20px;
-            }
-        </style>
+        {stamp}
+        <script>
+            // Saved theme applied before the stylesheet, so a light page doesn't flash dark
+            try {
+                document.documentElement.dataset.theme = localStorage.getItem('nvr-theme') || 'dark';
+            } catch (err) {}
+        </script>
+        <link rel="icon" href="{base_path}/static/favicon.svg" type="image/svg+xml">
+        <link rel="stylesheet" href="{base_path}/static/nvr.css">
     </head>
     <body>
         <div class="header">
             <h1>CCTV Surveillance System</h1>
+            <div class="pager" id="pager" hidden>
+                <button class="pager-btn" id="prev-page" title="Previous page">&lsaquo;</button>
+                <span id="page-label"></span>
+                <button class="pager-btn" id="next-page" title="Next page">&rsaquo;</button>
+            </div>
+            <div class="notice" id="mse-notice" hidden></div>
             <div class="datetime" id="datetime">Loading...</div>
         </div>
         <div class="container">
-    "#.to_string();
+    "#.replace("{base_path}", base_path).replace("{stamp}", stamp);
     
-    for name in stream_names {
+    for PageStream { name, display_name, has_substream, size, scale_mode, .. } in streams {
+        // Main/sub switch, only for cameras that have a substream
+        let quality_btn = match has_substream {
+            true => r#"<div class="control-btn quality-btn" title="Switch main/sub stream">
+                        <svg viewBox="0 0 24 24">
+                            <path d="M21,3H3C1.89,3 1,3.89 1,5V19A2,2 0 0,0 3,21H21A2,2 0 0,0 23,19V5C23,3.89 22.1,3 21,3M21,19H3V5H21V19M5,14H7V10H9V14H11V8H5V14M13,8V14H17A2,2 0 0,0 19,12V10A2,2 0 0,0 17,8H13M15,10H17V12H15V10Z" />
+                        </svg>
+                    </div>"#,
+            false => "",
+        };
+        // The server's own decode rate, bitrate and resolution, filled in by nvrPipelineStats
+        let pipeline = match settings.pipeline_stats {
+            true => r#"<div class="pipeline"></div>"#,
+            false => "",
+        };
+        let id = escape_html(&name.to_lowercase());
+        // The canvas is fitted into the tile the same way the camera was fitted into the preview
+        let fit = match scale_mode {
+            ScaleMode::Contain => "fit-contain",
+            ScaleMode::Cover => "fit-cover",
+            ScaleMode::Stretch => "fit-stretch",
+        };
         html.push_str(&format!(r#"
             <div class="stream">
                 <div class="stream-header">
@@ -463,9 +3331,10 @@ fn create_html_file(stream_names: &[String]) -> Result<()> {
                         <div class="status-text">LIVE</div>
                     </div>
                 </div>
-                <canvas id="canvas-{}" width="640" height="360"></canvas>
+                <canvas id="canvas-{}" class="{}" width="{}" height="{}"></canvas>
                 <div class="stream-footer">
                     <div class="fps" id="fps-{}">0 FPS</div>
+                    {}
                     <div class="location">{}</div>
                 </div>
                 <div class="controls">
@@ -474,7 +3343,7 @@ fn create_html_file(stream_names: &[String]) -> Result<()> {
                             <path d="M17.65,6.35C16.2,4.9 14.21,4 12,4A8,8 0 0,0 4,12A8,8 0 0,0 12,20C15.73,20 18.84,17.45 19.73,14H17.65C16.83,16.33 14.61,18 12,18A6,6 0 0,1 6,12A6,6 0 0,1 12,6C13.66,6 15.14,6.69 16.22,7.78L13,11H20V4L17.65,6.35Z" />
                         </svg>
                     </div>
-                    <div class="control-btn">
+                    <div class="control-btn pause-btn" title="Pause">
                         <svg viewBox="0 0 24 24">
                             <path d="M14,19H18V5H14M6,19H10V5H6V19Z" />
                         </svg>
@@ -484,13 +3353,19 @@ fn create_html_file(stream_names: &[String]) -> Result<()> {
                             <path d="M12,15.5A3.5,3.5 0 0,1 8.5,12A3.5,3.5 0 0,1 12,8.5A3.5,3.5 0 0,1 15.5,12A3.5,3.5 0 0,1 12,15.5M19.43,12.97C19.47,12.65 19.5,12.33 19.5,12C19.5,11.67 19.47,11.34 19.43,11L21.54,9.37C21.73,9.22 21.78,8.95 21.66,8.73L19.66,5.27C19.54,5.05 19.27,4.96 19.05,5.05L16.56,6.05C16.04,5.66 15.5,5.32 14.87,5.07L14.5,2.42C14.46,2.18 14.25,2 14,2H10C9.75,2 9.54,2.18 9.5,2.42L9.13,5.07C8.5,5.32 7.96,5.66 7.44,6.05L4.95,5.05C4.73,4.96 4.46,5.05 4.34,5.27L2.34,8.73C2.21,8.95 2.27,9.22 2.46,9.37L4.57,11C4.53,11.34 4.5,11.67 4.5,12C4.5,12.33 4.53,12.65 4.57,12.97L2.46,14.63C2.27,14.78 2.21,15.05 2.34,15.27L4.34,18.73C4.46,18.95 4.73,19.03 4.95,18.95L7.44,17.94C7.96,18.34 8.5,18.68 9.13,18.93L9.5,21.58C9.54,21.82 9.75,22 10,22H14C14.25,22 14.46,21.82 14.5,21.58L14.87,18.93C15.5,18.67 16.04,18.34 16.56,17.94L19.05,18.95C19.27,19.03 19.54,18.95 19.66,18.73L21.66,15.27C21.78,15.05 21.73,14.78 21.54,14.63L19.43,12.97Z" />
                         </svg>
                     </div>
+                    {}
+                    <div class="control-btn overlay-btn" title="Motion overlay">
+                        <svg viewBox="0 0 24 24">
+                            <path d="M13.5,5.5C14.59,5.5 15.5,4.58 15.5,3.5C15.5,2.38 14.59,1.5 13.5,1.5C12.39,1.5 11.5,2.38 11.5,3.5C11.5,4.58 12.39,5.5 13.5,5.5M9.89,19.38L10.89,15L13,17V23H15V15.5L12.89,13.5L13.5,10.5C14.79,12 16.79,13 19,13V11C17.09,11 15.5,10 14.69,8.58L13.69,7C13.29,6.38 12.69,6 12,6C11.69,6 11.5,6.08 11.19,6.08L6,8.28V13H8V9.58L9.79,8.88L8.19,17L3.29,16L2.89,18L9.89,19.38Z" />
+                        </svg>
+                    </div>
                 </div>
                 <div class="stats" id="stats-{}"></div>
             </div>
-        "#, name, name.to_lowercase(), name.to_lowercase(), name, name.to_lowercase()));
+        "#, escape_html(display_name), id, fit, size.0, size.1, id, pipeline, escape_html(display_name), quality_btn, id));
     }
     
-    html.push_str(r#"
+    html.push_str(&r#"
         </div>
         <div class="toolbar">
             <button class="toolbar-btn">
@@ -511,6 +3386,12 @@ fn create_html_file(stream_names: &[String]) -> Result<()> {
                 </svg>
                 Settings
             </button>
+            <button class="toolbar-btn" id="theme-btn" title="Switch light/dark theme">
+                <svg viewBox="0 0 24 24">
+                    <path d="M12,2A10,10 0 0,0 2,12A10,10 0 0,0 12,22A10,10 0 0,0 22,12A10,10 0 0,0 12,2M12,4A8,8 0 0,1 20,12A8,8 0 0,1 12,20V4Z" />
+                </svg>
+                Theme
+            </button>
             <button class="toolbar-btn" id="fullscreen-btn">
                 <svg viewBox="0 0 24 24">
                     <path d="M5,5H10V7H7V10H5V5M14,5H19V10H17V7H14V5M17,14H19V19H14V17H17V14M10,17V19H5V14H7V17H10Z" />
@@ -526,141 +3407,365 @@ fn create_html_file(stream_names: &[String]) -> Result<()> {
         </div>
 
         <script>
-            // Update date and time
-            function updateDateTime() {
-                const now = new Date();
-                const dateString = now.toLocaleDateString();
-                const timeString = now.toLocaleTimeString();
-                document.getElementById('datetime').textContent = `${dateString} ${timeString}`;
-            }
-            
-            setInterval(updateDateTime, 1000);
-            updateDateTime();
-            
-            function setupStream(streamName) {
-                const canvas = document.getElementById('canvas-' + streamName.toLowerCase());
-                const ctx = canvas.getContext('2d');
-                const stats = document.getElementById('stats-' + streamName.toLowerCase());
-                const fpsElement = document.getElementById('fps-' + streamName.toLowerCase());
-                const statusDot = canvas.parentElement.querySelector('.status-dot');
-                
-                ctx.fillStyle = 'black';
-                ctx.fillRect(0, 0, canvas.width, canvas.height);
-                
-                // Draw text on canvas
-                ctx.fillStyle = 'white';
-                ctx.font = '16px Arial';
-                ctx.textAlign = 'center';
-                ctx.fillText('Connecting to ' + streamName + '...', canvas.width/2, canvas.height/2);
-                
-                let frameCount = 0;
-                let lastTime = Date.now();
-                let fps = 0;
-                
-                // Connect to WebSocket
-                const ws = new WebSocket('ws://' + window.location.host + '/ws/' + streamName.toLowerCase());
-                
-                ws.binaryType = 'arraybuffer';
-                
-                ws.onopen = function() {
-                    console.log('Connected to ' + streamName);
-                    stats.textContent = 'Connected';
-                    statusDot.style.backgroundColor = '#4CAF50'; // Green
-                };
-                
-                ws.onmessage = function(event) {
-                    // Calculate FPS
-                    frameCount++;
-                    const now = Date.now();
-                    if (now - lastTime >= 1000) {
-                        fps = frameCount;
-                        frameCount = 0;
-                        lastTime = now;
-                        fpsElement.textContent = fps + ' FPS';
-                    }
-                    
-                    // Update stats
-                    stats.textContent = `${(event.data.byteLength / 1024).toFixed(1)} KB`;
-                    
-                    const blob = new Blob([event.data], {type: 'image/jpeg'});
-                    const url = URL.createObjectURL(blob);
-                    const img = new Image();
-                    
-                    img.onload = function() {
-                        ctx.drawImage(img, 0, 0, canvas.width, canvas.height);
-                        URL.revokeObjectURL(url);
-                    };
-                    
-                    img.onerror = function(err) {
-                        console.error(`Error loading image for ${streamName}:`, err);
-                        statusDot.style.backgroundColor = 'red';
-                    };
-                    
-                    img.src = url;
-                };
-                
-                ws.onclose = function() {
-                    console.log('Disconnected from ' + streamName);
-                    statusDot.style.backgroundColor = '#FF9800'; // Orange
-                    
-                    // Draw text on canvas
-                    ctx.fillStyle = 'black';
-                    ctx.fillRect(0, 0, canvas.width, canvas.height);
-                    ctx.fillStyle = 'red';
-                    ctx.font = '16px Arial';
-                    ctx.textAlign = 'center';
-                    ctx.fillText('Connection lost. Reconnecting...', canvas.width/2, canvas.height/2);
-                    
-                    // Try to reconnect after a delay
-                    setTimeout(() => setupStream(streamName), 5000);
-                };
-                
-                ws.onerror = function(err) {
-                    console.error('WebSocket Error for ' + streamName + ':', err);
-                    statusDot.style.backgroundColor = 'red';
-                };
-                
-                // Fullscreen toggle
-                canvas.addEventListener('dblclick', function() {
-                    if (!document.fullscreenElement) {
-                        canvas.parentElement.requestFullscreen().catch(err => {
-                            console.error(`Could not enter fullscreen: ${err.message}`);
-                        });
-                    } else {
-                        document.exitFullscreen();
-                    }
-                });
-            }
-            
-            // Setup all streams
-    "#);
+            window.NVR_BASE_PATH = {base_path_js};
+            window.NVR_STREAM_PORT = {stream_port};
+            window.NVR_RECONNECT_GRACE_MS = {reconnect_grace_ms};
+            window.NVR_CAMERAS_PER_PAGE = {cameras_per_page};
+            // H.264 over Media Source Extensions only where the browser says it can play it,
+            // the MJPEG canvas everywhere else (and a notice that it's the fallback)
+            window.NVR_MSE_MIME = '{mse_mime}';
+            window.NVR_MSE_WANTED = {prefer_mse};
+            window.NVR_MSE = window.NVR_MSE_WANTED && 'MediaSource' in window
+                && MediaSource.isTypeSupported(window.NVR_MSE_MIME);
+            {pipeline_stats}
+        </script>
+        <script src="{base_path}/static/nvr.js"></script>
+        <script>
+            // Streams are connected page by page
+            showPages([
+    "#
+        .replace("{base_path_js}", &js_string(base_path))
+        .replace("{base_path}", base_path)
+        .replace("{stream_port}", &settings.stream_address.map_or("null".to_string(), |address| address.port().to_string()))
+        .replace("{reconnect_grace_ms}", &(settings.reconnect_grace_secs * 1000).to_string())
+        .replace("{cameras_per_page}", &settings.cameras_per_page.to_string())
+        .replace("{mse_mime}", branches::MSE_PROBE_MIME)
+        .replace("{prefer_mse}", &settings.prefer_mse.to_string())
+        .replace("{pipeline_stats}", match settings.pipeline_stats {
+            true => PIPELINE_STATS_JS,
+            false => "",
+        }));
     
-    for name in stream_names {
-        html.push_str(&format!("            setupStream('{}');\n", name));
+    for PageStream { name, display_name, has_substream, interpolate, .. } in streams {
+        html.push_str(&format!("                [{}, {}, {}, {}],\n", js_string(name), js_string(display_name), has_substream, interpolate));
     }
     
     html.push_str(r#"
-            // Toolbar buttons
-            document.getElementById('fullscreen-btn').addEventListener('click', function() {
-                if (!document.fullscreenElement) {
-                    document.documentElement.requestFullscreen().catch(err => {
-                        console.error(`Could not enter fullscreen: ${err.message}`);
-                    });
-                } else {
-                    document.exitFullscreen();
-                }
-            });
+            ]);
         </script>
     </body>
     </html>
     "#);
     
-    // Create src directory if it doesn't exist
-    std::fs::create_dir_all("src")?;
-    
-    // Write the HTML file
-    let mut file = File::create("src/index.html")?;
-    file.write_all(html.as_bytes())?;
-    
-    Ok(())
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Settings as they resolve with no flags or config file
+    fn default_settings() -> config::Settings {
+        config::resolve_config(&Cli::parse_from(["rtspstream"])).unwrap()
+    }
+
+    fn page_stream(name: &str) -> PageStream {
+        PageStream {
+            name: name.to_string(),
+            display_name: name.to_string(),
+            has_substream: false,
+            interpolate: false,
+            size: (640, 360),
+            scale_mode: ScaleMode::Contain,
+        }
+    }
+
+    fn test_frame(seq: u64) -> Frame {
+        Frame { jpeg: vec![seq as u8], motion_box: None, seq, captured_at: Utc::now(), camera_clock: false }
+    }
+
+    fn test_channels(frames: broadcast::Sender<Frame>, stats: Arc<StreamStats>) -> StreamChannels {
+        StreamChannels {
+            frames,
+            sub_frames: None,
+            events: broadcast::channel(16).0,
+            event_log: None,
+            audio: None,
+            live: None,
+            stats,
+        }
+    }
+
+    fn test_clients(name: &str, frames: broadcast::Sender<Frame>, stats: Arc<StreamStats>) -> Clients {
+        Arc::new(Mutex::new(HashMap::from([(name.to_string(), test_channels(frames, stats))])))
+    }
+
+    pub(crate) fn test_supervisor() -> Arc<PipelineSupervisor> {
+        PipelineSupervisor::new(Arc::new(AuditLog::open(None, 0, 0, false).unwrap()), ShedLimits { quality: 0, fps: None })
+    }
+
+    // A viewer on its own WebSocket, connected the way the ws route connects one
+    async fn connect_viewer(clients: &Clients, supervisor: &Arc<PipelineSupervisor>, stream_name: &str, quality: Quality) -> warp::test::WsClient {
+        let (clients, supervisor, stream_name) = (clients.clone(), supervisor.clone(), stream_name.to_string());
+        let audit = Arc::new(AuditLog::open(None, 0, 0, false).unwrap());
+        let access_log = Arc::new(AccessLog { log_ips: false, file: None, audit });
+        let options = ViewerOptions {
+            drop_log_interval: Duration::from_secs(60),
+            heartbeat: Duration::ZERO,
+            max_send_buffer: 1 << 20,
+            pipeline_stats: false,
+        };
+        let route = warp::ws().map(move |ws: warp::ws::Ws| {
+            let (clients, supervisor, stream_name, access_log) = (clients.clone(), supervisor.clone(), stream_name.clone(), access_log.clone());
+            ws.on_upgrade(move |socket| handle_ws_client(socket, clients, supervisor, stream_name, quality, None, options, None, access_log))
+        });
+        warp::test::ws().handshake(route).await.unwrap()
+    }
+
+    // A camera as the config file describes it
+    pub(crate) fn test_camera(yaml: &str) -> CameraConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    // What the supervisor would build `camera` from, and a subscriber to its frames
+    pub(crate) fn test_spec(camera: CameraConfig) -> (PipelineSpec, broadcast::Receiver<Frame>) {
+        gst::init().unwrap();
+        let (tx, rx) = broadcast::channel(16);
+        let options = PipelineOptions {
+            queue_size: 4,
+            debug_frames: 0,
+            debug_dir: PathBuf::from("debug"),
+            recordings_dir: std::env::temp_dir().join(format!("nvr-recordings-test-{}", std::process::id())),
+            qos_stats: false,
+            seek_buffer: None,
+            start_timeout: None,
+            processors: Vec::new(),
+            shed_fps: false,
+            broadcast_pool: None,
+        };
+        let stats = Arc::new(StreamStats {
+            preview_size: (camera.width, camera.height),
+            backoff: Backoff::new(camera.reconnect),
+            ..Default::default()
+        });
+        let spec = PipelineSpec {
+            camera,
+            user: String::new(),
+            pass: String::new(),
+            tx,
+            audio_tx: None,
+            events: broadcast::channel(16).0,
+            live: None,
+            stats,
+            options,
+        };
+        (spec, rx)
+    }
+
+    pub(crate) async fn next_frame(rx: &mut broadcast::Receiver<Frame>) -> Frame {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(10), rx.recv()).await.expect("no frame within 10s") {
+                Ok(frame) => return frame,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => panic!("frames channel closed"),
+            }
+        }
+    }
+
+    fn jpeg_size(jpeg: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory(jpeg).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn unchanged_page_is_not_rewritten() {
+        let dir = std::env::temp_dir().join(format!("nvr-page-test-{}", std::process::id()));
+        let mut settings = default_settings();
+        settings.page_dir = dir.clone();
+        let streams = vec![page_stream("front_door"), page_stream("yard")];
+
+        assert_eq!(page_html(&streams, &settings, ""), page_html(&streams, &settings, ""));
+        assert!(create_html_file(&streams, &settings).unwrap());
+        let written = std::fs::read_to_string(dir.join("index.html")).unwrap();
+        assert!(!create_html_file(&streams, &settings).unwrap());
+        assert_eq!(std::fs::read_to_string(dir.join("index.html")).unwrap(), written);
+
+        // Any change to what's rendered writes the page again
+        assert!(create_html_file(&streams[..1], &settings).unwrap());
+        settings.reconnect_grace_secs += 1;
+        assert!(create_html_file(&streams[..1], &settings).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    const HOSTILE_NAMES: [&str; 5] = ["O'Brien's gate", "the \"yard\"", "</script><script>alert(1)</script>", "<!-- back", "line\u{2028}break"];
+
+    #[test]
+    fn escape_html_leaves_no_markup() {
+        for name in HOSTILE_NAMES {
+            let escaped = escape_html(name);
+            assert!(!escaped.contains(['<', '>', '"', '\'']), "{:?} -> {:?}", name, escaped);
+        }
+        assert_eq!(escape_html("O'Brien & \"Sons\""), "O&#39;Brien &amp; &quot;Sons&quot;");
+        assert_eq!(escape_html("</script>"), "&lt;/script&gt;");
+    }
+
+    #[test]
+    fn js_string_is_a_safe_literal_for_the_same_string() {
+        for name in HOSTILE_NAMES {
+            let literal = js_string(name);
+            assert!(!literal.contains(['<', '>', '&', '\u{2028}', '\u{2029}']), "{:?} -> {:?}", name, literal);
+            // JSON's \uXXXX escapes are valid JavaScript too, the page sees the name unchanged
+            assert_eq!(serde_json::from_str::<String>(&literal).unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn hostile_names_stay_inside_the_page_script() {
+        let streams: Vec<PageStream> = HOSTILE_NAMES.iter().map(|name| page_stream(name)).collect();
+        let html = page_html(&streams, &default_settings(), "");
+        assert_eq!(html.matches("</script>").count(), html.matches("<script").count());
+        assert!(!html.contains("<!--"));
+    }
+
+    #[tokio::test]
+    async fn reconnecting_viewer_never_gets_a_frame_older_than_the_replayed_one() {
+        let (tx, _) = broadcast::channel(16);
+        let stats = Arc::new(StreamStats::default());
+        *stats.latest_frame.lock().unwrap() = Some(test_frame(5));
+        let clients = test_clients("cam", tx.clone(), stats);
+        let supervisor = test_supervisor();
+
+        let mut viewer = connect_viewer(&clients, &supervisor, "cam", Quality::Main).await;
+        assert!(viewer.recv().await.unwrap().is_text(), "hello first");
+        // Subscribed by now. 4 and 5 stand for frames queued between subscribing and
+        // reading the frame to replay.
+        for seq in [4, 5, 6] {
+            tx.send(test_frame(seq)).unwrap();
+        }
+        let mut seen = Vec::new();
+        while seen.len() < 2 {
+            let message = tokio::time::timeout(Duration::from_secs(5), viewer.recv()).await.unwrap().unwrap();
+            if message.is_binary() {
+                seen.push(message.as_bytes()[0]);
+            }
+        }
+        assert_eq!(seen, [5, 6]);
+
+        supervisor.shutdown();
+    }
+
+    // The hello's "quality" and the first frame's payload
+    async fn first_frame(viewer: &mut warp::test::WsClient, frames: &broadcast::Sender<Frame>, seq: u64) -> (String, u8) {
+        let hello: serde_json::Value = serde_json::from_str(viewer.recv().await.unwrap().to_str().unwrap()).unwrap();
+        frames.send(test_frame(seq)).unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(5), viewer.recv()).await.unwrap().unwrap();
+        (hello["quality"].as_str().unwrap().to_string(), frame.as_bytes()[0])
+    }
+
+    #[tokio::test]
+    async fn viewers_get_the_stream_and_quality_they_ask_for() {
+        let (main_tx, _) = broadcast::channel(16);
+        let (sub_tx, _) = broadcast::channel(16);
+        let (plain_tx, _) = broadcast::channel(16);
+        let mut gate = test_channels(main_tx.clone(), Arc::new(StreamStats::default()));
+        gate.sub_frames = Some(sub_tx.clone());
+        let yard = test_channels(plain_tx.clone(), Arc::new(StreamStats::default()));
+        let clients: Clients = Arc::new(Mutex::new(HashMap::from([("Gate".to_string(), gate), ("yard".to_string(), yard)])));
+        let supervisor = test_supervisor();
+
+        // Names match whatever their case
+        let mut viewer = connect_viewer(&clients, &supervisor, "gate", Quality::Main).await;
+        assert_eq!(first_frame(&mut viewer, &main_tx, 1).await, ("main".to_string(), 1));
+        let mut viewer = connect_viewer(&clients, &supervisor, "GATE", Quality::Sub).await;
+        assert_eq!(first_frame(&mut viewer, &sub_tx, 2).await, ("sub".to_string(), 2));
+        // No substream, the main stream instead
+        let mut viewer = connect_viewer(&clients, &supervisor, "yard", Quality::Sub).await;
+        assert_eq!(first_frame(&mut viewer, &plain_tx, 3).await, ("main".to_string(), 3));
+
+        supervisor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn source_resolution_change_keeps_the_preview_size() {
+        let (spec, mut rx) = test_spec(test_camera("name: daynight\nsource: test:320x240@30"));
+        let resources = setup_pipeline(&spec).unwrap();
+        // The test source's caps stand in for a camera switching resolution
+        let source_caps = resources
+            .pipeline
+            .iterate_recurse()
+            .into_iter()
+            .flatten()
+            .find(|element| {
+                element.factory().is_some_and(|factory| factory.name() == "capsfilter")
+                    && element.property::<gst::Caps>("caps").structure(0).is_some_and(|s| s.get::<i32>("width") == Ok(320))
+            })
+            .expect("test source capsfilter");
+
+        let before = next_frame(&mut rx).await;
+        assert_eq!(*spec.stats.source_resolution.lock().unwrap(), Some((320, 240)));
+        assert_eq!(jpeg_size(&before.jpeg), (spec.camera.width, spec.camera.height));
+
+        let switched = gst::Caps::builder("video/x-raw")
+            .field("width", 640)
+            .field("height", 480)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build();
+        source_caps.set_property("caps", switched);
+        let mut frames = 0;
+        while *spec.stats.source_resolution.lock().unwrap() != Some((640, 480)) {
+            next_frame(&mut rx).await;
+            frames += 1;
+            assert!(frames < 100, "source resolution change never seen");
+        }
+        // Same stream, still going at the configured size
+        let after = next_frame(&mut rx).await;
+        assert!(after.seq > before.seq);
+        assert_eq!(jpeg_size(&after.jpeg), (spec.camera.width, spec.camera.height));
+        assert_eq!(resources.pipeline.current_state(), gst::State::Playing);
+        assert_eq!(spec.stats.reconnect_count.load(Ordering::SeqCst), 0);
+    }
+
+    fn pad_caps(pipeline: &gst::Pipeline, element: &str) -> (i32, i32, gst::Fraction) {
+        let caps = pipeline.by_name(element).unwrap().static_pad("sink").unwrap().current_caps().unwrap();
+        let structure = caps.structure(0).unwrap();
+        (structure.get("width").unwrap(), structure.get("height").unwrap(), structure.get("framerate").unwrap())
+    }
+
+    #[tokio::test]
+    async fn recording_gets_the_full_source_while_the_preview_is_scaled_and_capped() {
+        if recorder::select_encoder(config::RecordingCodec::H264).is_none() {
+            println!("No H.264 encoder installed, skipping");
+            return;
+        }
+        let camera = test_camera("name: topology\nsource: test:1280x720@30\nwidth: 320\nheight: 180\npreview_fps: 5\nrecord: true");
+        let (spec, mut rx) = test_spec(camera);
+        let resources = setup_pipeline(&spec).unwrap();
+        let first = next_frame(&mut rx).await;
+        let second = next_frame(&mut rx).await;
+
+        // The recording branch leaves the tee ahead of the preview's rate cap and scaling
+        let (width, height, framerate) = pad_caps(&resources.pipeline, "recording-valve");
+        assert_eq!((width, height, framerate), (1280, 720, gst::Fraction::new(30, 1)));
+        let (width, height, _) = pad_caps(&resources.pipeline, "encoder");
+        assert_eq!((width, height), (320, 180));
+        assert_eq!(jpeg_size(&second.jpeg), (320, 180));
+        // 30 fps from the source, preview_fps lets through one frame in six
+        let gap = second.captured_at - first.captured_at;
+        assert!(gap >= chrono::TimeDelta::milliseconds(100), "preview frames {:?} apart", gap);
+
+        drop(resources);
+        let _ = std::fs::remove_dir_all(spec.options.recordings_dir.join("topology"));
+    }
+
+    #[cfg(feature = "fake-source")]
+    #[tokio::test]
+    async fn fake_camera_frames_reach_a_subscriber() {
+        gst::init().unwrap();
+        // The fake server's mounts are dispatched by the supervisor's main loop
+        let supervisor = test_supervisor();
+        let server = fake_source::start(1).unwrap();
+        let mut cameras = vec![test_camera(&format!("name: {}", fake_source::camera_name(0)))];
+        server.point_cameras(&mut cameras);
+        let (spec, mut rx) = test_spec(cameras.remove(0));
+
+        let resources = setup_pipeline(&spec).unwrap();
+        let first = next_frame(&mut rx).await;
+        let second = next_frame(&mut rx).await;
+        assert!(second.seq > first.seq);
+        assert_eq!(jpeg_size(&first.jpeg), (spec.camera.width, spec.camera.height));
+
+        drop(resources);
+        supervisor.shutdown();
+    }
 }
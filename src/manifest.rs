@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::config::{CameraConfig, CameraMode, RecordingCodec, Settings};
+use crate::decode::DecoderProbe;
+use crate::Stats;
+
+// Bumped when fields are removed or change meaning, additions keep it
+const MANIFEST_VERSION: u32 = 1;
+
+// JSON shape returned by GET /api/manifest: everything a parent dashboard needs to find
+// and show this instance's streams without knowing its config. Endpoints are paths
+// (including base_path) on this server; the streaming ones (ws, sse, mse, hls, audio) are
+// on stream_port when that's set.
+#[derive(Serialize)]
+pub struct Manifest {
+    manifest_version: u32,
+    server_id: String,
+    version: &'static str,
+    base_path: String,
+    stream_port: Option<u16>,
+    generated_at: DateTime<Utc>,
+    capabilities: Capabilities,
+    // Installed hardware decoders and whether they initialized at startup, null when
+    // probe_hw_decoders is off
+    hardware_decoders: Option<Vec<DecoderProbe>>,
+    streams: Vec<ManifestStream>,
+}
+
+// What this instance offers, over all of its cameras
+#[derive(Serialize)]
+struct Capabilities {
+    recording: bool,
+    motion: bool,
+    // Not implemented yet, listed so parents don't have to guess
+    ptz: bool,
+    audio: bool,
+    substreams: bool,
+    mse: bool,
+    hls: bool,
+    seek: bool,
+}
+
+#[derive(Serialize)]
+struct ManifestStream {
+    name: String,
+    display_name: String,
+    mode: CameraMode,
+    tags: Vec<String>,
+    running: bool,
+    // Decoded size, after any crop but before scaling. None until the first caps arrive.
+    source_resolution: Option<(u32, u32)>,
+    // None for audio-only cameras
+    preview: Option<Preview>,
+    // None when the camera doesn't record
+    recording: Option<Recording>,
+    motion: bool,
+    ptz: bool,
+    endpoints: Endpoints,
+}
+
+#[derive(Serialize)]
+struct Preview {
+    codec: &'static str,
+    width: u32,
+    height: u32,
+    // Configured cap, None when every source frame is sent
+    max_fps: Option<u32>,
+    has_substream: bool,
+}
+
+#[derive(Serialize)]
+struct Recording {
+    codec: RecordingCodec,
+}
+
+#[derive(Serialize)]
+struct Endpoints {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ws: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sse: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mse: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hls: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audio: Option<String>,
+    metrics: String,
+}
+
+// The config side of the manifest, fixed for the life of the process
+pub struct ManifestSource {
+    server_id: String,
+    base_path: String,
+    stream_port: Option<u16>,
+    seek: bool,
+    hardware_decoders: Option<Vec<DecoderProbe>>,
+    cameras: Vec<CameraConfig>,
+}
+
+impl ManifestSource {
+    pub fn new(settings: &Settings, hardware_decoders: Option<Vec<DecoderProbe>>) -> Self {
+        ManifestSource {
+            server_id: settings.server_id.clone(),
+            base_path: settings.base_path.clone(),
+            stream_port: settings.stream_address.map(|address| address.port()),
+            seek: settings.seek_buffer_secs > 0,
+            hardware_decoders,
+            cameras: settings.cameras.clone(),
+        }
+    }
+
+    // Adds what's only known at runtime, whether each pipeline runs and at what resolution
+    pub fn render(&self, stats: &Stats) -> Manifest {
+        let stats = stats.lock().unwrap();
+        let has_video = self.cameras.iter().any(|camera| camera.mode == CameraMode::Video);
+        let capabilities = Capabilities {
+            recording: self.cameras.iter().any(|camera| camera.record),
+            motion: self.cameras.iter().any(|camera| camera.motion_detection),
+            ptz: false,
+            audio: self.cameras.iter().any(|camera| camera.mode == CameraMode::Audio),
+            substreams: self.cameras.iter().any(|camera| camera.substream_url.is_some()),
+            mse: has_video,
+            hls: has_video,
+            seek: self.seek,
+        };
+
+        let streams = self
+            .cameras
+            .iter()
+            .map(|camera| {
+                let stream_stats = stats.get(&camera.name);
+                let running = stream_stats.is_some_and(|stream_stats| stream_stats.started.lock().unwrap().is_some());
+                let source_resolution = stream_stats.and_then(|stream_stats| *stream_stats.source_resolution.lock().unwrap());
+                let is_video = camera.mode == CameraMode::Video;
+                let path = |route: &str| format!("{}/{}/{}", self.base_path, route, camera.name);
+
+                ManifestStream {
+                    name: camera.name.clone(),
+                    display_name: camera.display_name.clone().unwrap_or_else(|| camera.name.clone()),
+                    mode: camera.mode,
+                    tags: camera.tags.clone(),
+                    running,
+                    source_resolution,
+                    preview: is_video.then(|| Preview {
+                        codec: "jpeg",
+                        width: camera.width,
+                        height: camera.height,
+                        max_fps: camera.preview_fps,
+                        has_substream: camera.substream_url.is_some(),
+                    }),
+                    recording: camera.record.then_some(Recording { codec: camera.recording_codec }),
+                    motion: camera.motion_detection,
+                    ptz: false,
+                    endpoints: Endpoints {
+                        ws: is_video.then(|| path("ws")),
+                        sse: is_video.then(|| path("sse")),
+                        mse: is_video.then(|| path("ws/mse")),
+                        hls: is_video.then(|| format!("{}/{}", path("hls"), crate::branches::HLS_PLAYLIST)),
+                        thumbnail: is_video.then(|| path("api/thumbnail")),
+                        audio: (!is_video).then(|| path("ws/audio")),
+                        metrics: path("api/metrics"),
+                    },
+                }
+            })
+            .collect();
+
+        Manifest {
+            manifest_version: MANIFEST_VERSION,
+            server_id: self.server_id.clone(),
+            version: env!("CARGO_PKG_VERSION"),
+            base_path: self.base_path.clone(),
+            stream_port: self.stream_port,
+            generated_at: Utc::now(),
+            capabilities,
+            hardware_decoders: self.hardware_decoders.clone(),
+            streams,
+        }
+    }
+}
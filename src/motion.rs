@@ -0,0 +1,180 @@
+use chrono::Utc;
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::onvif::CameraEvent;
+use crate::processor::{FrameData, FrameProcessor};
+use crate::StreamStats;
+
+const TOPIC: &str = "Detector/Motion";
+
+// Motion that stops for less than this is still the same event, so a person pausing
+// mid-frame doesn't make a burst of them
+const MOTION_EVENT_HOLD: Duration = Duration::from_secs(3);
+
+// Distance in pixels between luma samples. 8 keeps a 640x360 frame to ~3600 samples.
+const GRID_STEP: usize = 8;
+
+// Luma change for a single sample to count as motion when computing the bounding box
+const CHANGED_SAMPLE_DELTA: u8 = 25;
+
+// Fewer changed samples than this is treated as sensor noise
+const MIN_CHANGED_SAMPLES: usize = 4;
+
+// Region of a frame that changed, in frame pixel coordinates
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct MotionBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Subsampled luma of a raw frame, cheap enough to compute and compare on every frame
+#[derive(Clone)]
+pub struct LumaGrid {
+    width: usize,
+    height: usize,
+    cols: usize,
+    rows: usize,
+    values: Vec<u8>,
+}
+
+impl LumaGrid {
+    // Plane 0 is 8-bit luma for the planar/semi-planar YUV formats the preview branch uses
+    pub fn sample(frame: &gst_video::VideoFrameRef<&gst::BufferRef>) -> Option<LumaGrid> {
+        let data = frame.plane_data(0).ok()?;
+        let stride = *frame.plane_stride().first()? as usize;
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+
+        let mut values = Vec::with_capacity(width.div_ceil(GRID_STEP) * height.div_ceil(GRID_STEP));
+        for y in (0..height).step_by(GRID_STEP) {
+            let row = data.get(y * stride..y * stride + width)?;
+            values.extend(row.iter().step_by(GRID_STEP));
+        }
+
+        Some(LumaGrid {
+            width,
+            height,
+            cols: width.div_ceil(GRID_STEP),
+            rows: height.div_ceil(GRID_STEP),
+            values,
+        })
+    }
+
+    // Mean absolute luma difference scaled to 0.0 (identical) - 1.0 (inverted).
+    // Grids of different sizes (resolution change) count as completely different.
+    pub fn score(&self, other: &LumaGrid) -> f64 {
+        if self.cols != other.cols || self.rows != other.rows || self.values.is_empty() {
+            return 1.0;
+        }
+
+        let total: u64 = self.values
+            .iter()
+            .zip(&other.values)
+            .map(|(a, b)| a.abs_diff(*b) as u64)
+            .sum();
+        total as f64 / (self.values.len() as f64 * 255.0)
+    }
+
+    // Bounding box around every sample that changed noticeably since `other`
+    pub fn motion_box(&self, other: &LumaGrid) -> Option<MotionBox> {
+        if self.cols != other.cols || self.rows != other.rows {
+            return None;
+        }
+
+        let (mut min_col, mut min_row) = (usize::MAX, usize::MAX);
+        let (mut max_col, mut max_row) = (0, 0);
+        let mut changed = 0;
+
+        for (i, (a, b)) in self.values.iter().zip(&other.values).enumerate() {
+            if a.abs_diff(*b) < CHANGED_SAMPLE_DELTA {
+                continue;
+            }
+            let (col, row) = (i % self.cols, i / self.cols);
+            min_col = min_col.min(col);
+            max_col = max_col.max(col);
+            min_row = min_row.min(row);
+            max_row = max_row.max(row);
+            changed += 1;
+        }
+
+        if changed < MIN_CHANGED_SAMPLES {
+            return None;
+        }
+
+        let x = min_col * GRID_STEP;
+        let y = min_row * GRID_STEP;
+        Some(MotionBox {
+            x: x as u32,
+            y: y as u32,
+            width: (((max_col + 1) * GRID_STEP).min(self.width) - x) as u32,
+            height: (((max_row + 1) * GRID_STEP).min(self.height) - y) as u32,
+        })
+    }
+}
+
+// Latest motion analysis of the preview branch, read by the appsink callback
+#[derive(Default)]
+pub struct MotionState {
+    pub grid: Option<LumaGrid>,
+    pub motion_box: Option<MotionBox>,
+}
+
+// The built-in frame processor: scores each raw preview frame against the previous one
+// and records where it changed, leaving the pixels alone
+pub struct MotionDetector {
+    state: Arc<Mutex<MotionState>>,
+    stats: Arc<StreamStats>,
+    // With motion_detection, motion starting and stopping goes out as detector events
+    events: Option<broadcast::Sender<CameraEvent>>,
+    // Last frame with motion, while a motion event is active
+    moving_at: Mutex<Option<Instant>>,
+}
+
+impl MotionDetector {
+    pub fn new(state: Arc<Mutex<MotionState>>, stats: Arc<StreamStats>, events: Option<broadcast::Sender<CameraEvent>>) -> Self {
+        MotionDetector { state, stats, events, moving_at: Mutex::new(None) }
+    }
+
+    fn track(&self, moving: bool) {
+        let Some(events) = &self.events else { return };
+        let mut moving_at = self.moving_at.lock().unwrap();
+        let active = match (moving, *moving_at) {
+            (true, None) => true,
+            (false, Some(at)) if at.elapsed() >= MOTION_EVENT_HOLD => false,
+            (true, Some(_)) => {
+                *moving_at = Some(Instant::now());
+                return;
+            }
+            _ => return,
+        };
+        *moving_at = active.then(Instant::now);
+        // Nobody subscribed is fine
+        let _ = events.send(CameraEvent {
+            source: "detector",
+            topic: TOPIC.to_string(),
+            active,
+            time: Some(Utc::now().to_rfc3339()),
+        });
+    }
+}
+
+impl FrameProcessor for MotionDetector {
+    fn process(&self, frame: &FrameData) -> Option<FrameData> {
+        let grid = frame.video_frame().and_then(|frame| LumaGrid::sample(&frame));
+        let mut state = self.state.lock().unwrap();
+        state.motion_box = None;
+        if let (Some(grid), Some(previous)) = (&grid, &state.grid) {
+            *self.stats.motion_score.lock().unwrap() = grid.score(previous);
+            state.motion_box = grid.motion_box(previous);
+        }
+        state.grid = grid;
+        self.track(state.motion_box.is_some());
+        None
+    }
+}
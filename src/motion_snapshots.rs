@@ -0,0 +1,72 @@
+use chrono::Utc;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Most frames snapshot_burst may save per motion event
+pub const MAX_SNAPSHOT_BURST: u32 = 10;
+
+// A lightweight event archive for cameras where full recording isn't warranted. When motion
+// shows up, the preview JPEG (and the next burst - 1 frames) is written next to the camera's
+// recordings, named after the time and motion score. Then nothing is saved for `cooldown`,
+// so a busy scene leaves one burst per cooldown instead of a file per frame.
+pub struct MotionSnapshots {
+    stream_name: String,
+    dir: PathBuf,
+    cooldown: Duration,
+    burst: u32,
+    state: Mutex<SnapshotState>,
+}
+
+#[derive(Default)]
+struct SnapshotState {
+    burst_started: Option<Instant>,
+    // Frames of the current burst still to be saved
+    remaining: u32,
+}
+
+impl MotionSnapshots {
+    pub fn new(stream_name: String, dir: PathBuf, cooldown: Duration, burst: u32) -> Self {
+        MotionSnapshots {
+            stream_name,
+            dir,
+            cooldown,
+            burst,
+            state: Mutex::new(SnapshotState::default()),
+        }
+    }
+
+    // Called with every preview frame. The file is written off the streaming thread.
+    pub fn offer(&self, jpeg: &[u8], motion: bool, score: f64) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.remaining == 0 {
+                if !motion || state.burst_started.is_some_and(|started| started.elapsed() < self.cooldown) {
+                    return;
+                }
+                state.burst_started = Some(Instant::now());
+                state.remaining = self.burst;
+            }
+            state.remaining -= 1;
+        }
+
+        let path = self.dir.join(format!(
+            "{}-motion-{}-{:.3}.jpg",
+            self.stream_name,
+            Utc::now().format("%Y%m%d-%H%M%S-%3f"),
+            score
+        ));
+        let jpeg = jpeg.to_vec();
+        let stream_name = self.stream_name.clone();
+        std::thread::spawn(move || {
+            let written = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(&path, jpeg));
+            match written {
+                Ok(()) => println!("{}: Saved motion snapshot {}", stream_name, path.display()),
+                Err(err) => println!("{}: Failed to save motion snapshot {}: {:?}", stream_name, path.display(), err),
+            }
+        });
+    }
+}
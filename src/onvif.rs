@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::Utc;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+// Subscriptions are requested for this long and renewed well before they lapse
+const SUBSCRIPTION_TERMINATION: &str = "PT60S";
+const RENEW_INTERVAL: Duration = Duration::from_secs(30);
+
+// PullMessages long-polls on the camera for up to PULL_TIMEOUT, the HTTP timeout leaves headroom
+const PULL_TIMEOUT: &str = "PT10S";
+const PULL_MESSAGE_LIMIT: u32 = 32;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(20);
+
+// How long to wait before re-subscribing after the camera dropped the subscription
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(10);
+
+const EVENTS_NS: &str = "http://www.onvif.org/ver10/events/wsdl";
+const NOTIFICATION_NS: &str = "http://docs.oasis-open.org/wsn/b-2";
+
+// An event reported by the camera itself, e.g. topic "RuleEngine/CellMotionDetector/Motion"
+#[derive(Clone, Debug, Serialize)]
+pub struct CameraEvent {
+    // What reported it: "onvif" for the camera itself, "recorder" for our own recording,
+    // "detector" for our own analysis of the picture
+    pub source: &'static str,
+    pub topic: String,
+    // Value of the event's first data item ("IsMotion", "State", ...), true when unset
+    pub active: bool,
+    pub time: Option<String>,
+}
+
+// Poll the camera's ONVIF event service from its own thread and publish every
+// notification on `tx`. Lost subscriptions are recreated after RESUBSCRIBE_DELAY.
+pub fn spawn_pull_point(stream_name: String, event_url: String, user: String, pass: String, tx: broadcast::Sender<CameraEvent>) {
+    std::thread::spawn(move || {
+        let client = Client {
+            agent: ureq::AgentBuilder::new().timeout(HTTP_TIMEOUT).build(),
+            user,
+            pass,
+        };
+        loop {
+            if let Err(err) = pull_events(&client, &stream_name, &event_url, &tx) {
+                println!("{}: ONVIF subscription failed: {:?}", stream_name, err);
+            }
+            println!("{}: Resubscribing to ONVIF events in {:?}", stream_name, RESUBSCRIBE_DELAY);
+            std::thread::sleep(RESUBSCRIBE_DELAY);
+        }
+    });
+}
+
+// Runs until the subscription fails
+fn pull_events(client: &Client, stream_name: &str, event_url: &str, tx: &broadcast::Sender<CameraEvent>) -> Result<()> {
+    let response = client.call(
+        event_url,
+        &format!("{}/EventPortType/CreatePullPointSubscriptionRequest", EVENTS_NS),
+        &format!(
+            "<CreatePullPointSubscription xmlns=\"{}\"><InitialTerminationTime>{}</InitialTerminationTime></CreatePullPointSubscription>",
+            EVENTS_NS, SUBSCRIPTION_TERMINATION
+        ),
+    )?;
+    let subscription_url = elements(&response, "SubscriptionReference")
+        .first()
+        .and_then(|reference| elements(reference, "Address").first().map(|address| text(address).trim().to_string()))
+        .ok_or_else(|| anyhow!("No subscription address in CreatePullPointSubscription response"))?;
+    println!("{}: Subscribed to ONVIF events at {}", stream_name, subscription_url);
+
+    let mut renewed_at = Instant::now();
+    loop {
+        if renewed_at.elapsed() >= RENEW_INTERVAL {
+            client.call(
+                &subscription_url,
+                "http://docs.oasis-open.org/wsn/bw-2/SubscriptionManager/RenewRequest",
+                &format!(
+                    "<Renew xmlns=\"{}\"><TerminationTime>{}</TerminationTime></Renew>",
+                    NOTIFICATION_NS, SUBSCRIPTION_TERMINATION
+                ),
+            )?;
+            renewed_at = Instant::now();
+        }
+
+        let response = client.call(
+            &subscription_url,
+            &format!("{}/PullPointSubscription/PullMessagesRequest", EVENTS_NS),
+            &format!(
+                "<PullMessages xmlns=\"{}\"><Timeout>{}</Timeout><MessageLimit>{}</MessageLimit></PullMessages>",
+                EVENTS_NS, PULL_TIMEOUT, PULL_MESSAGE_LIMIT
+            ),
+        )?;
+
+        for notification in elements(&response, "NotificationMessage") {
+            let Some(event) = parse_notification(notification) else { continue };
+            println!("{}: ONVIF event {} active={}", stream_name, event.topic, event.active);
+            let _ = tx.send(event);
+        }
+    }
+}
+
+fn parse_notification(notification: &str) -> Option<CameraEvent> {
+    // "tns1:RuleEngine/tnsaxis:CellMotionDetector/Motion" -> "RuleEngine/CellMotionDetector/Motion"
+    let topic = text(elements(notification, "Topic").first()?)
+        .trim()
+        .split('/')
+        .map(|segment| segment.rsplit(':').next().unwrap_or(segment))
+        .collect::<Vec<_>>()
+        .join("/");
+
+    // wsnt:Message wraps the tt:Message carrying the time and data items
+    let outer = *elements(notification, "Message").first()?;
+    let message = elements(text(outer), "Message").first().copied().unwrap_or(outer);
+    let value = elements(message, "Data")
+        .first()
+        .and_then(|data| elements(data, "SimpleItem").first().and_then(|item| attr(item, "Value")));
+
+    Some(CameraEvent {
+        source: "onvif",
+        topic,
+        active: value.is_none_or(|value| value == "true" || value == "1"),
+        time: attr(message, "UtcTime").map(str::to_string),
+    })
+}
+
+struct Client {
+    agent: ureq::Agent,
+    user: String,
+    pass: String,
+}
+
+impl Client {
+    // POST a SOAP 1.2 request with a WS-Security UsernameToken and return the response XML
+    fn call(&self, url: &str, action: &str, body: &str) -> Result<String> {
+        let envelope = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" xmlns:a=\"http://www.w3.org/2005/08/addressing\">\
+             <s:Header>{}<a:Action>{}</a:Action><a:To>{}</a:To></s:Header>\
+             <s:Body>{}</s:Body></s:Envelope>",
+            self.security_header(),
+            action,
+            url,
+            body
+        );
+
+        let response = self.agent
+            .post(url)
+            .set("Content-Type", &format!("application/soap+xml; charset=utf-8; action=\"{}\"", action))
+            .send_string(&envelope);
+        match response {
+            Ok(response) => Ok(response.into_string()?),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let reason = elements(&body, "Text").first().map(|reason| text(reason).trim().to_string());
+                Err(anyhow!("{} returned HTTP {}: {}", url, status, reason.unwrap_or(body)))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // PasswordDigest = Base64(SHA1(nonce + created + password))
+    fn security_header(&self) -> String {
+        let nonce: [u8; 16] = rand::random();
+        let created = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+        let mut hasher = Sha1::new();
+        hasher.update(nonce);
+        hasher.update(created.as_bytes());
+        hasher.update(self.pass.as_bytes());
+        let digest = BASE64.encode(hasher.finalize());
+
+        format!(
+            "<Security s:mustUnderstand=\"1\" xmlns=\"http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd\">\
+             <UsernameToken><Username>{}</Username>\
+             <Password Type=\"http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordDigest\">{}</Password>\
+             <Nonce EncodingType=\"http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-soap-message-security-1.0#Base64Binary\">{}</Nonce>\
+             <Created xmlns=\"http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd\">{}</Created>\
+             </UsernameToken></Security>",
+            escape(&self.user),
+            digest,
+            BASE64.encode(nonce),
+            created
+        )
+    }
+}
+
+// ONVIF responses are small and shallow, so elements are found by scanning for tags
+// with a matching local name (whatever namespace prefix the camera picked).
+// Each returned slice covers the whole element, start tag through end tag.
+fn elements<'a>(xml: &'a str, local_name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = 0;
+    while let Some(offset) = xml[rest..].find('<') {
+        let start = rest + offset;
+        let Some(tag_len) = xml[start..].find('>') else { break };
+        let tag = &xml[start + 1..start + tag_len];
+        rest = start + tag_len + 1;
+
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        if name.rsplit(':').next() != Some(local_name) {
+            continue;
+        }
+        if tag.ends_with('/') {
+            found.push(&xml[start..rest]);
+            continue;
+        }
+        if let Some(end) = xml[rest..].find(&format!("</{}>", name)) {
+            let end = rest + end + name.len() + 3;
+            found.push(&xml[start..end]);
+            rest = end;
+        }
+    }
+    found
+}
+
+// Content between an element's start and end tags
+fn text(element: &str) -> &str {
+    match (element.find('>'), element.rfind('<')) {
+        (Some(start), Some(end)) if start < end => &element[start + 1..end],
+        _ => "",
+    }
+}
+
+// Attribute of an element's start tag
+fn attr<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let tag = &element[..element.find('>')?];
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
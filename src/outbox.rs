@@ -0,0 +1,111 @@
+use futures::stream::SplitSink;
+use futures::SinkExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use warp::ws::{Message, WebSocket};
+
+// A viewer's outgoing messages, written to its socket by a task of its own so a client
+// that stops reading only stalls that task. Whatever the client hasn't taken yet is counted,
+// and once that passes `limit` the client is dropped instead of queueing more frames for it.
+pub struct Outbox {
+    tx: mpsc::UnboundedSender<Message>,
+    // Bytes handed to send() that the socket hasn't accepted yet
+    queued: Arc<AtomicUsize>,
+    limit: usize,
+    label: String,
+    writer: JoinHandle<()>,
+}
+
+impl Outbox {
+    // `label` names the client in the overflow log line, e.g. "<stream>: Client <addr>"
+    pub fn new(mut ws_tx: SplitSink<WebSocket, Message>, limit: usize, label: String) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let queued_writer = queued.clone();
+        let writer = tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let len = message.as_bytes().len();
+                let sent = ws_tx.send(message).await;
+                queued_writer.fetch_sub(len, Ordering::Relaxed);
+                if sent.is_err() {
+                    break; // Client disconnected
+                }
+            }
+        });
+
+        Outbox { tx, queued, limit, label, writer }
+    }
+
+    // Queue a message without waiting for the client. False means the client is gone, or
+    // has so much unread that it should be dropped; either way stop sending to it.
+    pub fn send(&self, message: Message) -> bool {
+        let len = message.as_bytes().len();
+        let queued = self.queued.fetch_add(len, Ordering::Relaxed) + len;
+        // A single message is let through whatever its size, the limit is for backlogs
+        if queued > self.limit && queued > len {
+            self.queued.fetch_sub(len, Ordering::Relaxed);
+            println!("{} has {} bytes unsent, disconnecting", self.label, queued - len);
+            return false;
+        }
+        self.tx.send(message).is_ok()
+    }
+}
+
+impl Drop for Outbox {
+    // The writer may be stuck on a client that never reads, don't leave it waiting
+    fn drop(&mut self) {
+        self.writer.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use warp::Filter;
+
+    const LIMIT: usize = 256 * 1024;
+    const FRAME_BYTES: usize = 64 * 1024;
+
+    #[tokio::test]
+    async fn client_that_never_reads_is_dropped() {
+        // Frames are offered the way a viewer gets them, with time in between for the
+        // writer to hand them to the socket. Some(n) once the outbox gave up after n.
+        let (result_tx, mut result_rx) = mpsc::unbounded_channel::<Option<usize>>();
+        let route = warp::ws().map(move |ws: warp::ws::Ws| {
+            let result_tx = result_tx.clone();
+            ws.on_upgrade(move |socket| async move {
+                let (ws_tx, _ws_rx) = socket.split();
+                let outbox = Outbox::new(ws_tx, LIMIT, "test client".to_string());
+                let mut result = None;
+                for sent in 0..10_000 {
+                    if !outbox.send(Message::binary(vec![0; FRAME_BYTES])) {
+                        result = Some(sent);
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                let _ = result_tx.send(result);
+            })
+        });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        // Upgrade by hand, then never read a byte
+        let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .await
+            .unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(60), result_rx.recv()).await.unwrap().unwrap();
+        let sent = result.expect("outbox kept queueing for a client that never reads");
+        // The socket took some before filling up, the backlog past that stayed under the limit
+        assert!(sent > LIMIT / FRAME_BYTES);
+        drop(socket);
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::{anyhow, Result};
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gst::prelude::*;
+
+// Longest we wait for the one-frame render pipeline before giving up on this tick
+const RENDER_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(2);
+
+// Renders "NO SIGNAL" JPEGs at a stream's preview size. The pipeline produces a single
+// frame per run, so it is cheap to keep around and replay once per placeholder.
+pub struct NoSignalRenderer {
+    pipeline: gst::Pipeline,
+    appsink: gst_app::AppSink,
+    overlay: gst::Element,
+}
+
+impl NoSignalRenderer {
+    pub fn new(width: u32, height: u32, jpeg_quality: u32) -> Result<Self> {
+        let pipeline_str = format!(
+            "videotestsrc num-buffers=1 pattern=black ! video/x-raw,format=I420,width={},height={} ! textoverlay name=overlay valignment=center halignment=center line-alignment=center font-desc=\"Sans Bold 16\" ! jpegenc quality={} ! appsink name=sink sync=false",
+            width, height, jpeg_quality
+        );
+
+        let pipeline = gst::parse::launch(&pipeline_str)?;
+        let pipeline = pipeline.downcast::<gst::Pipeline>().unwrap();
+        let appsink = pipeline
+            .by_name("sink")
+            .expect("Couldn't find appsink")
+            .downcast::<gst_app::AppSink>()
+            .unwrap();
+        let overlay = pipeline.by_name("overlay").expect("Couldn't find textoverlay");
+
+        Ok(NoSignalRenderer { pipeline, appsink, overlay })
+    }
+
+    pub fn render(&self, text: &str) -> Result<Vec<u8>> {
+        self.overlay.set_property("text", text);
+        self.pipeline.set_state(gst::State::Playing)?;
+        let sample = self.appsink.try_pull_sample(RENDER_TIMEOUT);
+        self.pipeline.set_state(gst::State::Null)?;
+
+        let sample = sample.ok_or_else(|| anyhow!("Timed out rendering placeholder"))?;
+        let buffer = sample.buffer().ok_or_else(|| anyhow!("No buffer in placeholder sample"))?;
+        let map = buffer.map_readable()?;
+        Ok(map.to_vec())
+    }
+}
@@ -0,0 +1,41 @@
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use std::sync::Arc;
+
+// A raw preview frame on its way to the JPEG encoder. The buffer is reference counted,
+// handing it to processors doesn't copy the pixels.
+#[derive(Clone)]
+pub struct FrameData {
+    pub buffer: gst::Buffer,
+    pub info: gst_video::VideoInfo,
+}
+
+impl FrameData {
+    pub fn video_frame(&self) -> Option<gst_video::VideoFrameRef<&gst::BufferRef>> {
+        gst_video::VideoFrameRef::from_buffer_ref_readable(self.buffer.as_ref(), &self.info).ok()
+    }
+}
+
+// Hook for custom analytics on the preview branch. Processors run in order on the
+// streaming thread right before encoding, so they need to be quick. Returning a frame
+// (same caps) replaces the one seen by later processors and the encoder, None passes it on
+// untouched. The default does nothing.
+pub trait FrameProcessor: Send + Sync {
+    fn process(&self, _frame: &FrameData) -> Option<FrameData> {
+        None
+    }
+}
+
+pub type Processors = Vec<Arc<dyn FrameProcessor>>;
+
+// Run `frame` through every processor, None if none of them replaced it
+pub fn run(processors: &[Arc<dyn FrameProcessor>], frame: FrameData) -> Option<FrameData> {
+    let mut replaced = None;
+    for processor in processors {
+        let current = replaced.as_ref().unwrap_or(&frame);
+        if let Some(output) = processor.process(current) {
+            replaced = Some(output);
+        }
+    }
+    replaced
+}
@@ -0,0 +1,69 @@
+use gstreamer as gst;
+use gst::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+// What one element last reported in its QoS messages. processed/dropped are the
+// element's own running totals, so they are replaced rather than summed.
+#[derive(Clone, Default, Serialize)]
+pub struct ElementQos {
+    pub processed: u64,
+    pub dropped: u64,
+    // How late the last buffer was when it reached the element, negative means early
+    pub jitter_ms: f64,
+    pub max_jitter_ms: f64,
+    // Rate the element asked upstream to produce at, below 1.0 means it can't keep up
+    pub proportion: f64,
+    pub messages: u64,
+}
+
+// GStreamer's own view of a pipeline, as opposed to what happens after the appsink
+#[derive(Default)]
+pub struct QosStats {
+    elements: Mutex<BTreeMap<String, ElementQos>>,
+    // Minimum latency the pipeline reported in its last latency query
+    latency_ms: Mutex<Option<f64>>,
+}
+
+// JSON shape of the `qos` part of GET /api/metrics/:stream_name
+#[derive(Serialize)]
+pub struct QosSnapshot {
+    pub latency_ms: Option<f64>,
+    pub elements: BTreeMap<String, ElementQos>,
+}
+
+impl QosStats {
+    pub fn record(&self, qos: &gst::message::Qos) {
+        let element = qos.src().map(|src| src.name().to_string()).unwrap_or_default();
+        let (processed, dropped) = qos.stats();
+        let (jitter, proportion, _quality) = qos.values();
+        let jitter_ms = jitter as f64 / 1e6;
+
+        let mut elements = self.elements.lock().unwrap();
+        let entry = elements.entry(element).or_default();
+        // -1 means the element doesn't track it
+        entry.processed = processed.value().max(0) as u64;
+        entry.dropped = dropped.value().max(0) as u64;
+        entry.jitter_ms = jitter_ms;
+        entry.max_jitter_ms = entry.max_jitter_ms.max(jitter_ms);
+        entry.proportion = proportion;
+        entry.messages += 1;
+    }
+
+    // Called on latency messages, after the pipeline redistributed its latency
+    pub fn update_latency(&self, pipeline: &gst::Pipeline) {
+        let mut query = gst::query::Latency::new();
+        if pipeline.query(&mut query) {
+            let (_live, min, _max) = query.result();
+            *self.latency_ms.lock().unwrap() = Some(min.nseconds() as f64 / 1e6);
+        }
+    }
+
+    pub fn snapshot(&self) -> QosSnapshot {
+        QosSnapshot {
+            latency_ms: *self.latency_ms.lock().unwrap(),
+            elements: self.elements.lock().unwrap().clone(),
+        }
+    }
+}
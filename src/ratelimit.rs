@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Drop idle buckets once the map grows past this many addresses
+const MAX_TRACKED_ADDRS: usize = 4096;
+
+// Per-IP token bucket for WebSocket connection attempts
+pub struct RateLimiter {
+    // Tokens refilled per second; 0 disables limiting
+    rate: f64,
+    burst: f64,
+    allow: Vec<IpAddr>,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: u32, allow: Vec<IpAddr>) -> Self {
+        RateLimiter {
+            rate,
+            burst: burst.max(1) as f64,
+            allow,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Take a token for `ip`, returning false when it has run out
+    pub fn check(&self, ip: IpAddr) -> bool {
+        if self.rate <= 0.0 || self.allow.contains(&ip) {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_ADDRS {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, (tokens, at)| *tokens + at.elapsed().as_secs_f64() * rate < burst);
+        }
+
+        let (tokens, at) = buckets.entry(ip).or_insert((self.burst, now));
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * self.rate).min(self.burst);
+        *at = now;
+
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,338 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gst::prelude::*;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::config::{CameraConfig, KeyframeInterval, RecordingCodec, RecordingContainer};
+use crate::error::{NvrError, Result};
+use crate::motion::MotionState;
+use crate::onvif::CameraEvent;
+use crate::rotation;
+
+// Length of each recorded file
+const SEGMENT_DURATION: gst::ClockTime = gst::ClockTime::from_seconds(300);
+
+// Hardware encoders only take a bitrate, used when none is configured
+const DEFAULT_HW_BITRATE_KBPS: u32 = 4000;
+
+// Bounds for keyframe_interval. More than a segment apart would leave segments without one.
+const MAX_KEYFRAME_FRAMES: u32 = 3600;
+const MAX_KEYFRAME_SECS: f64 = 300.0;
+
+// For a keyframe_interval in seconds when the camera doesn't announce its frame rate
+const ASSUMED_FPS: f64 = 25.0;
+
+// A text track cue per second, each lasting until the next
+const TEXT_CUE_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long an event stays in the text track's cues after it arrived
+const TEXT_EVENT_SECS: i64 = 5;
+
+// Hardware encoders first, they are nearly free compared to x264/x265
+fn candidates(codec: RecordingCodec) -> &'static [&'static str] {
+    match codec {
+        RecordingCodec::H264 => &["nvh264enc", "x264enc"],
+        RecordingCodec::H265 => &["nvh265enc", "x265enc"],
+    }
+}
+
+// First encoder for `codec` that is installed, if any
+pub fn select_encoder(codec: RecordingCodec) -> Option<&'static str> {
+    candidates(codec)
+        .iter()
+        .copied()
+        .find(|factory| gst::ElementFactory::find(factory).is_some())
+}
+
+// Extra tee branch encoding the decoded camera video into segmented files, Matroska or
+// MP4 per recording_container. Matroska stays playable if the server dies mid-segment,
+// unlike MP4 without its final moov. The leaky queue keeps a slow encoder from stalling
+// the live preview, the valve pauses recording outside the camera's schedule.
+pub fn recording_branch(camera: &CameraConfig, encoder: &str, recording: bool) -> String {
+    let default_crf = match camera.recording_codec {
+        RecordingCodec::H264 => 23,
+        RecordingCodec::H265 => 28,
+    };
+    let crf = camera.recording_crf.unwrap_or(default_crf);
+
+    let encoder = match (encoder, camera.recording_bitrate) {
+        ("x264enc", Some(kbps)) => format!("x264enc speed-preset=veryfast bitrate={}", kbps),
+        ("x264enc", None) => format!("x264enc speed-preset=veryfast pass=qual quantizer={}", crf),
+        ("x265enc", Some(kbps)) => format!("x265enc speed-preset=veryfast bitrate={}", kbps),
+        ("x265enc", None) => format!("x265enc speed-preset=veryfast option-string=crf={}", crf),
+        (hardware, kbps) => format!("{} bitrate={}", hardware, kbps.unwrap_or(DEFAULT_HW_BITRATE_KBPS)),
+    };
+    // An interval in seconds is set once the frame rate is known, see keyframe_interval
+    let keyframes = match camera.keyframe_interval {
+        Some(KeyframeInterval::Frames(frames)) => format!(" {}={}", gop_property(&encoder), frames),
+        _ => String::new(),
+    };
+    let parser = match camera.recording_codec {
+        RecordingCodec::H264 => "h264parse",
+        RecordingCodec::H265 => "h265parse",
+    };
+    let muxer = match camera.recording_container {
+        RecordingContainer::Mkv => "matroskamux",
+        RecordingContainer::Mp4 => "mp4mux",
+    };
+
+    format!(
+        " t. ! queue leaky=downstream max-size-buffers=30 ! valve name=recording-valve drop={} ! {}videoconvert ! {} name=recording-encoder{} ! {} ! splitmuxsink name=recorder muxer-factory={} max-size-time={}",
+        !recording,
+        rotation::branch_flip(camera),
+        encoder,
+        keyframes,
+        parser,
+        muxer,
+        SEGMENT_DURATION.nseconds()
+    )
+}
+
+// Timed-text track muxed next to the recorded video, fed by text_track. Matroska stores it
+// as an S_TEXT/UTF8 subtitle track, MP4 as a tx3g one; players offer to switch either on
+// and off.
+pub fn text_track_branch() -> String {
+    " appsrc name=recording-text is-live=true do-timestamp=true format=time caps=\"text/x-raw,format=(string)utf8\" ! queue ! recorder.subtitle_%u".to_string()
+}
+
+// Feed the recording's text track from its own thread: every second a cue with the camera
+// name and UTC time, the motion detector's box while it sees motion, and the camera's
+// events of the last few seconds. Nothing is written while recording is paused. The
+// thread exits once the pipeline is gone.
+pub fn text_track(
+    pipeline: &gst::Pipeline,
+    stream_name: &str,
+    label: &str,
+    events: &broadcast::Sender<CameraEvent>,
+    motion_state: Arc<Mutex<MotionState>>,
+) -> Result<()> {
+    let Some(appsrc) = pipeline.by_name("recording-text") else { return Ok(()) };
+    let appsrc = appsrc
+        .downcast::<gst_app::AppSrc>()
+        .map_err(|_| NvrError::ElementMissing("recording-text appsrc".to_string()))?
+        .downgrade();
+    let valve = pipeline.by_name("recording-valve").map(|valve| valve.downgrade());
+    let mut events = events.subscribe();
+    let label = label.to_string();
+    println!("{}: Writing a text track into the recording", stream_name);
+
+    std::thread::spawn(move || {
+        let mut recent: VecDeque<(DateTime<Utc>, String)> = VecDeque::new();
+        while let Some(appsrc) = appsrc.upgrade() {
+            let now = Utc::now();
+            loop {
+                match events.try_recv() {
+                    Ok(event) => recent.push_back((now, describe_event(&event))),
+                    Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                }
+            }
+            while recent.front().is_some_and(|(seen, _)| now - *seen >= TimeDelta::seconds(TEXT_EVENT_SECS)) {
+                recent.pop_front();
+            }
+
+            let paused = valve
+                .as_ref()
+                .and_then(|valve| valve.upgrade())
+                .is_some_and(|valve| valve.property::<bool>("drop"));
+            if !paused {
+                let mut text = format!("{} {}", label, now.format("%Y-%m-%d %H:%M:%S UTC"));
+                if let Some(motion) = motion_state.lock().unwrap().motion_box {
+                    text.push_str(&format!("\nMotion at {},{} {}x{}", motion.x, motion.y, motion.width, motion.height));
+                }
+                for (_, event) in &recent {
+                    text.push('\n');
+                    text.push_str(event);
+                }
+                let mut buffer = gst::Buffer::from_slice(text.into_bytes());
+                if let Some(buffer) = buffer.get_mut() {
+                    buffer.set_duration(gst::ClockTime::from_nseconds(TEXT_CUE_INTERVAL.as_nanos() as u64));
+                }
+                // Flushing until the pipeline plays, the next cue is a second away anyway
+                let _ = appsrc.push_buffer(buffer);
+            }
+            drop(appsrc);
+            std::thread::sleep(TEXT_CUE_INTERVAL);
+        }
+    });
+    Ok(())
+}
+
+// "RuleEngine/CellMotionDetector/Motion on (onvif)"
+fn describe_event(event: &CameraEvent) -> String {
+    format!("{} {} ({})", event.topic, if event.active { "on" } else { "off" }, event.source)
+}
+
+// Keyframe distance property, by encoder
+fn gop_property(encoder: &str) -> &'static str {
+    match encoder.split_whitespace().next() {
+        Some("x264enc" | "x265enc") => "key-int-max",
+        _ => "gop-size",
+    }
+}
+
+// "2s", "0.5s" -> seconds
+fn interval_secs(interval: &str) -> Option<f64> {
+    interval.trim().strip_suffix('s')?.trim().parse().ok().filter(|secs: &f64| secs.is_finite())
+}
+
+// For config validation
+pub fn check_keyframe_interval(interval: &KeyframeInterval) -> std::result::Result<(), String> {
+    match interval {
+        KeyframeInterval::Frames(frames) if *frames == 0 || *frames > MAX_KEYFRAME_FRAMES => {
+            Err(format!("keyframe_interval {} is outside 1-{} frames", frames, MAX_KEYFRAME_FRAMES))
+        }
+        KeyframeInterval::Frames(_) => Ok(()),
+        KeyframeInterval::Seconds(interval) => match interval_secs(interval) {
+            Some(secs) if secs > 0.0 && secs <= MAX_KEYFRAME_SECS => Ok(()),
+            Some(_) => Err(format!("keyframe_interval {:?} is outside 0-{}s", interval, MAX_KEYFRAME_SECS)),
+            None => Err(format!("keyframe_interval {:?} must be a number of frames or seconds like \"2s\"", interval)),
+        },
+    }
+}
+
+// A keyframe_interval in seconds becomes frames at the frame rate the encoder is given.
+// Set from the caps event on the way in, before the encoder configures itself from them.
+pub fn keyframe_interval(pipeline: &gst::Pipeline, stream_name: &str, camera: &CameraConfig) {
+    let Some(KeyframeInterval::Seconds(interval)) = &camera.keyframe_interval else { return };
+    let Some(secs) = interval_secs(interval) else { return };
+    let Some(encoder) = pipeline.by_name("recording-encoder") else { return };
+    let Some(sink) = encoder.static_pad("sink") else { return };
+    let property = gop_property(&encoder.factory().map(|factory| factory.name().to_string()).unwrap_or_default());
+
+    let stream_name = stream_name.to_string();
+    sink.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        let Some(gst::PadProbeData::Event(ref event)) = info.data else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let gst::EventView::Caps(caps) = event.view() else {
+            return gst::PadProbeReturn::Ok;
+        };
+        let fps = caps
+            .caps()
+            .structure(0)
+            .and_then(|s| s.get::<gst::Fraction>("framerate").ok())
+            .filter(|fps| fps.numer() > 0 && fps.denom() > 0)
+            .map(|fps| fps.numer() as f64 / fps.denom() as f64);
+        let fps = fps.unwrap_or_else(|| {
+            println!("{}: Camera doesn't announce its frame rate, assuming {} fps for keyframe_interval", stream_name, ASSUMED_FPS);
+            ASSUMED_FPS
+        });
+        let frames = ((secs * fps).round() as u32).clamp(1, MAX_KEYFRAME_FRAMES);
+        println!("{}: Recording keyframe every {} frames ({} at {:.2} fps)", stream_name, frames, interval, fps);
+        encoder.set_property_from_str(property, &frames.to_string());
+        gst::PadProbeReturn::Ok
+    });
+}
+
+// Have the recorder's file sink wait for each buffer's time on the pipeline clock. Set
+// before the pipeline starts, splitmuxsink only creates its default sink when none is set.
+pub fn sync_to_clock(pipeline: &gst::Pipeline) -> Result<()> {
+    let Some(recorder) = pipeline.by_name("recorder") else { return Ok(()) };
+    let sink = gst::ElementFactory::make("filesink").property("sync", true).build()?;
+    recorder.set_property("sink", &sink);
+    Ok(())
+}
+
+// Name segments <dir>/<stream>-<UTC start time, ms>.mkv (or .mp4) so they sort and are
+// easy to find
+pub fn name_segments(pipeline: &gst::Pipeline, stream_name: &str, dir: &Path, container: RecordingContainer) {
+    let Some(recorder) = pipeline.by_name("recorder") else { return };
+    let stream_name = stream_name.to_string();
+    let dir: PathBuf = dir.to_path_buf();
+    recorder.connect("format-location", false, move |_| {
+        let path = segment_path(&dir, &stream_name, Utc::now(), container);
+        println!("{}: Recording to {}", stream_name, path.display());
+        Some(path.to_string_lossy().to_value())
+    });
+}
+
+fn segment_path(dir: &Path, stream_name: &str, start: DateTime<Utc>, container: RecordingContainer) -> PathBuf {
+    let stem = format!("{}-{}", stream_name, start.format("%Y%m%d-%H%M%S-%3f"));
+    unique_segment_path(dir, &stem, container.extension())
+}
+
+// Splits within the same millisecond (or a clock step backwards onto an existing file) get
+// a -1, -2, ... suffix instead of overwriting the earlier segment. The previous segment's
+// file already exists by the time the next one is named, splitmuxsink opens it right away.
+fn unique_segment_path(dir: &Path, stem: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", stem, extension));
+    let mut counter = 0;
+    while path.exists() {
+        counter += 1;
+        path = dir.join(format!("{}-{}.{}", stem, counter, extension));
+    }
+    path
+}
+
+// Red dot in the preview's top right corner, shown while the recording valve is open. Sits
+// on the preview branch after the tee, so it never reaches the recording. set_recording
+// shows and hides it.
+pub fn indicator_element(recording: bool) -> String {
+    format!(
+        "textoverlay name=recording-indicator text=\"\u{25CF}\" color=0xffff0000 halignment=right valignment=top font-desc=\"Sans 20\" shaded-background=false silent={} ! ",
+        !recording
+    )
+}
+
+// None if the pipeline doesn't record at all
+pub fn is_recording(pipeline: &gst::Pipeline) -> Option<bool> {
+    let valve = pipeline.by_name("recording-valve")?;
+    Some(!valve.property::<bool>("drop"))
+}
+
+// Open or close the recording valve. Resuming starts a new segment so files are named
+// after the time recording actually restarted.
+pub fn set_recording(pipeline: &gst::Pipeline, stream_name: &str, recording: bool) -> bool {
+    if is_recording(pipeline).is_none_or(|current| current == recording) {
+        return false;
+    }
+    println!("{}: Recording {}", stream_name, if recording { "started" } else { "paused" });
+    if recording {
+        if let Some(recorder) = pipeline.by_name("recorder") {
+            recorder.emit_by_name::<()>("split-now", &[]);
+        }
+    }
+    if let Some(valve) = pipeline.by_name("recording-valve") {
+        valve.set_property("drop", !recording);
+    }
+    if let Some(indicator) = pipeline.by_name("recording-indicator") {
+        indicator.set_property("silent", !recording);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn rapid_rotation_gets_distinct_segment_names() {
+        let dir = std::env::temp_dir().join(format!("nvr-segments-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let start = Utc::now();
+
+        // Splits a millisecond apart, then several within the same millisecond. Each file
+        // exists before the next is named, as splitmuxsink opens it right away.
+        let starts = [start, start + TimeDelta::milliseconds(1), start + TimeDelta::milliseconds(2)]
+            .into_iter()
+            .chain(std::iter::repeat_n(start + TimeDelta::milliseconds(3), 5));
+        let mut names = HashSet::new();
+        for start in starts {
+            let path = segment_path(&dir, "cam", start, RecordingContainer::Mkv);
+            std::fs::File::create(&path).unwrap();
+            assert!(names.insert(path.file_name().unwrap().to_owned()), "{} named twice", path.display());
+        }
+        assert_eq!(names.len(), 8);
+        let same_ms = (start + TimeDelta::milliseconds(3)).format("%Y%m%d-%H%M%S-%3f");
+        assert!(names.contains(std::ffi::OsStr::new(&format!("cam-{}-4.mkv", same_ms))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
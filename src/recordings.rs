@@ -0,0 +1,101 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::Body;
+
+// GET /recordings/:stream/:file, with single-range `Range` support so browsers can seek
+pub async fn serve_recording(
+    base: PathBuf,
+    stream: String,
+    file: String,
+    range: Option<String>,
+) -> Result<Response<Body>, warp::Rejection> {
+    let path = resolve_recording_path(&base, &stream, &file).ok_or_else(warp::reject::not_found)?;
+    
+    let mut recording = tokio::fs::File::open(&path).await.map_err(|_| warp::reject::not_found())?;
+    let len = recording.metadata().await.map_err(|_| warp::reject::not_found())?.len();
+    
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type(&path))
+        .header(header::ACCEPT_RANGES, "bytes");
+    
+    let Some(range) = range else {
+        let body = Body::wrap_stream(ReaderStream::new(recording));
+        return Ok(response.header(header::CONTENT_LENGTH, len).body(body).unwrap());
+    };
+    
+    let Some((start, end)) = parse_range(&range, len) else {
+        println!("Unsatisfiable range {:?} for {}", range, path.display());
+        let response = response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Body::empty())
+            .unwrap();
+        return Ok(response);
+    };
+    
+    recording.seek(SeekFrom::Start(start)).await.map_err(|_| warp::reject::not_found())?;
+    let body = Body::wrap_stream(ReaderStream::new(recording.take(end - start + 1)));
+    
+    let response = response
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+        .header(header::CONTENT_LENGTH, end - start + 1)
+        .body(body)
+        .unwrap();
+    Ok(response)
+}
+
+// Resolve `stream/file` inside the recordings dir, refusing anything that could escape it
+// (separators, dot segments, or symlinks pointing outside the base)
+fn resolve_recording_path(base: &Path, stream: &str, file: &str) -> Option<PathBuf> {
+    let is_plain = |part: &str| {
+        !part.is_empty() && !part.starts_with('.') && !part.contains(['/', '\\', '\0'])
+    };
+    if !is_plain(stream) || !is_plain(file) {
+        return None;
+    }
+    
+    let base = base.canonicalize().ok()?;
+    let path = base.join(stream).join(file).canonicalize().ok()?;
+    (path.starts_with(&base) && path.is_file()).then_some(path)
+}
+
+// Parse the first range of a `bytes=` header into inclusive (start, end) offsets
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    if len == 0 {
+        return None;
+    }
+    
+    let spec = range.trim().strip_prefix("bytes=")?.split(',').next()?.trim();
+    let (start, end) = spec.split_once('-')?;
+    
+    let (start, end) = if start.is_empty() {
+        // bytes=-N => last N bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 {
+            return None;
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = match end {
+            "" => len - 1,
+            end => end.parse::<u64>().ok()?.min(len - 1),
+        };
+        (start, end)
+    };
+    
+    (start <= end && start < len).then_some((start, end))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("mp4") => "video/mp4",
+        Some("mkv") => "video/x-matroska",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,144 @@
+use gstreamer as gst;
+use gstreamer_app as gst_app;
+use gst::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+use crate::branches;
+use crate::config::CameraConfig;
+use crate::error::{NvrError, Result};
+
+// Frames a viewer's ROI encode may be ahead of its WebSocket, newer ones are dropped
+pub const ROI_CHANNEL_FRAMES: usize = 2;
+
+// Part of the camera picture a viewer zooms in on, as fractions of its width and height
+// (0.0-1.0) so it doesn't depend on the camera's resolution
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl Region {
+    // "x,y,w,h", for the ?roi= query parameter
+    pub fn parse(spec: &str) -> std::result::Result<Self, String> {
+        let parts: Vec<f64> = spec
+            .split(',')
+            .map(|part| part.trim().parse::<f64>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| format!("roi {:?} must be x,y,w,h", spec))?;
+        let [x, y, w, h] = parts[..] else {
+            return Err(format!("roi {:?} must be x,y,w,h", spec));
+        };
+        let region = Region { x, y, w, h };
+        region.check()?;
+        Ok(region)
+    }
+
+    pub fn check(&self) -> std::result::Result<(), String> {
+        let Region { x, y, w, h } = *self;
+        if ![x, y, w, h].iter().all(|value| value.is_finite()) {
+            return Err("roi values must be numbers".to_string());
+        }
+        if x < 0.0 || y < 0.0 || w <= 0.0 || h <= 0.0 || x + w > 1.0 || y + h > 1.0 {
+            return Err(format!("roi {},{} {}x{} isn't inside the picture (0.0-1.0)", x, y, w, h));
+        }
+        Ok(())
+    }
+
+    // videocrop's left, right, top and bottom for a picture of `width` x `height`
+    fn crop(&self, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        let left = (self.x * width as f64).round() as i32;
+        let top = (self.y * height as f64).round() as i32;
+        let right = width - ((self.x + self.w) * width as f64).round() as i32;
+        let bottom = height - ((self.y + self.h) * height as f64).round() as i32;
+        (left.max(0), right.max(0), top.max(0), bottom.max(0))
+    }
+}
+
+// A viewer's zoomed view of a stream. Outlives pipeline rebuilds, the supervisor attaches a
+// new branch for it to each new pipeline.
+pub struct RoiView {
+    pub name: String,
+    pub region: Arc<Mutex<Region>>,
+    pub frames: mpsc::Sender<Vec<u8>>,
+}
+
+// Crop, scale and JPEG-encode `view`'s region for one viewer, from the tee of a running
+// pipeline. Scaled to the camera's preview size with black bars, so the zoomed picture
+// keeps its proportions. Returns the branch, for branches::remove.
+pub fn attach(pipeline: &gst::Pipeline, camera: &CameraConfig, view: &RoiView) -> Result<gst::Bin> {
+    let rate = match camera.preview_fps {
+        Some(fps) => format!("videorate drop-only=true max-rate={} ! ", fps),
+        None => String::new(),
+    };
+    let description = format!(
+        "queue leaky=downstream max-size-buffers=2 ! {}videocrop name=roi-crop ! videoscale add-borders=true ! videoconvert ! video/x-raw,format=I420,width={},height={},pixel-aspect-ratio=1/1 ! jpegenc quality={} ! appsink name=roi-sink sync=false",
+        rate, camera.width, camera.height, camera.jpeg_quality
+    );
+    let branch = gst::parse::bin_from_description(&description, true)?;
+    let crop = branch.by_name("roi-crop").ok_or_else(|| NvrError::ElementMissing("roi videocrop".to_string()))?;
+    let appsink = branch
+        .by_name("roi-sink")
+        .and_then(|sink| sink.downcast::<gst_app::AppSink>().ok())
+        .ok_or_else(|| NvrError::ElementMissing("roi appsink".to_string()))?;
+
+    // The crop is in pixels, worked out again whenever the camera's resolution changes
+    let region = view.region.clone();
+    let crop_weak = crop.downgrade();
+    crop.static_pad("sink").expect("videocrop without sink pad").add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        let Some(gst::PadProbeData::Event(ref event)) = info.data else {
+            return gst::PadProbeReturn::Ok;
+        };
+        if let gst::EventView::Caps(caps) = event.view() {
+            if let (Some(crop), Some(size)) = (crop_weak.upgrade(), caps_size(caps.caps())) {
+                set_crop(&crop, *region.lock().unwrap(), size);
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    let frames = view.frames.clone();
+    appsink.set_callbacks(
+        gst_app::AppSinkCallbacks::builder()
+            .new_sample(move |appsink| {
+                let Ok(sample) = appsink.pull_sample() else { return Ok(gst::FlowSuccess::Ok) };
+                let Some(buffer) = sample.buffer() else { return Ok(gst::FlowSuccess::Ok) };
+                let Ok(map) = buffer.map_readable() else { return Ok(gst::FlowSuccess::Ok) };
+                // A viewer that isn't keeping up misses frames, the encode doesn't wait for
+                // it. One that's gone is detached by its RoiLease shortly.
+                let _ = frames.try_send(map.to_vec());
+                Ok(gst::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+
+    branches::add(pipeline, &branch)?;
+    Ok(branch)
+}
+
+// Move a running branch to `region`. Before the branch has seen caps the probe in attach
+// applies it instead.
+pub fn apply(branch: &gst::Bin, region: Region) {
+    let Some(crop) = branch.by_name("roi-crop") else { return };
+    let size = crop.static_pad("sink").and_then(|pad| pad.current_caps()).and_then(|caps| caps_size(&caps));
+    if let Some(size) = size {
+        set_crop(&crop, region, size);
+    }
+}
+
+fn set_crop(crop: &gst::Element, region: Region, (width, height): (i32, i32)) {
+    let (left, right, top, bottom) = region.crop(width, height);
+    crop.set_property("left", left);
+    crop.set_property("right", right);
+    crop.set_property("top", top);
+    crop.set_property("bottom", bottom);
+}
+
+fn caps_size(caps: &gst::CapsRef) -> Option<(i32, i32)> {
+    let structure = caps.structure(0)?;
+    Some((structure.get::<i32>("width").ok()?, structure.get::<i32>("height").ok()?))
+}
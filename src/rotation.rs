@@ -0,0 +1,66 @@
+use crate::config::{CameraConfig, RotationMode};
+
+// videoflip element rotating decoded video clockwise by `degrees`, with a trailing " ! "
+pub fn videoflip(degrees: u32) -> String {
+    let method = match degrees {
+        90 => "clockwise",
+        180 => "rotate-180",
+        270 => "counterclockwise",
+        _ => return String::new(),
+    };
+    format!("videoflip method={} ! ", method)
+}
+
+// Rotation applied right after decoding, shared by every branch
+pub fn source_flip(camera: &CameraConfig) -> String {
+    match camera.rotation_mode {
+        RotationMode::Pixels => videoflip(camera.rotation),
+        RotationMode::Metadata => String::new(),
+    }
+}
+
+// Rotation for branches that need real pixels (recording, restreaming) when the preview
+// only carries metadata
+pub fn branch_flip(camera: &CameraConfig) -> String {
+    match camera.rotation_mode {
+        RotationMode::Pixels => String::new(),
+        RotationMode::Metadata => videoflip(camera.rotation),
+    }
+}
+
+// EXIF orientation value for the preview JPEGs, None when nothing needs tagging
+pub fn exif_orientation(camera: &CameraConfig) -> Option<u16> {
+    if camera.rotation_mode != RotationMode::Metadata {
+        return None;
+    }
+    match camera.rotation {
+        90 => Some(6),
+        180 => Some(3),
+        270 => Some(8),
+        _ => None,
+    }
+}
+
+// Insert a minimal APP1 Exif segment holding only the orientation tag right after SOI.
+// Browsers honour it when drawing the image, so the preview never gets rotated here.
+pub fn tag_orientation(jpeg: &[u8], orientation: u16) -> Vec<u8> {
+    if jpeg.len() < 2 || jpeg[..2] != [0xFF, 0xD8] {
+        return jpeg.to_vec();
+    }
+
+    let mut tagged = Vec::with_capacity(jpeg.len() + 36);
+    tagged.extend_from_slice(&[0xFF, 0xD8]);
+    // APP1, length 34 (counting itself)
+    tagged.extend_from_slice(&[0xFF, 0xE1, 0x00, 0x22]);
+    tagged.extend_from_slice(b"Exif\0\0");
+    // Big-endian TIFF header, first IFD right after it
+    tagged.extend_from_slice(&[b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08]);
+    // One entry: Orientation (0x0112), SHORT, count 1, value padded to 4 bytes
+    tagged.extend_from_slice(&[0x00, 0x01, 0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01]);
+    tagged.extend_from_slice(&orientation.to_be_bytes());
+    tagged.extend_from_slice(&[0x00, 0x00]);
+    // No next IFD
+    tagged.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    tagged.extend_from_slice(&jpeg[2..]);
+    tagged
+}
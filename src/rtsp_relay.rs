@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Result};
+use gstreamer_rtsp_server as gst_rtsp_server;
+use gst_rtsp_server::prelude::*;
+use std::net::SocketAddr;
+
+use crate::config::CameraConfig;
+
+// Bitrate of the relayed H.264, like the RTMP restream
+const RELAY_BITRATE_KBPS: u32 = 2500;
+
+// Keeps the server alive, clients are dispatched by the supervisor's glib main loop
+pub struct RtspRelay {
+    _server: gst_rtsp_server::RTSPServer,
+    _source: gst_rtsp_server::glib::SourceId,
+}
+
+// Channel the camera pipeline's relay branch feeds, see relay_branch
+fn channel(stream_name: &str) -> String {
+    format!("rtsp-relay-{}", stream_name)
+}
+
+// Tee branch handing the decoded camera video to the relay. The camera is connected once,
+// however many RTSP clients watch.
+pub fn relay_branch(stream_name: &str, flip: &str) -> String {
+    format!(" t. ! queue leaky=downstream max-size-buffers=5 ! {}intervideosink channel={}", flip, channel(stream_name))
+}
+
+// Serve every camera with rtsp_relay at rtsp://<address>/<name>. Each mount encodes H.264
+// once, shared by all of its clients, and only while at least one is connected.
+pub fn start(address: SocketAddr, cameras: &[CameraConfig]) -> Result<RtspRelay> {
+    let server = gst_rtsp_server::RTSPServer::new();
+    server.set_address(&address.ip().to_string());
+    server.set_service(&address.port().to_string());
+    let mounts = server.mount_points().ok_or_else(|| anyhow!("RTSP server without mount points"))?;
+
+    for camera in cameras.iter().filter(|camera| camera.rtsp_relay) {
+        let factory = gst_rtsp_server::RTSPMediaFactory::new();
+        factory.set_launch(&format!(
+            "( intervideosrc channel={} ! videoconvert ! x264enc tune=zerolatency speed-preset=veryfast bitrate={} key-int-max=60 ! video/x-h264,profile=main ! rtph264pay name=pay0 pt=96 config-interval=1 )",
+            channel(&camera.name),
+            RELAY_BITRATE_KBPS
+        ));
+        factory.set_shared(true);
+        mounts.add_factory(&format!("/{}", camera.name), factory);
+        println!("{}: Relaying over RTSP at rtsp://{}/{}", camera.name, address, camera.name);
+    }
+
+    let source = server.attach(None)?;
+    Ok(RtspRelay { _server: server, _source: source })
+}
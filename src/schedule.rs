@@ -0,0 +1,90 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Days, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+use crate::config::{CameraConfig, RecordingSchedule};
+
+// Whether a recording camera should be recording right now
+pub fn should_record(camera: &CameraConfig, now: DateTime<Utc>) -> bool {
+    match &camera.recording_schedule {
+        Some(schedule) => Schedule::compile(schedule).is_ok_and(|schedule| schedule.is_active(now)),
+        None => camera.record,
+    }
+}
+
+// A recording schedule with its timezone and times parsed
+pub struct Schedule {
+    timezone: Tz,
+    ranges: Vec<Range>,
+}
+
+struct Range {
+    // Empty means every day
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    // Before `start` means the range runs past midnight into the next day
+    end: NaiveTime,
+}
+
+impl Schedule {
+    pub fn compile(config: &RecordingSchedule) -> Result<Schedule> {
+        let timezone: Tz = config
+            .timezone
+            .parse()
+            .map_err(|_| anyhow!("unknown timezone {:?}, use an IANA name like Europe/Berlin or UTC", config.timezone))?;
+        if config.ranges.is_empty() {
+            bail!("recording_schedule has no ranges");
+        }
+
+        let mut ranges = Vec::new();
+        for range in &config.ranges {
+            let days = range
+                .days
+                .iter()
+                .map(|day| day.parse::<Weekday>().map_err(|_| anyhow!("unknown day {:?}", day)))
+                .collect::<Result<Vec<_>>>()?;
+            let start = parse_time(&range.start)?;
+            let end = parse_time(&range.end)?;
+            if start == end {
+                bail!("range {}-{} is empty", range.start, range.end);
+            }
+            ranges.push(Range { days, start, end });
+        }
+
+        Ok(Schedule { timezone, ranges })
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.timezone);
+        let (day, time) = (local.weekday(), local.time());
+        self.ranges.iter().any(|range| {
+            let on = |day: Weekday| range.days.is_empty() || range.days.contains(&day);
+            if range.start < range.end {
+                on(day) && range.start <= time && time < range.end
+            } else {
+                (on(day) && time >= range.start) || (on(day.pred()) && time < range.end)
+            }
+        })
+    }
+
+    // When recording next starts or stops, looking up to a week ahead
+    pub fn next_change(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let active = self.is_active(now);
+        let today = now.with_timezone(&self.timezone).date_naive();
+
+        let mut boundaries: Vec<DateTime<Utc>> = (0..=8)
+            .filter_map(|offset| today.checked_add_days(Days::new(offset)))
+            .flat_map(|date| self.ranges.iter().flat_map(move |range| [date.and_time(range.start), date.and_time(range.end)]))
+            // Local times skipped by a DST change don't exist, ambiguous ones take the first
+            .filter_map(|local| self.timezone.from_local_datetime(&local).earliest())
+            .map(|at| at.with_timezone(&Utc))
+            .filter(|at| *at > now)
+            .collect();
+        boundaries.sort();
+        boundaries.into_iter().find(|at| self.is_active(*at) != active)
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| anyhow!("time {:?} must be HH:MM", time))
+}
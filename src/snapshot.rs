@@ -0,0 +1,37 @@
+use anyhow::{bail, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::io::Read;
+use std::time::Duration;
+
+// Cameras answer snapshot requests quickly or not at all
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Larger than any sane still, guards against a misconfigured URL streaming forever
+const MAX_SNAPSHOT_BYTES: u64 = 10 * 1024 * 1024;
+
+// A camera's HTTP still image endpoint
+#[derive(Clone)]
+pub struct SnapshotSource {
+    pub uri: String,
+    pub user: String,
+    pub pass: String,
+}
+
+// Blocking, call from spawn_blocking
+pub fn fetch(source: &SnapshotSource) -> Result<Vec<u8>> {
+    let credentials = BASE64.encode(format!("{}:{}", source.user, source.pass));
+    let response = ureq::get(&source.uri)
+        .timeout(HTTP_TIMEOUT)
+        .set("Authorization", &format!("Basic {}", credentials))
+        .call()?;
+
+    let content_type = response.content_type().to_string();
+    if content_type != "image/jpeg" {
+        bail!("snapshot is {:?}, expected image/jpeg", content_type);
+    }
+
+    let mut jpeg = Vec::new();
+    response.into_reader().take(MAX_SNAPSHOT_BYTES).read_to_end(&mut jpeg)?;
+    Ok(jpeg)
+}
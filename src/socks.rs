@@ -0,0 +1,241 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+
+// rtspsrc can't talk SOCKS, so each proxied camera gets a local port that forwards every
+// connection through the SOCKS5 proxy to the camera. rtspsrc is pointed at that port and
+// forced to RTSP-over-TCP, since SOCKS CONNECT only carries TCP (no RTP over UDP).
+//
+// The same forwarding, minus the proxy, pins the RTSP connection of a local_address camera
+// to one of the host's addresses, which rtspsrc has no setting for. That's TCP only too:
+// rtspsrc would send RTCP for UDP streams to the tunnel's 127.0.0.1, not the camera.
+//
+// Limitation: cameras that answer with an absolute Content-Base naming their own address
+// make rtspsrc send the follow-up requests there directly, bypassing the tunnel.
+pub struct Tunnel {
+    // Camera URL rewritten to the local forwarding port
+    pub local_url: String,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Tunnel {
+    // Aborting the accept loop drops its JoinSet, which aborts every open connection
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Proxy address as host:port, optionally socks5://[user:pass@]host:port
+#[derive(Clone)]
+struct Proxy {
+    addr: String,
+    credentials: Option<(String, String)>,
+}
+
+// Where a tunnel's connections are forwarded
+#[derive(Clone)]
+enum Upstream {
+    Socks(Proxy),
+    // Straight to the camera, from a socket bound to this address
+    Bound(IpAddr),
+}
+
+// Must be called from within the tokio runtime
+pub fn open(stream_name: &str, camera_url: &str, proxy: &str) -> Result<Tunnel> {
+    listen(stream_name, camera_url, Upstream::Socks(parse_proxy(proxy)?))
+}
+
+// Must be called from within the tokio runtime
+pub fn bind(stream_name: &str, camera_url: &str, local_address: IpAddr) -> Result<Tunnel> {
+    listen(stream_name, camera_url, Upstream::Bound(local_address))
+}
+
+fn listen(stream_name: &str, camera_url: &str, upstream: Upstream) -> Result<Tunnel> {
+    let (userinfo, host, port, path) = split_rtsp_url(camera_url).ok_or_else(|| anyhow!("can't parse camera url for tunnelling"))?;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    listener.set_nonblocking(true)?;
+    let local_port = listener.local_addr()?.port();
+    let listener = TcpListener::from_std(listener)?;
+    match &upstream {
+        Upstream::Socks(proxy) => {
+            println!("{}: Tunnelling {}:{} through SOCKS5 proxy {} on local port {}", stream_name, host, port, proxy.addr, local_port)
+        }
+        Upstream::Bound(local_address) => {
+            println!("{}: Connecting to {}:{} from {} through local port {}", stream_name, host, port, local_address, local_port)
+        }
+    }
+
+    let stream_name = stream_name.to_string();
+    let task = tokio::spawn(async move {
+        let mut connections = JoinSet::new();
+        loop {
+            let client = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((client, _)) => client,
+                    Err(err) => {
+                        println!("{}: Tunnel accept failed: {:?}", stream_name, err);
+                        continue;
+                    }
+                },
+                // Reap finished connections so the set doesn't grow forever
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            };
+
+            let (stream_name, host, upstream) = (stream_name.clone(), host.clone(), upstream.clone());
+            connections.spawn(async move {
+                if let Err(err) = forward(client, &upstream, &host, port).await {
+                    println!("{}: Tunnel connection failed: {:?}", stream_name, err);
+                }
+            });
+        }
+    });
+
+    Ok(Tunnel {
+        local_url: format!("rtsp://{}127.0.0.1:{}{}", userinfo, local_port, path),
+        task,
+    })
+}
+
+async fn forward(mut client: TcpStream, upstream: &Upstream, host: &str, port: u16) -> Result<()> {
+    let mut upstream = match upstream {
+        Upstream::Socks(proxy) => {
+            let mut stream = TcpStream::connect(&proxy.addr).await.with_context(|| format!("connecting to proxy {}", proxy.addr))?;
+            handshake(&mut stream, proxy.credentials.as_ref(), host, port).await?;
+            stream
+        }
+        Upstream::Bound(local_address) => connect_from(*local_address, host, port).await?,
+    };
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+async fn connect_from(local_address: IpAddr, host: &str, port: u16) -> Result<TcpStream> {
+    let camera = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("resolving {}", host))?
+        .find(|addr| addr.is_ipv4() == local_address.is_ipv4())
+        .ok_or_else(|| anyhow!("{} has no address of the same family as {}", host, local_address))?;
+    let socket = match local_address {
+        IpAddr::V4(_) => TcpSocket::new_v4()?,
+        IpAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.bind(SocketAddr::new(local_address, 0))?;
+    socket.connect(camera).await.with_context(|| format!("connecting to {} from {}", camera, local_address))
+}
+
+// RFC 1928 CONNECT, with RFC 1929 username/password auth when credentials are set
+async fn handshake(stream: &mut TcpStream, credentials: Option<&(String, String)>, host: &str, port: u16) -> Result<()> {
+    let method = if credentials.is_some() { 0x02 } else { 0x00 };
+    stream.write_all(&[0x05, 0x01, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [0x05, method] {
+        bail!("proxy refused authentication method {:#04x}", method);
+    }
+
+    if let Some((user, pass)) = credentials {
+        let mut auth = vec![0x01, user.len() as u8];
+        auth.extend_from_slice(user.as_bytes());
+        auth.push(pass.len() as u8);
+        auth.extend_from_slice(pass.as_bytes());
+        stream.write_all(&auth).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0x00 {
+            bail!("proxy rejected the username/password");
+        }
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            request.push(0x03);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // VER REP RSV ATYP, then the bound address and port which we don't need
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[1] != 0x00 {
+        bail!("proxy could not connect to {}:{} (reply {:#04x})", host, port, header[1]);
+    }
+    let addr_len = match header[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await? as usize,
+        atyp => bail!("proxy replied with unknown address type {:#04x}", atyp),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+fn parse_proxy(proxy: &str) -> Result<Proxy> {
+    let rest = proxy
+        .strip_prefix("socks5://")
+        .or_else(|| proxy.strip_prefix("socks5h://"))
+        .unwrap_or(proxy);
+    let (credentials, addr) = match rest.rsplit_once('@') {
+        Some((userinfo, addr)) => {
+            let (user, pass) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+            (Some((user.to_string(), pass.to_string())), addr)
+        }
+        None => (None, rest),
+    };
+    let addr = addr.trim_end_matches('/');
+    if addr.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok()).is_none() {
+        bail!("socks_proxy {:?} must be host:port", proxy);
+    }
+    if credentials.as_ref().is_some_and(|(user, pass)| user.len() > 255 || pass.len() > 255) {
+        bail!("socks_proxy credentials must be at most 255 bytes each");
+    }
+    Ok(Proxy { addr: addr.to_string(), credentials })
+}
+
+// rtsp://[user:pass@]host[:port][/path] -> ("user:pass@", host, port, "/path")
+fn split_rtsp_url(url: &str) -> Option<(String, String, u16, String)> {
+    let rest = url.strip_prefix("rtsp://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    let (userinfo, hostport) = match authority.rsplit_once('@') {
+        Some((userinfo, hostport)) => (format!("{}@", userinfo), hostport),
+        None => (String::new(), authority),
+    };
+
+    // [v6]:port, host:port or bare host
+    let (host, port) = if let Some(v6) = hostport.strip_prefix('[') {
+        let (host, after) = v6.split_once(']')?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match hostport.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (hostport, None),
+        }
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => 554,
+    };
+    Some((userinfo, host.to_string(), port, path.to_string()))
+}
+
+// For config validation
+pub fn check_proxy(proxy: &str) -> Result<()> {
+    parse_proxy(proxy).map(|_| ())
+}
@@ -0,0 +1,55 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamState {
+    // Pipeline started, no frame yet
+    Connecting,
+    Connected,
+    // Pipeline running but no recent frames
+    Stalled,
+    // Pipeline stopped or restarting
+    Disconnected,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StreamStatus {
+    pub stream: String,
+    pub state: StreamState,
+    pub fps: u32,
+}
+
+// Latest status of every stream plus a channel of changes, for /ws/status
+pub struct StatusHub {
+    tx: broadcast::Sender<StreamStatus>,
+    current: Mutex<BTreeMap<String, StreamStatus>>,
+}
+
+impl StatusHub {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(100);
+        StatusHub {
+            tx,
+            current: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Only publishes when something actually changed
+    pub fn update(&self, status: StreamStatus) {
+        let mut current = self.current.lock().unwrap();
+        if current.get(&status.stream) == Some(&status) {
+            return;
+        }
+        current.insert(status.stream.clone(), status.clone());
+        let _ = self.tx.send(status);
+    }
+
+    // Everything as of now, plus the changes after it
+    pub fn subscribe(&self) -> (Vec<StreamStatus>, broadcast::Receiver<StreamStatus>) {
+        let current = self.current.lock().unwrap();
+        (current.values().cloned().collect(), self.tx.subscribe())
+    }
+}
@@ -0,0 +1,688 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use gstreamer as gst;
+use gst::prelude::*;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::audit::AuditLog;
+use crate::branches::{self, BranchKind};
+use crate::config::QualityStep;
+use crate::error::{NvrError, Result};
+use crate::roi::{self, Region, RoiView};
+use crate::socks::{self, Tunnel};
+use crate::{recorder, schedule};
+use crate::{setup_pipeline, PipelineResources, PipelineSpec};
+
+// HLS players keep fetching the playlist while they play. An HLS output nobody fetched
+// from for this long is stopped at the next reap.
+const HLS_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Supervised {
+    spec: PipelineSpec,
+    resources: PipelineResources,
+    // On-demand output branches currently hung off the pipeline's tee
+    branches: HashMap<BranchKind, gst::Bin>,
+    // A branch per zoomed viewer, by RoiLease id
+    roi_branches: HashMap<u64, gst::Bin>,
+    // Outlives in-place pipeline restarts, so the forwarded port stays the same
+    _tunnel: Option<Tunnel>,
+}
+
+// What a degraded preview is held to while shedding load
+#[derive(Clone, Copy)]
+pub struct ShedLimits {
+    pub quality: u32,
+    // None leaves the frame rate alone
+    pub fps: Option<u32>,
+}
+
+// A stream's preview settings as they are right now, for /api/metrics
+#[derive(Clone, Copy, Serialize)]
+pub struct EffectivePreview {
+    pub priority: u32,
+    pub jpeg_quality: u32,
+    // None when every decoded frame is encoded
+    pub max_fps: Option<u32>,
+    // Lowered to shed load
+    pub degraded: bool,
+    // Step of the camera's quality_ladder in effect, counted from 0. None below the first.
+    pub quality_tier: Option<usize>,
+}
+
+// Owns every running camera pipeline, keyed by stream name. Dropping an entry tears the
+// pipeline down (see PipelineResources), so stopped or replaced pipelines don't linger.
+// All bus watches are dispatched by one glib MainLoop running on a tokio blocking thread.
+pub struct PipelineSupervisor {
+    pipelines: Mutex<HashMap<String, Supervised>>,
+    // Pipelines that failed to start for a reason that may clear up, retried by reap
+    failed: Mutex<HashMap<String, PipelineSpec>>,
+    main_loop: glib::MainLoop,
+    // Recording is paused everywhere while the recordings disk is low on space
+    disk_full: AtomicBool,
+    shed_limits: ShedLimits,
+    // Previews of streams with this priority or lower are held to shed_limits, None when
+    // not shedding load
+    shed_through: Mutex<Option<u32>>,
+    // Preview JPEG qualities set at runtime, by stream name. Kept across pipeline rebuilds.
+    quality_overrides: Mutex<HashMap<String, u32>>,
+    // Viewer counts of streams with a quality_ladder, as last reported by set_viewers
+    viewers: Mutex<HashMap<String, usize>>,
+    // Who uses each stream's on-demand outputs. Kept across pipeline rebuilds.
+    output_users: Mutex<HashMap<(String, BranchKind), OutputUsers>>,
+    // Viewers zoomed in on part of a stream, by RoiLease id. Kept across pipeline rebuilds.
+    roi_views: Mutex<HashMap<u64, RoiView>>,
+    next_roi_id: AtomicU64,
+    audit: Arc<AuditLog>,
+}
+
+#[derive(Default)]
+struct OutputUsers {
+    // Open MSE WebSockets
+    leases: usize,
+    // HLS is plain HTTP, players are only known to be gone once they stop fetching
+    hls_until: Option<Instant>,
+}
+
+impl OutputUsers {
+    fn wanted(&self, now: Instant) -> bool {
+        self.leases > 0 || self.hls_until.is_some_and(|until| until > now)
+    }
+}
+
+// Keeps a stream's output encoding while held, see PipelineSupervisor::acquire_output
+pub struct OutputLease {
+    supervisor: Arc<PipelineSupervisor>,
+    name: String,
+    kind: BranchKind,
+}
+
+impl Drop for OutputLease {
+    fn drop(&mut self) {
+        self.supervisor.release_output(&self.name, self.kind);
+    }
+}
+
+// Keeps a viewer's region-of-interest encode while held, see PipelineSupervisor::acquire_roi
+pub struct RoiLease {
+    supervisor: Arc<PipelineSupervisor>,
+    id: u64,
+}
+
+impl RoiLease {
+    // Move the view to another region without restarting its encode
+    pub fn set(&self, region: Region) {
+        self.supervisor.move_roi(self.id, region);
+    }
+}
+
+impl Drop for RoiLease {
+    fn drop(&mut self) {
+        self.supervisor.release_roi(self.id);
+    }
+}
+
+impl PipelineSupervisor {
+    pub fn new(audit: Arc<AuditLog>, shed_limits: ShedLimits) -> Arc<Self> {
+        let main_loop = glib::MainLoop::new(None, false);
+        let main_loop_run = main_loop.clone();
+        tokio::task::spawn_blocking(move || main_loop_run.run());
+
+        Arc::new(PipelineSupervisor {
+            pipelines: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashMap::new()),
+            main_loop,
+            disk_full: AtomicBool::new(false),
+            shed_limits,
+            shed_through: Mutex::new(None),
+            quality_overrides: Mutex::new(HashMap::new()),
+            viewers: Mutex::new(HashMap::new()),
+            output_users: Mutex::new(HashMap::new()),
+            roi_views: Mutex::new(HashMap::new()),
+            next_roi_id: AtomicU64::new(0),
+            audit,
+        })
+    }
+
+    // Start a pipeline, replacing any running one with the same name. If it fails for a
+    // reason that may clear up (see NvrError::is_retryable), reap tries again later.
+    pub fn start(&self, spec: PipelineSpec) -> Result<()> {
+        let name = spec.camera.name.clone();
+        self.stop(&name);
+        // A fresh pipeline gets the full reconnect policy again, also after it gave up
+        spec.stats.backoff.reset();
+
+        let (resources, tunnel) = match build(&spec) {
+            Ok(built) => built,
+            Err(err) => {
+                if err.is_retryable() {
+                    println!("{}: Failed to start, retrying later: {}", name, err);
+                    self.failed.lock().unwrap().insert(name, spec);
+                }
+                return Err(err);
+            }
+        };
+        let supervised = Supervised { spec, resources, branches: HashMap::new(), roi_branches: HashMap::new(), _tunnel: tunnel };
+        apply_preview(&supervised, self.preview(&supervised, *self.shed_through.lock().unwrap()));
+        if recorder::is_recording(&supervised.resources.pipeline) == Some(true) {
+            self.audit.record("recording_start", Some(&name), None, None);
+        }
+        self.pipelines.lock().unwrap().insert(name.clone(), supervised);
+        // A rebuilt pipeline picks the outputs its viewers were using back up
+        self.sync_outputs(&name);
+        self.sync_roi(&name);
+        Ok(())
+    }
+
+    // Tear a stream's pipeline down and build it again from its spec, e.g. for a stuck
+    // camera. The spec keeps the broadcast sender, so viewers stay subscribed throughout.
+    // Returns None for a stream that isn't supervised, else whether the new pipeline got
+    // to Playing within `timeout`.
+    pub fn restart(&self, name: &str, timeout: Duration) -> Option<Result<bool>> {
+        let running = self.pipelines.lock().unwrap().get(name).map(|supervised| supervised.spec.clone());
+        let spec = match running {
+            Some(spec) => spec,
+            None => self.failed.lock().unwrap().get(name).cloned()?,
+        };
+        println!("{}: Restart requested", name);
+        if let Err(err) = self.start(spec) {
+            return Some(Err(err));
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let state = self.pipelines.lock().unwrap().get(name).map(|supervised| supervised.resources.pipeline.current_state());
+            if state == Some(gst::State::Playing) {
+                return Some(Ok(true));
+            }
+            if Instant::now() >= deadline {
+                return Some(Ok(false));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    // Returns false if no pipeline with that name was running
+    pub fn stop(&self, name: &str) -> bool {
+        self.failed.lock().unwrap().remove(name);
+        let removed = self.pipelines.lock().unwrap().remove(name);
+        match removed {
+            Some(supervised) => {
+                println!("{}: Stopping pipeline", name);
+                if recorder::is_recording(&supervised.resources.pipeline) == Some(true) {
+                    self.audit.record("recording_stop", Some(name), None, None);
+                }
+                supervised.spec.stats.mark_stopped();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Open or close each recording pipeline's valve according to its schedule and free disk space
+    pub fn apply_recording_state(&self, now: DateTime<Utc>) {
+        let disk_full = self.is_disk_full();
+        for (name, supervised) in self.pipelines.lock().unwrap().iter() {
+            if supervised.spec.camera.record {
+                let recording = schedule::should_record(&supervised.spec.camera, now) && !disk_full;
+                if recorder::set_recording(&supervised.resources.pipeline, name, recording) {
+                    let kind = if recording { "recording_start" } else { "recording_stop" };
+                    self.audit.record(kind, Some(name), None, disk_full.then_some("disk_full"));
+                }
+            }
+        }
+    }
+
+    // Returns true if the state changed
+    pub fn set_disk_full(&self, disk_full: bool) -> bool {
+        self.disk_full.swap(disk_full, Ordering::SeqCst) != disk_full
+    }
+
+    pub fn is_disk_full(&self) -> bool {
+        self.disk_full.load(Ordering::SeqCst)
+    }
+
+    // Degrade the previews of the lowest priority that isn't degraded yet. Returns that
+    // priority, or None if every stream already is.
+    pub fn shed_next(&self) -> Option<u32> {
+        let mut shed_through = self.shed_through.lock().unwrap();
+        let current = *shed_through;
+        let pipelines = self.pipelines.lock().unwrap();
+        let next = pipelines
+            .values()
+            .map(|supervised| supervised.spec.camera.priority)
+            .filter(|priority| current.is_none_or(|through| *priority > through))
+            .min()?;
+        *shed_through = Some(next);
+        for supervised in pipelines.values() {
+            apply_preview(supervised, self.preview(supervised, Some(next)));
+        }
+        Some(next)
+    }
+
+    // Restore the previews of the highest degraded priority. Returns that priority, or
+    // None if nothing was degraded.
+    pub fn restore_next(&self) -> Option<u32> {
+        let mut shed_through = self.shed_through.lock().unwrap();
+        let restored = (*shed_through)?;
+        let pipelines = self.pipelines.lock().unwrap();
+        *shed_through = pipelines
+            .values()
+            .map(|supervised| supervised.spec.camera.priority)
+            .filter(|priority| *priority < restored)
+            .max();
+        for supervised in pipelines.values() {
+            apply_preview(supervised, self.preview(supervised, *shed_through));
+        }
+        Some(restored)
+    }
+
+    // Change a running preview's JPEG quality without restarting it. Returns the quality
+    // the encoder now runs at, lower than asked while shedding load or while the quality
+    // ladder holds it down, or None if the stream has no running preview encoder.
+    pub fn set_jpeg_quality(&self, name: &str, quality: u32) -> Option<u32> {
+        let shed_through = *self.shed_through.lock().unwrap();
+        let pipelines = self.pipelines.lock().unwrap();
+        let supervised = pipelines.get(name)?;
+        supervised.resources.pipeline.by_name("encoder")?;
+        self.quality_overrides.lock().unwrap().insert(name.to_string(), quality);
+        println!("{}: JPEG quality set to {}", name, quality);
+        let preview = self.preview(supervised, shed_through);
+        apply_preview(supervised, preview);
+        Some(preview.jpeg_quality)
+    }
+
+    // Follow a stream's viewer count along its quality_ladder. Returns the new step and
+    // quality if that changed the step in effect.
+    pub fn set_viewers(&self, name: &str, viewers: usize) -> Option<(Option<usize>, u32)> {
+        let shed_through = *self.shed_through.lock().unwrap();
+        let pipelines = self.pipelines.lock().unwrap();
+        let supervised = pipelines.get(name)?;
+        let ladder = &supervised.spec.camera.quality_ladder;
+        let previous = self.viewers.lock().unwrap().insert(name.to_string(), viewers);
+        if previous.is_some_and(|previous| ladder_step(ladder, previous) == ladder_step(ladder, viewers)) {
+            return None;
+        }
+        supervised.resources.pipeline.by_name("encoder")?;
+        let preview = self.preview(supervised, shed_through);
+        apply_preview(supervised, preview);
+        Some((preview.quality_tier, preview.jpeg_quality))
+    }
+
+    // Let a preview_on_demand pipeline's preview run or not. Returns true if that changed
+    // anything, a rebuilt pipeline starts out idle until called again.
+    pub fn set_preview_active(&self, name: &str, active: bool) -> bool {
+        let pipelines = self.pipelines.lock().unwrap();
+        let Some(valve) = pipelines.get(name).and_then(|supervised| supervised.resources.pipeline.by_name("preview-valve")) else {
+            return false;
+        };
+        if valve.property::<bool>("drop") != active {
+            return false;
+        }
+        valve.set_property("drop", !active);
+        true
+    }
+
+    // The running preview's settings, None if the stream has no running preview encoder
+    pub fn effective_preview(&self, name: &str) -> Option<EffectivePreview> {
+        let shed_through = *self.shed_through.lock().unwrap();
+        let pipelines = self.pipelines.lock().unwrap();
+        let supervised = pipelines.get(name)?;
+        supervised.resources.pipeline.by_name("encoder")?;
+        Some(self.preview(supervised, shed_through))
+    }
+
+    // Configured (or runtime) quality and frame rate, capped by the quality_ladder step for
+    // the current viewers and held to shed_limits if the stream's priority is being shed
+    fn preview(&self, supervised: &Supervised, shed_through: Option<u32>) -> EffectivePreview {
+        let camera = &supervised.spec.camera;
+        let viewers = self.viewers.lock().unwrap().get(&camera.name).copied().unwrap_or(0);
+        let quality_tier = ladder_step(&camera.quality_ladder, viewers);
+        let quality = match quality_tier {
+            Some(tier) => self.jpeg_quality(supervised).min(camera.quality_ladder[tier].quality),
+            None => self.jpeg_quality(supervised),
+        };
+        let shed = shed_through.filter(|through| camera.priority <= *through).map(|_| self.shed_limits);
+        let max_fps = match (camera.preview_fps, shed.and_then(|shed| shed.fps)) {
+            (Some(fps), Some(cap)) => Some(fps.min(cap)),
+            (fps, cap) => fps.or(cap),
+        };
+        EffectivePreview {
+            priority: camera.priority,
+            jpeg_quality: shed.map_or(quality, |shed| quality.min(shed.quality)),
+            max_fps,
+            degraded: shed.is_some(),
+            quality_tier,
+        }
+    }
+
+    // Quality set at runtime, or the configured one
+    fn jpeg_quality(&self, supervised: &Supervised) -> u32 {
+        let camera = &supervised.spec.camera;
+        self.quality_overrides.lock().unwrap().get(&camera.name).copied().unwrap_or(camera.jpeg_quality)
+    }
+
+    // Encode `kind` for a stream until the returned lease is dropped. Every output is shared
+    // by all of its viewers and the pipeline's single decode.
+    pub fn acquire_output(self: &Arc<Self>, name: &str, kind: BranchKind) -> OutputLease {
+        self.output_users.lock().unwrap().entry((name.to_string(), kind)).or_default().leases += 1;
+        self.sync_outputs(name);
+        OutputLease { supervisor: self.clone(), name: name.to_string(), kind }
+    }
+
+    fn release_output(&self, name: &str, kind: BranchKind) {
+        {
+            let mut output_users = self.output_users.lock().unwrap();
+            let key = (name.to_string(), kind);
+            if let Some(users) = output_users.get_mut(&key) {
+                users.leases = users.leases.saturating_sub(1);
+                if !users.wanted(Instant::now()) {
+                    output_users.remove(&key);
+                }
+            }
+        }
+        self.sync_outputs(name);
+    }
+
+    // Someone fetched from the stream's HLS output, keep it going for another
+    // HLS_IDLE_TIMEOUT. Returns true if it wasn't running for anyone yet.
+    pub fn touch_hls(&self, name: &str) -> bool {
+        let now = Instant::now();
+        let started = {
+            let mut output_users = self.output_users.lock().unwrap();
+            let users = output_users.entry((name.to_string(), BranchKind::Hls)).or_default();
+            let started = !users.wanted(now);
+            users.hls_until = Some(now + HLS_IDLE_TIMEOUT);
+            started
+        };
+        if started {
+            self.sync_outputs(name);
+        }
+        started
+    }
+
+    // Attach the output branches a stream's users want and detach the rest
+    fn sync_outputs(&self, name: &str) {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        let Some(supervised) = pipelines.get_mut(name) else { return };
+        let Some(live) = &supervised.spec.live else { return };
+        let pipeline = &supervised.resources.pipeline;
+        let now = Instant::now();
+        let output_users = self.output_users.lock().unwrap();
+        for kind in BranchKind::ALL {
+            let wanted = output_users.get(&(name.to_string(), kind)).is_some_and(|users| users.wanted(now));
+            match (wanted, supervised.branches.remove(&kind)) {
+                (true, None) => match branches::attach(pipeline, name, kind, live) {
+                    Ok(branch) => {
+                        supervised.branches.insert(kind, branch);
+                    }
+                    Err(err) => println!("{}: Failed to start {:?} output: {}", name, kind, err),
+                },
+                (true, Some(branch)) => {
+                    supervised.branches.insert(kind, branch);
+                }
+                (false, Some(branch)) => branches::detach(pipeline, name, kind, branch, live),
+                (false, None) => (),
+            }
+        }
+    }
+
+    // Encode `region` of a stream for one viewer into `frames` until the returned lease is
+    // dropped. None if the camera doesn't allow it or all its roi_max_clients are taken.
+    pub fn acquire_roi(self: &Arc<Self>, name: &str, region: Region, frames: tokio::sync::mpsc::Sender<Vec<u8>>) -> Option<RoiLease> {
+        let limit = self.pipelines.lock().unwrap().get(name)?.spec.camera.roi_max_clients;
+        let id = {
+            let mut roi_views = self.roi_views.lock().unwrap();
+            if roi_views.values().filter(|view| view.name == name).count() >= limit as usize {
+                return None;
+            }
+            let id = self.next_roi_id.fetch_add(1, Ordering::Relaxed);
+            let view = RoiView { name: name.to_string(), region: Arc::new(Mutex::new(region)), frames };
+            roi_views.insert(id, view);
+            id
+        };
+        self.sync_roi(name);
+        Some(RoiLease { supervisor: self.clone(), id })
+    }
+
+    fn move_roi(&self, id: u64, region: Region) {
+        let name = {
+            let roi_views = self.roi_views.lock().unwrap();
+            let Some(view) = roi_views.get(&id) else { return };
+            *view.region.lock().unwrap() = region;
+            view.name.clone()
+        };
+        let pipelines = self.pipelines.lock().unwrap();
+        if let Some(branch) = pipelines.get(&name).and_then(|supervised| supervised.roi_branches.get(&id)) {
+            roi::apply(branch, region);
+        }
+    }
+
+    fn release_roi(&self, id: u64) {
+        let removed = self.roi_views.lock().unwrap().remove(&id);
+        if let Some(view) = removed {
+            self.sync_roi(&view.name);
+        }
+    }
+
+    // Attach a branch for each of a stream's zoomed viewers that hasn't got one, detach the
+    // ones whose viewer left
+    fn sync_roi(&self, name: &str) {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        let Some(supervised) = pipelines.get_mut(name) else { return };
+        let pipeline = &supervised.resources.pipeline;
+        let roi_views = self.roi_views.lock().unwrap();
+        let gone: Vec<u64> = supervised.roi_branches.keys().filter(|id| !roi_views.contains_key(id)).copied().collect();
+        for id in gone {
+            if let Some(branch) = supervised.roi_branches.remove(&id) {
+                println!("{}: Stopping region of interest #{}", name, id);
+                branches::remove(pipeline, branch);
+            }
+        }
+        for (id, view) in roi_views.iter().filter(|(_, view)| view.name == name) {
+            if supervised.roi_branches.contains_key(id) {
+                continue;
+            }
+            match roi::attach(pipeline, &supervised.spec.camera, view) {
+                Ok(branch) => {
+                    println!("{}: Started region of interest #{} at {:?}", name, id, *view.region.lock().unwrap());
+                    supervised.roi_branches.insert(*id, branch);
+                }
+                Err(err) => println!("{}: Failed to start region of interest #{}: {}", name, id, err),
+            }
+        }
+    }
+
+    pub fn is_load_shedding(&self) -> bool {
+        self.shed_through.lock().unwrap().is_some()
+    }
+
+    // Recording state of a running pipeline, None if it isn't running or doesn't record
+    pub fn is_recording(&self, name: &str) -> Option<bool> {
+        let pipelines = self.pipelines.lock().unwrap();
+        recorder::is_recording(&pipelines.get(name)?.resources.pipeline)
+    }
+
+    // Rebuild pipelines that have fallen back to Null and aren't being restarted, e.g.
+    // after a reconnect attempt failed to even change state, and retry the ones that failed
+    // to start. The rebuild reuses the spec's broadcast sender, so viewers stay subscribed.
+    // Pipelines that can never be built (bad config, missing plugins) are given up on.
+    // Also stops HLS outputs nobody fetched from lately.
+    pub fn reap(&self) {
+        let mut specs: Vec<PipelineSpec> = self.pipelines
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|supervised| !supervised.resources.is_alive(&supervised.spec.stats))
+            .map(|supervised| supervised.spec.clone())
+            .collect();
+        for spec in &specs {
+            println!("{}: Pipeline is dead, rebuilding it from scratch", spec.camera.name);
+        }
+        specs.extend(self.failed.lock().unwrap().values().cloned());
+        for spec in specs {
+            let name = spec.camera.name.clone();
+            if let Err(err) = self.start(spec) {
+                if !err.is_retryable() {
+                    println!("{}: Giving up on pipeline, rebuilding can't succeed: {}", name, err);
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let idle: Vec<String> = {
+            let mut output_users = self.output_users.lock().unwrap();
+            let idle = output_users
+                .iter()
+                .filter(|(_, users)| !users.wanted(now))
+                .map(|((name, _), _)| name.clone())
+                .collect();
+            output_users.retain(|_, users| users.wanted(now));
+            idle
+        };
+        for name in idle {
+            self.sync_outputs(&name);
+        }
+    }
+
+    pub fn shutdown(&self) {
+        let pipelines: Vec<_> = self.pipelines.lock().unwrap().drain().collect();
+        for (name, supervised) in pipelines {
+            println!("{}: Shutting down pipeline", name);
+            if recorder::is_recording(&supervised.resources.pipeline) == Some(true) {
+                self.audit.record("recording_stop", Some(&name), None, Some("shutdown"));
+            }
+            supervised.spec.stats.mark_stopped();
+        }
+        self.main_loop.quit();
+    }
+}
+
+// Without an explicit shutdown, e.g. when startup fails after the supervisor was created,
+// the main loop thread would otherwise keep running. Pipelines drop with the map.
+impl Drop for PipelineSupervisor {
+    fn drop(&mut self) {
+        self.main_loop.quit();
+    }
+}
+
+// Cameras behind a SOCKS proxy or pinned to a local address are reached through a
+// local forwarding port
+fn build(spec: &PipelineSpec) -> Result<(PipelineResources, Option<Tunnel>)> {
+    let camera = &spec.camera;
+    let tunnel = match (&camera.socks_proxy, camera.local_address) {
+        (Some(proxy), _) => socks::open(&camera.name, &camera.url, proxy),
+        (None, Some(local_address)) => socks::bind(&camera.name, &camera.url, local_address),
+        (None, None) => return Ok((setup_pipeline(spec)?, None)),
+    };
+    let tunnel = tunnel.map_err(|err| NvrError::RtspConnect(format!("{:#}", err)))?;
+    let mut tunnelled = spec.clone();
+    tunnelled.camera.url = tunnel.local_url.clone();
+    Ok((setup_pipeline(&tunnelled)?, Some(tunnel)))
+}
+
+// Index of the last step `viewers` reaches, steps are in order of increasing viewers
+fn ladder_step(ladder: &[QualityStep], viewers: usize) -> Option<usize> {
+    ladder.iter().rposition(|step| viewers >= step.viewers)
+}
+
+// jpegenc quality and videorate's max-rate are adjustable while playing, and take effect
+// from the next frame. Previews without preview_fps or load_shed_fps have no videorate.
+fn apply_preview(supervised: &Supervised, preview: EffectivePreview) {
+    let pipeline = &supervised.resources.pipeline;
+    if let Some(encoder) = pipeline.by_name("encoder") {
+        encoder.set_property("quality", preview.jpeg_quality as i32);
+    }
+    if let Some(rate) = pipeline.by_name("preview-rate") {
+        rate.set_property("max-rate", preview.max_fps.map_or(i32::MAX, |fps| fps as i32));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{next_frame, test_camera, test_spec, test_supervisor};
+    use crate::Frame;
+    use tokio::sync::broadcast;
+
+    // Skip frames the old pipeline left in the channel
+    async fn frame_after(rx: &mut broadcast::Receiver<Frame>, seq: u64) -> Frame {
+        loop {
+            let frame = next_frame(rx).await;
+            if frame.seq > seq {
+                return frame;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribers_keep_receiving_after_a_rebuild() {
+        let supervisor = test_supervisor();
+        let (spec, mut rx) = test_spec(test_camera("name: rebuilt\nsource: test:320x240@30"));
+        supervisor.start(spec).unwrap();
+        let before = next_frame(&mut rx).await;
+
+        // Dead the way a failed reconnect leaves it: in Null, no restart pending
+        let old = supervisor.pipelines.lock().unwrap()["rebuilt"].resources.pipeline.clone();
+        old.set_state(gst::State::Null).unwrap();
+        supervisor.reap();
+        let new = supervisor.pipelines.lock().unwrap()["rebuilt"].resources.pipeline.clone();
+        assert_ne!(old, new);
+
+        // Same receiver, frames from the new pipeline
+        let after = frame_after(&mut rx, before.seq).await;
+        let again = next_frame(&mut rx).await;
+        assert!(again.seq > after.seq);
+        supervisor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn subscribers_keep_receiving_after_an_error_restart() {
+        let supervisor = test_supervisor();
+        let camera = test_camera("name: restarted\nsource: test:320x240@30\nreconnect:\n  initial_delay_secs: 0.2");
+        let (spec, mut rx) = test_spec(camera);
+        let stats = spec.stats.clone();
+        supervisor.start(spec).unwrap();
+        let before = next_frame(&mut rx).await;
+
+        // What the bus watch restarts on, dispatched by the supervisor's main loop
+        let pipeline = supervisor.pipelines.lock().unwrap()["restarted"].resources.pipeline.clone();
+        let error = gst::message::Error::builder(gst::CoreError::Failed, "simulated camera failure").src(&pipeline).build();
+        pipeline.post_message(error).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while stats.reconnect_count.load(Ordering::SeqCst) == 0 {
+            assert!(Instant::now() < deadline, "pipeline never restarted");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let after = frame_after(&mut rx, before.seq).await;
+        assert!(after.seq > before.seq);
+        supervisor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn stopping_a_stream_drops_its_pipeline_and_broadcaster() {
+        let supervisor = test_supervisor();
+        let (spec, mut rx) = test_spec(test_camera("name: removed\nsource: test:320x240@30"));
+        supervisor.start(spec).unwrap();
+        next_frame(&mut rx).await;
+
+        let pipeline = supervisor.pipelines.lock().unwrap()["removed"].resources.pipeline.downgrade();
+        assert!(supervisor.stop("removed"));
+        assert!(pipeline.upgrade().is_none());
+
+        // The broadcaster thread holds the last sender, the channel closes once it has exited
+        let closed = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                match rx.recv().await {
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        closed.await.expect("broadcaster thread still running");
+        supervisor.shutdown();
+    }
+}
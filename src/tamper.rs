@@ -0,0 +1,99 @@
+use chrono::Utc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::motion::LumaGrid;
+use crate::onvif::CameraEvent;
+use crate::processor::{FrameData, FrameProcessor};
+
+const TOPIC: &str = "Detector/Tamper";
+
+// A scene closer to the one before the jump than this fraction of the threshold counts
+// as restored, e.g. someone walked right past the lens
+const RESTORED_FRACTION: f64 = 0.5;
+
+// Covering, spraying or knocking a camera askew changes the whole picture at once and it
+// stays changed, unlike motion, which comes and goes, or light, which changes slowly.
+// A frame-to-frame jump of at least `threshold` remembers the scene before it. Still that
+// different from it after `duration`, a tamper event goes out on the events channel; once
+// the old scene is back, an inactive one follows.
+pub struct TamperDetector {
+    stream_name: String,
+    threshold: f64,
+    duration: Duration,
+    events: broadcast::Sender<CameraEvent>,
+    state: Mutex<TamperState>,
+}
+
+#[derive(Default)]
+struct TamperState {
+    previous: Option<LumaGrid>,
+    // Scene before the jump and when it happened, while the jump is being watched
+    suspect: Option<(LumaGrid, Instant)>,
+    tampered: bool,
+}
+
+impl TamperDetector {
+    pub fn new(stream_name: String, threshold: f64, duration: Duration, events: broadcast::Sender<CameraEvent>) -> Self {
+        TamperDetector {
+            stream_name,
+            threshold,
+            duration,
+            events,
+            state: Mutex::new(TamperState::default()),
+        }
+    }
+
+    fn send(&self, active: bool) {
+        match active {
+            true => println!("{}: Camera tampering suspected, scene changed and stayed changed", self.stream_name),
+            false => println!("{}: Camera scene restored", self.stream_name),
+        }
+        // Nobody subscribed is fine
+        let _ = self.events.send(CameraEvent {
+            source: "detector",
+            topic: TOPIC.to_string(),
+            active,
+            time: Some(Utc::now().to_rfc3339()),
+        });
+    }
+}
+
+impl FrameProcessor for TamperDetector {
+    fn process(&self, frame: &FrameData) -> Option<FrameData> {
+        let grid = frame.video_frame().and_then(|frame| LumaGrid::sample(&frame))?;
+        let mut state = self.state.lock().unwrap();
+
+        match state.suspect.take() {
+            Some((before, since)) => {
+                let score = grid.score(&before);
+                if state.tampered {
+                    if score < self.threshold * RESTORED_FRACTION {
+                        state.tampered = false;
+                        self.send(false);
+                    } else {
+                        state.suspect = Some((before, since));
+                    }
+                } else if score >= self.threshold {
+                    if since.elapsed() >= self.duration {
+                        state.tampered = true;
+                        self.send(true);
+                    }
+                    state.suspect = Some((before, since));
+                }
+                // Otherwise the change didn't last, something passed in front of the camera
+            }
+            None => {
+                if let Some(previous) = &state.previous {
+                    if grid.score(previous) >= self.threshold {
+                        state.suspect = Some((previous.clone(), Instant::now()));
+                    }
+                }
+            }
+        }
+
+        state.previous = Some(grid);
+        None
+    }
+}
@@ -0,0 +1,56 @@
+use anyhow::Result;
+use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+use image::{DynamicImage, ImageDecoder};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_WIDTH: u32 = 160;
+const MIN_WIDTH: u32 = 16;
+const MAX_WIDTH: u32 = 640;
+const JPEG_QUALITY: u8 = 70;
+
+// Pickers tend to poll every stream at once, so reuse a thumbnail for a little while
+const CACHE_TTL: Duration = Duration::from_secs(2);
+
+// Recently generated thumbnails, keyed by (stream name, width)
+#[derive(Default)]
+pub struct ThumbnailCache {
+    entries: Mutex<HashMap<(String, u32), (Instant, Arc<Vec<u8>>)>>,
+}
+
+impl ThumbnailCache {
+    // Width is clamped to MIN_WIDTH..=MAX_WIDTH, height follows the frame's aspect ratio.
+    // `source` is only asked for the full-size JPEG when there's no fresh thumbnail.
+    pub fn get_or_render(&self, stream_name: &str, width: u32, source: impl FnOnce() -> Result<Vec<u8>>) -> Result<Arc<Vec<u8>>> {
+        let width = width.clamp(MIN_WIDTH, MAX_WIDTH);
+        let key = (stream_name.to_string(), width);
+
+        if let Some((rendered_at, thumbnail)) = self.entries.lock().unwrap().get(&key) {
+            if rendered_at.elapsed() < CACHE_TTL {
+                return Ok(thumbnail.clone());
+            }
+        }
+
+        let thumbnail = Arc::new(render(&source()?, width)?);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (rendered_at, _)| rendered_at.elapsed() < CACHE_TTL);
+        entries.insert(key, (Instant::now(), thumbnail.clone()));
+        Ok(thumbnail)
+    }
+}
+
+fn render(jpeg: &[u8], width: u32) -> Result<Vec<u8>> {
+    // Honour EXIF orientation, set on previews of cameras using metadata rotation
+    let mut decoder = JpegDecoder::new(Cursor::new(jpeg))?;
+    let orientation = decoder.orientation()?;
+    let mut frame = DynamicImage::from_decoder(decoder)?;
+    frame.apply_orientation(orientation);
+    let height = (frame.height() as u64 * width as u64 / frame.width().max(1) as u64).max(1) as u32;
+    let thumbnail = frame.thumbnail_exact(width, height).into_rgb8();
+
+    let mut out = Vec::new();
+    JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode_image(&thumbnail)?;
+    Ok(out)
+}